@@ -0,0 +1,193 @@
+use anyhow::Result;
+use std::{
+    env,
+    ffi::{OsStr, OsString},
+    os::windows::ffi::{OsStrExt, OsStringExt},
+};
+use windows::{
+    core::{h, HSTRING},
+    Win32::{
+        Foundation::ERROR_FILE_NOT_FOUND,
+        System::Registry::{RegDeleteKeyValueW, RegSetKeyValueW, HKEY_CURRENT_USER, REG_SZ},
+    },
+};
+
+use crate::APP_NAME;
+
+pub fn install() -> Result<()> {
+    //! Registers the current executable with the current arguments (minus the autostart options and `--reset`) to run at login. The arguments also form the registry value name, so that every configuration gets its own entry.
+
+    let (value_name, data) = entry()?;
+
+    unsafe {
+        RegSetKeyValueW(
+            HKEY_CURRENT_USER,
+            h!(r"Software\Microsoft\Windows\CurrentVersion\Run"),
+            &value_name,
+            REG_SZ.0,
+            Some(data.as_ptr() as _),
+            ((data.len() + 1) * 2) as _,
+        )?
+    };
+
+    Ok(())
+}
+
+pub fn uninstall() -> Result<()> {
+    //! Removes the entry that `install()` registered with the same arguments.
+
+    let (value_name, _) = entry()?;
+
+    unsafe {
+        RegDeleteKeyValueW(
+            HKEY_CURRENT_USER,
+            h!(r"Software\Microsoft\Windows\CurrentVersion\Run"),
+            &value_name,
+        )?
+    };
+
+    Ok(())
+}
+
+pub fn uninstall_if_installed() -> Result<bool> {
+    //! Like `uninstall()`, but a missing entry isn't an error. Returns whether there was one.
+
+    match uninstall() {
+        Ok(()) => Ok(true),
+        Err(error)
+            if error
+                .downcast_ref::<windows::core::Error>()
+                .is_some_and(|error| error.code() == ERROR_FILE_NOT_FOUND.to_hresult()) =>
+        {
+            Ok(false)
+        }
+        Err(error) => Err(error),
+    }
+}
+
+fn entry() -> Result<(HSTRING, HSTRING)> {
+    //! The registry value name and the command line. (Arguments that aren't valid Unicode are kept as they are.)
+
+    let mut is_after_separator = false;
+    let args = env::args_os()
+        .skip(1)
+        .filter(|arg| {
+            if is_after_separator {
+                true
+            } else if arg == "--" {
+                is_after_separator = true;
+                true
+            } else {
+                !["--install-autostart", "--uninstall-autostart", "--reset"]
+                    .map(OsStr::new)
+                    .contains(&arg.as_os_str())
+            }
+        })
+        .map(|arg| quote_os_arg(&arg))
+        .fold(OsString::new(), |mut args, arg| {
+            if !args.is_empty() {
+                args.push(" ");
+            }
+            args.push(arg);
+            args
+        });
+
+    let mut value_name = OsString::from(format!("{APP_NAME}: "));
+    value_name.push(&args);
+
+    let mut command_line = quote_os_arg(env::current_exe()?.as_os_str());
+    command_line.push(" ");
+    command_line.push(&args);
+
+    Ok((HSTRING::from(value_name), HSTRING::from(command_line)))
+}
+
+pub fn quote_arg(arg: &str) -> String {
+    //! Quotes the argument, if necessary, so that `CommandLineToArgvW()` and the Rust standard library parse it back unchanged.
+
+    quote_units(&arg.chars().collect::<Vec<_>>())
+        .into_iter()
+        .collect()
+}
+
+pub fn quote_os_arg(arg: &OsStr) -> OsString {
+    //! Like `quote_arg()`, but also for arguments that aren't valid Unicode.
+
+    OsString::from_wide(&quote_units(&arg.encode_wide().collect::<Vec<_>>()))
+}
+
+fn quote_units<T>(arg: &[T]) -> Vec<T>
+where
+    T: Copy + PartialEq + From<u8>,
+{
+    //! For `char`s or UTF-16 code units.
+
+    let [space, tab, line_feed, vertical_tab, quote, backslash] =
+        [b' ', b'\t', b'\n', 0x0b, b'"', b'\\'].map(T::from);
+
+    if !arg.is_empty()
+        && !arg
+            .iter()
+            .any(|unit| [space, tab, line_feed, vertical_tab, quote].contains(unit))
+    {
+        return arg.to_vec();
+    }
+
+    let mut quoted = vec![quote];
+    let mut backslash_count = 0;
+
+    for &unit in arg {
+        if unit == backslash {
+            backslash_count += 1;
+            continue;
+        }
+
+        // Backslashes are only special before quotes.
+        let escaped_backslash_count = if unit == quote {
+            backslash_count * 2 + 1
+        } else {
+            backslash_count
+        };
+        quoted.extend(vec![backslash; escaped_backslash_count]);
+        quoted.push(unit);
+
+        backslash_count = 0;
+    }
+
+    // (Backslashes before the closing quote must be escaped.)
+    quoted.extend(vec![backslash; backslash_count * 2]);
+    quoted.push(quote);
+
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{quote_arg, quote_os_arg};
+    use std::{ffi::OsString, os::windows::ffi::OsStringExt};
+
+    #[test]
+    fn quotes_only_when_necessary() {
+        assert_eq!(
+            quote_arg(r"C:\path\to\script.ps1"),
+            r"C:\path\to\script.ps1"
+        );
+        assert_eq!(quote_arg(""), r#""""#);
+        assert_eq!(quote_arg(r"C:\with space\"), r#""C:\with space\\""#);
+        assert_eq!(quote_arg(r#"say "hi""#), r#""say \"hi\"""#);
+        assert_eq!(quote_arg(r#"a\"b"#), r#""a\\\"b""#);
+        assert_eq!(quote_arg("line\nbreak"), "\"line\nbreak\"");
+        assert_eq!(quote_arg("vertical\x0btab"), "\"vertical\x0btab\"");
+    }
+
+    #[test]
+    fn quotes_non_unicode_args() {
+        // (An unpaired surrogate.)
+        let arg = OsString::from_wide(&[b'a' as _, 0xd800, b' ' as _]);
+
+        assert_eq!(
+            quote_os_arg(&arg),
+            OsString::from_wide(&[b'"' as _, b'a' as _, 0xd800, b' ' as _, b'"' as _])
+        );
+    }
+}
@@ -1,106 +1,390 @@
-use anyhow::Result;
+use anyhow::anyhow;
 use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::FromPrimitive;
-use std::{borrow::Cow, pin::Pin, ptr};
+use regex::Regex;
+use std::{
+    cell::Cell,
+    ffi::OsString,
+    mem::{self, size_of},
+    os::windows::process::CommandExt,
+    path::PathBuf,
+    pin::Pin,
+    process::Command,
+    ptr,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
 use windows::{
-    core::{h, HSTRING},
+    core::{HSTRING, PCWSTR},
     Win32::{
-        Foundation::{HWND, LPARAM, LRESULT, WPARAM},
-        UI::WindowsAndMessaging::{
-            DestroyIcon, DestroyWindow, PostQuitMessage, HICON, WM_APP, WM_COMMAND, WM_DESTROY,
-            WM_TIMER,
+        Foundation::{HWND, LPARAM, LRESULT, POINT, WPARAM},
+        Graphics::Gdi::{MonitorFromRect, MONITOR_DEFAULTTONULL},
+        System::{SystemInformation::GetTickCount, Threading::GetCurrentProcessId},
+        UI::{
+            Input::KeyboardAndMouse::{GetLastInputInfo, LASTINPUTINFO},
+            Shell::{
+                SHQueryUserNotificationState, QUNS_BUSY, QUNS_PRESENTATION_MODE,
+                QUNS_RUNNING_D3D_FULL_SCREEN,
+            },
+            WindowsAndMessaging::{
+                DestroyWindow, GetCursorPos, GetForegroundWindow, GetWindowThreadProcessId,
+                PostMessageW, PostQuitMessage, HICON, WINDOW_STYLE, WM_APP, WM_DESTROY, WM_HOTKEY,
+                WM_SETTINGCHANGE, WM_SYSCOMMAND, WM_TIMER, WS_EX_APPWINDOW, WS_EX_TOOLWINDOW,
+                WS_MAXIMIZEBOX, WS_MINIMIZEBOX, WS_THICKFRAME,
+            },
         },
     },
 };
 
 use crate::{
-    cli::Cli,
-    foreign_process_tree::{ForeignProcessTree, ForeignWindowEvent},
+    cli::{Cli, CloseMethod, DoubleClickAction, MenuPosition, TooltipTruncation, WindowCorners},
+    control_server::{ControlCommand, ControlServer, ControlStatus},
+    error::TrayValetError,
+    event_command,
+    event_output::{self, Event},
+    foreign_process_tree::{
+        ForeignProcessTree, ForeignWindowEvent, SearchOptions, SpawnOptions, TrackingOptions,
+        WindowSizeLimits,
+    },
+    state::{self, AppState},
     win32::{
-        base_window::{self, BaseWindow, CommandMsg, OnWindowMsg},
-        context_menu::ContextMenu,
-        icon::{duplicate_hicon, load_tray_monitor_icon},
-        tray_icon::{TrayIcon, TrayIconEvent},
+        base_window::{translate_sys_command_msg, BaseWindow, OnWindowMsg, SystemCommand},
+        context_menu::{ContextMenu, MenuEntry},
+        hotkey::HotkeyRegistration,
+        icon::{
+            create_badged_icon, create_monochrome_icon, destroy_hicon, duplicate_hicon,
+            load_folder_icon, load_placeholder_icon, load_tray_monitor_icon,
+            load_tray_monitor_icons,
+        },
+        timer::Timer,
+        tray_icon::{TrayIcon, TrayIconEvent, Truncation},
     },
     APP_NAME,
 };
 
+/// How long to wait for a non-empty window title with `--wait-for-title` before configuring the foreign window anyway.
+const WAIT_FOR_TITLE_FALLBACK_MILLIS: u32 = 1000;
+/// How long another window must stay in the foreground with `--hide-on-focus-loss` before the foreign window is hidden, to ride out transient focus changes.
+const FOCUS_LOSS_HIDE_DELAY_MILLIS: u32 = 100;
+/// How long after hiding because of focus loss tray icon activations don't toggle the foreign window, since clicking the tray icon itself takes the focus.
+const FOCUS_LOSS_ACTIVATION_GRACE_MILLIS: u128 = 500;
+/// How long the foreign window still counts as the foreground window after losing that state, since clicking the tray icon first makes the taskbar the foreground window.
+const FOREGROUND_LOSS_GRACE_MILLIS: u128 = 500;
+/// The interval of checking for idleness with `--auto-hide-idle-ms`.
+const IDLE_CHECK_MILLIS: u32 = 500;
+/// How long the tray icon is kept after the foreign window was destroyed with `--notify-on-exit`, since the notification disappears with it.
+const EXIT_NOTIFICATION_MILLIS: u32 = 4000;
+/// The ID of `--relaunch-hotkey`, which is registered for the background window.
+const RELAUNCH_HOTKEY_ID: i32 = 1;
+
+thread_local! {
+    /// The number of existing windows. The app quits when the last one is destroyed.
+    static WINDOW_COUNT: Cell<usize> = const { Cell::new(0) };
+
+    /// Whether a window was destroyed because of an error. Makes the app exit with a nonzero exit code.
+    static HAD_ERROR: Cell<bool> = const { Cell::new(false) };
+}
+
 pub struct BackgroundWindow<'a> {
     base_window: Pin<Box<BaseWindow<'a, BackgroundWindow<'a>>>>,
-    tray_icon: TrayIcon,
-    context_menu: ContextMenu<ContextMenuItem>,
+    /// `None` with `--no-tray`, like `context_menu`.
+    tray_icon: Option<TrayIcon>,
+    /// Shut down when dropped.
+    control_server: Option<ControlServer>,
+    activate_cooldown: Duration,
+    /// When an activation last toggled the foreign window.
+    last_toggle_time: Option<Instant>,
+    tray_min_lifetime: Duration,
+    creation_time: Instant,
+    context_menu: Option<ContextMenu<ContextMenuItem>>,
     foreign_process_tree: ForeignProcessTree,
+    /// The arguments for starting the foreign process tree anew with `--keep-alive-after-close`.
+    relaunch_cli: Option<Cli>,
+    /// Whether the foreign window was closed with `--keep-alive-after-close`, so that the app can be relaunched.
+    awaiting_relaunch: bool,
+    /// Registered while awaiting a relaunch.
+    relaunch_hotkey_registration: Option<HotkeyRegistration>,
     hide_after_start: bool,
+    /// The key in the state file with `--remember-state`.
+    state_key: Option<String>,
+    close_method: CloseMethod,
+    on_minimize_command: Option<String>,
+    /// Taken when run, so that following a new main window doesn't run it again.
+    on_found_hwnd_command: Option<String>,
+    probe_interval_millis: Option<u32>,
+    hang_timeout_millis: u32,
+    on_hung_command: Option<String>,
+    /// Whether the last probe found the foreign window not responding.
+    foreign_window_hung: bool,
+    /// The last known title, for when the foreign window doesn't exist anymore.
+    foreign_window_title: String,
+    /// Whether the foreign window's destruction was handled, which closing it via the context menu may anticipate.
+    foreign_window_destroyed: bool,
+    emits_events: bool,
+    /// The index of the app in the `--config` file, which identifies it in the events.
+    app_index: usize,
+    /// The visibility last reported with `--emit-events`.
+    emitted_visible: Option<bool>,
+    notifies_on_exit: bool,
+    waits_for_exit: bool,
+    has_menu_header: bool,
+    left_click_shows_menu: bool,
+    raises_on_activation: bool,
+    menu_position: MenuPosition,
+    double_click_action: DoubleClickAction,
+    hides_on_focus_loss: bool,
+    /// Whether the context menu is currently tracking, during which focus loss is ignored.
+    context_menu_open: bool,
+    /// Whether the user is currently moving or sizing the foreign window, during which auto-hiding is suppressed.
+    foreign_window_moving: bool,
+    /// Whether the foreign window or a window owned by it is the foreground window, as last reported by `ForeignWindowEvent::ForegroundChanged`.
+    foreign_window_foreground: bool,
+    /// When `foreign_window_foreground` last became `false`.
+    foreground_loss_time: Option<Instant>,
+    /// When the foreign window was last hidden because of focus loss.
+    last_focus_loss_hide_time: Option<Instant>,
+    auto_hide_idle_duration: Option<Duration>,
+    /// When the foreign window was last shown or presumably received input.
+    last_activity_time: Instant,
+    quiet_errors: bool,
+    /// Whether `sendmsg` control commands are sent instead of posted.
+    sends_messages_sync: bool,
+    wait_for_title: bool,
+    /// Whether the tooltip and hiding are deferred until the foreign window has a non-empty title.
+    awaiting_title: bool,
+    focus_title_regex: Option<Regex>,
+    /// Whether the title matched `focus_title_regex` when it last changed, so that only the start of a match counts.
+    title_matched: bool,
+    wait_for_title_timer: Option<Timer>,
+    probe_timer: Option<Timer>,
+    focus_loss_timer: Option<Timer>,
+    exit_notification_timer: Option<Timer>,
+    /// Running while the tray icon lingers for `--tray-min-lifetime-ms` before the window is destroyed.
+    destroy_timer: Option<Timer>,
+    idle_check_timer: Option<Timer>,
     small_hicon: Option<HICON>,
     large_hicon: Option<HICON>,
+    /// The worker thread of `load_icons_async()`, until its icons were taken. (They're owned by this struct from the start, so that they're also destroyed if the window is destroyed first.)
+    icon_loader: Option<JoinHandle<[Option<HICON>; 2]>>,
+    /// Tray icons for the foreign window's visibility states. Where missing, `small_hicon` is used.
+    shown_hicon: Option<HICON>,
+    hidden_hicon: Option<HICON>,
+    /// The text for the tray icon's badge. `None` instead of an empty text or `0`.
+    badge_text: Option<String>,
+    icon_monochrome: bool,
+    /// For the context menu item that opens the executable file's folder.
+    folder_hicon: Option<HICON>,
     foreign_window_needs_icon: bool,
+    /// The size of the small icon from `--icon-size`, instead of the one from the system metrics.
+    tray_icon_size: Option<u32>,
+    removed_foreign_window_style: WINDOW_STYLE,
+    foreign_window_needs_no_taskbar_button: bool,
+    foreign_window_caption: Option<String>,
+    foreign_window_corners: Option<WindowCorners>,
+    /// Whether the caption is reapplied when the foreign window changes its title.
+    must_force_caption: bool,
 }
 
 impl<'a> BackgroundWindow<'a> {
-    pub fn new(cli: Cli) -> Result<Pin<Box<Self>>> {
+    pub fn new(cli: Cli, app_index: usize) -> Result<Pin<Box<Self>>, TrayValetError> {
+        let relaunch_cli = cli.keep_alive_after_close.then(|| cli.clone());
+
         // Create objects.
-        let base_window = BaseWindow::new()?;
-        let mut tray_icon =
-            TrayIcon::with_primary_id(base_window.hwnd(), CustomWindowMsg::TrayIcon as _)?;
-
-        let context_menu = ContextMenu::new(
-            vec![
-                (
-                    ContextMenuItem::ToggleForeignWindowVisible,
-                    Cow::Borrowed("&Show/Hide"),
-                ),
-                (
-                    ContextMenuItem::ReleaseForeignWindowAndExit,
-                    Cow::Borrowed("&Release"),
-                ),
-                (
-                    ContextMenuItem::CloseForeignWindowAndExit,
-                    Cow::Borrowed("&Close"),
-                ),
-            ],
-            ContextMenuItem::ToggleForeignWindowVisible,
-            base_window.hwnd(),
-        )?;
+        let base_window = BaseWindow::new().map_err(TrayValetError::WindowCreation)?;
+        let (mut tray_icon, context_menu, folder_hicon) = if cli.no_tray {
+            (None, None, None)
+        } else {
+            let mut tray_icon = TrayIcon::with_id(
+                cli.tray_id,
+                base_window.hwnd(),
+                CustomWindowMsg::TrayIcon as _,
+            )
+            .map_err(TrayValetError::TrayRegister)?;
+            tray_icon.set_double_click_millis(cli.double_click_ms);
+            tray_icon.set_tooltip_truncation(match cli.tooltip_truncate {
+                TooltipTruncation::End => Truncation::End,
+                TooltipTruncation::Middle => Truncation::Middle,
+                TooltipTruncation::Start => Truncation::Start,
+            });
+            if cli.delay_spawn_ms.is_some_and(|millis| millis != 0) {
+                let _ = tray_icon.set_tooltip(format!("{APP_NAME}: Waiting to launch..."));
+            }
 
-        let foreign_process_tree = unsafe {
-            ForeignProcessTree::new(
-                cli.foreign_process_tree_args,
-                &cli.win_class,
+            let folder_hicon = load_folder_icon().ok();
+            let context_menu = ContextMenu::new(
+                Self::context_menu_entries(None, false, false, folder_hicon),
+                ContextMenuItem::ToggleForeignWindowVisible,
                 base_window.hwnd(),
-                CustomWindowMsg::WinEventHook as _,
-                CustomWindowMsg::WaitingForForeignWindowError as _,
-            )?
+            )
+            .map_err(TrayValetError::WindowCreation)?;
+
+            (Some(tray_icon), Some(context_menu), folder_hicon)
         };
 
         // Early configuration.
         let (small_hicon, large_hicon) = if let Some(icon_path) = cli.icon.as_ref() {
-            let small_hicon = load_tray_monitor_icon(icon_path, false).ok();
-            let large_hicon = load_tray_monitor_icon(icon_path, true).ok();
+            match load_tray_monitor_icons(icon_path, cli.icon_size) {
+                Ok((small_hicon, large_hicon)) => {
+                    if let Some(tray_icon) = tray_icon.as_mut() {
+                        // (Only copies the cached icon.)
+                        let _ = tray_icon.set_icon_from_path(icon_path, false, cli.icon_size);
+                    }
 
-            if let Some(small_hicon) = small_hicon {
-                let second_small_icon = duplicate_hicon(small_hicon);
-                if let Ok(hicon) = second_small_icon {
-                    let _ = tray_icon.set_icon(hicon);
+                    (Some(small_hicon), Some(large_hicon))
                 }
-            }
+                Err(error) => {
+                    // (Shown before spawning the foreign process tree to not delay its events.)
+                    Self::show_warning(
+                        format!(
+                            "{:#}\n\nThe icon of the window's executable file will be used instead.",
+                            anyhow!(TrayValetError::IconLoad {
+                                path: icon_path.clone(),
+                                source: error,
+                            })
+                        ),
+                        cli.quiet_errors,
+                    );
 
-            (small_hicon, large_hicon)
+                    // Icons will be loaded from the executable file on `ForeignWindowEvent::Found`.
+                    (None, None)
+                }
+            }
         } else {
             (None, None)
         };
 
+        let [shown_hicon, hidden_hicon] =
+            [cli.icon_shown.as_ref(), cli.icon_hidden.as_ref()].map(|icon_path| {
+                icon_path.and_then(|icon_path| {
+                    match load_tray_monitor_icon(icon_path, false, cli.icon_size) {
+                        Ok(hicon) => Some(hicon),
+                        Err(error) => {
+                            Self::show_warning(
+                                format!(
+                                    "{:#}\n\nThe regular icon will be used instead.",
+                                    anyhow!(TrayValetError::IconLoad {
+                                        path: icon_path.clone(),
+                                        source: error,
+                                    })
+                                ),
+                                cli.quiet_errors,
+                            );
+
+                            None
+                        }
+                    }
+                })
+            });
+
+        let state_key = cli.remember_state.then(|| cli.state_key());
+
+        let foreign_process_tree = Self::new_foreign_process_tree(&cli, base_window.hwnd())?;
+
+        let mut removed_foreign_window_style = WINDOW_STYLE(0);
+        for (must_remove, style) in [
+            (cli.no_minimize_box, WS_MINIMIZEBOX),
+            (cli.no_maximize_box, WS_MAXIMIZEBOX),
+            (cli.no_resize, WS_THICKFRAME),
+        ] {
+            if must_remove {
+                removed_foreign_window_style |= style;
+            }
+        }
+
+        let control_server = cli
+            .control_port
+            .map(|port| {
+                ControlServer::new(
+                    port,
+                    base_window.hwnd(),
+                    CustomWindowMsg::Control as _,
+                    cli.allow_sendmsg,
+                )
+            })
+            .transpose()
+            .map_err(TrayValetError::ControlPort)?;
+
         // Create `Self` instance.
         let mut instance = Box::new(Self {
             base_window,
             tray_icon,
+            control_server,
+            activate_cooldown: Duration::from_millis(cli.activate_cooldown_ms.into()),
+            last_toggle_time: None,
+            tray_min_lifetime: Duration::from_millis(cli.tray_min_lifetime_ms.into()),
+            creation_time: Instant::now(),
             context_menu,
             foreign_process_tree,
+            relaunch_cli,
+            awaiting_relaunch: false,
+            relaunch_hotkey_registration: None,
             hide_after_start: !cli.dont_hide,
+            state_key,
+            close_method: cli.close_method,
+            on_minimize_command: cli.on_minimize,
+            on_found_hwnd_command: cli.on_found_hwnd,
+            probe_interval_millis: cli.probe_interval_ms.filter(|&millis| millis != 0),
+            hang_timeout_millis: cli.hang_timeout_ms,
+            on_hung_command: cli.on_hung,
+            foreign_window_hung: false,
+            foreign_window_title: String::new(),
+            foreign_window_destroyed: false,
+            emits_events: cli.emit_events,
+            app_index,
+            emitted_visible: None,
+            notifies_on_exit: cli.notify_on_exit,
+            waits_for_exit: cli.wait,
+            has_menu_header: cli.menu_header,
+            left_click_shows_menu: cli.left_click_menu,
+            raises_on_activation: cli.raise_on_click,
+            menu_position: cli.menu_position,
+            double_click_action: cli.double_click,
+            hides_on_focus_loss: cli.hide_on_focus_loss,
+            context_menu_open: false,
+            foreign_window_moving: false,
+            foreign_window_foreground: false,
+            foreground_loss_time: None,
+            last_focus_loss_hide_time: None,
+            auto_hide_idle_duration: cli
+                .auto_hide_idle_ms
+                .filter(|&millis| millis != 0)
+                .map(|millis| Duration::from_millis(millis.into())),
+            last_activity_time: Instant::now(),
+            quiet_errors: cli.quiet_errors,
+            sends_messages_sync: cli.send_sync,
+            wait_for_title: cli.wait_for_title,
+            awaiting_title: false,
+            focus_title_regex: cli.focus_on_title,
+            title_matched: false,
+            wait_for_title_timer: None,
+            probe_timer: None,
+            focus_loss_timer: None,
+            exit_notification_timer: None,
+            destroy_timer: None,
+            idle_check_timer: None,
             small_hicon,
             large_hicon,
+            icon_loader: None,
+            shown_hicon,
+            hidden_hicon,
+            badge_text: cli.icon_badge.as_deref().and_then(Self::badge_text),
+            icon_monochrome: cli.icon_monochrome,
+            folder_hicon,
             foreign_window_needs_icon: cli.set_win_icon,
+            tray_icon_size: cli.icon_size,
+            removed_foreign_window_style,
+            foreign_window_needs_no_taskbar_button: cli.no_taskbar_button,
+            foreign_window_caption: cli.window_caption,
+            foreign_window_corners: cli.corners,
+            must_force_caption: cli.force_caption,
         });
 
+        if instance.badge_text.is_some() || instance.icon_monochrome {
+            instance.refresh_tray_icon();
+        }
+
+        WINDOW_COUNT.with(|count| count.set(count.get() + 1));
+
         // Configure base window.
         Ok(BaseWindow::set_msg_callback_with_this_arg(
             ptr::addr_of_mut!(instance.base_window),
@@ -109,6 +393,750 @@ impl<'a> BackgroundWindow<'a> {
         ))
     }
 
+    fn new_foreign_process_tree(
+        cli: &Cli,
+        event_hwnd: HWND,
+    ) -> Result<ForeignProcessTree, TrayValetError> {
+        //! Starts the foreign process tree as configured, also when relaunching.
+
+        let mut foreign_process_tree = unsafe {
+            ForeignProcessTree::new(
+                cli.foreign_process_tree_args.clone(),
+                SpawnOptions {
+                    spawn_detached: cli.spawn_detached,
+                    spawn_suspended: cli.spawn_suspended,
+                    keep_root_process: cli.wait,
+                    priority: cli.priority,
+                    clear_env: cli.clear_env,
+                    env_vars: cli.env.clone(),
+                    output_path: cli.capture_output.clone(),
+                    append_output: cli.append_output,
+                    spawn_delay_millis: cli.delay_spawn_ms,
+                },
+                SearchOptions {
+                    window_classes: cli.win_class.clone(),
+                    window_class_ignores_case: cli.class_ci,
+                    process_name_filter: cli.process_name_filter.clone(),
+                    max_child_depth: cli.match_child_depth,
+                    exe_match: cli.exe_match.clone(),
+                    adopt_existing_window: cli.adopt_existing_on_single_instance,
+                    ready_file: cli.ready_file.clone(),
+                    ready_file_timeout_millis: cli.ready_file_timeout_ms,
+                },
+                TrackingOptions {
+                    watchdog_millis: cli.watchdog_ms,
+                    size_limits: (cli.window_min_size.is_some() || cli.window_max_size.is_some())
+                        .then_some(WindowSizeLimits {
+                            min: cli.window_min_size,
+                            max: cli.window_max_size,
+                            reapply_on_resize: cli.keep_size_limits,
+                        }),
+                },
+                event_hwnd,
+                CustomWindowMsg::WinEventHook as _,
+                CustomWindowMsg::WaitingForForeignWindowError as _,
+            )?
+        };
+
+        foreign_process_tree.set_restore_animation_suppressed(cli.no_minimize_animation);
+        foreign_process_tree.set_snap_corner(cli.snap_corner);
+        foreign_process_tree.set_centered_on_show(cli.center_on_show);
+        foreign_process_tree
+            .set_position_pin_tolerance((!cli.no_position_pin).then_some(cli.pin_tolerance));
+        foreign_process_tree.set_main_window_followed(cli.follow_main_window);
+        foreign_process_tree.set_kept_hidden(cli.tray_only);
+        foreign_process_tree.set_hidden_on_discovery(cli.hide_grace_on_start);
+        foreign_process_tree.set_hidden_windows_included(cli.include_hidden_windows);
+        foreign_process_tree.set_title_child_class(cli.match_child_class.clone());
+        foreign_process_tree.set_moves_reported(cli.remember_state);
+        foreign_process_tree.set_closed_on_drop(cli.exit_with_parent.then_some(cli.close_method));
+        foreign_process_tree
+            .set_foreground_tracked(
+                cli.hide_on_focus_loss || cli.raise_on_click || cli.control_port.is_some(),
+            )
+            .map_err(TrayValetError::HookFailed)?;
+
+        Ok(foreign_process_tree)
+    }
+
+    pub fn wait_for_foreign_exit(&mut self) -> Option<i32> {
+        //! With `--wait`, blocks until the started process exited and returns its exit code. Only if the foreign window was closed, since a released window's process keeps running. To be called after the message loop, before dropping.
+
+        if self.waits_for_exit && self.foreign_process_tree.window_closed() {
+            self.foreign_process_tree.wait_for_root_process_exit()
+        } else {
+            None
+        }
+    }
+
+    fn finish_foreign_window_configuration(&mut self) {
+        //! Sets the tooltip and hides the window or restores its remembered state. Deferred with `--wait-for-title`.
+
+        self.awaiting_title = false;
+        self.wait_for_title_timer = None;
+
+        let window_title = self
+            .foreign_process_tree
+            .window_title()
+            .unwrap_or_else(|_| "".to_string());
+        self.title_matched = self
+            .focus_title_regex
+            .as_ref()
+            .is_some_and(|regex| regex.is_match(&window_title));
+        self.set_tooltip_from_title(window_title);
+
+        let remembered_state = self.state_key.as_deref().and_then(state::load);
+        // (A rectangle on a monitor that was disconnected in the meantime would leave the window out of reach.)
+        if let Some(rect) = remembered_state
+            .and_then(|app_state| app_state.rect)
+            .filter(|rect| !unsafe { MonitorFromRect(rect, MONITOR_DEFAULTTONULL) }.is_invalid())
+        {
+            self.foreign_process_tree.set_window_rect(rect);
+        }
+
+        match remembered_state {
+            Some(app_state) => {
+                if app_state.visible != self.foreign_process_tree.window_visible() {
+                    self.foreign_process_tree
+                        .set_window_visible(app_state.visible);
+                }
+            }
+            None if self.hide_after_start => self.foreign_process_tree.set_window_visible(false),
+            None => {}
+        }
+
+        self.handle_visibility_change();
+    }
+
+    fn save_state(&self) {
+        //! Saves the foreign window's visibility and rectangle for `--remember-state`, unless the window is gone or was already restored.
+
+        let key = if let Some(key) = self.state_key.as_deref() {
+            key
+        } else {
+            return;
+        };
+
+        if self.foreign_process_tree.hwnd().is_none()
+            || self.foreign_process_tree.window_closed()
+            || self.foreign_process_tree.released()
+        {
+            return;
+        }
+
+        let app_state = AppState {
+            visible: self.foreign_process_tree.window_visible(),
+            rect: self.foreign_process_tree.window_normal_rect(),
+        };
+        if let Err(error) = state::save(key, &app_state) {
+            Self::show_warning(
+                format!("Couldn't save the state: {error:#}"),
+                self.quiet_errors,
+            );
+        }
+    }
+
+    fn focus_on_title_match(&mut self, window_title: &str) {
+        //! Shows and focuses the foreign window when its title starts to match `--focus-on-title`, unless the user is busy with a fullscreen app.
+
+        let matched = self
+            .focus_title_regex
+            .as_ref()
+            .is_some_and(|regex| regex.is_match(window_title));
+        let was_matched = mem::replace(&mut self.title_matched, matched);
+
+        if matched && !was_matched && !fullscreen_app_running() {
+            self.last_activity_time = Instant::now();
+            self.foreign_process_tree.set_window_visible(true);
+            self.foreign_process_tree.activate_window();
+            self.handle_visibility_change();
+        }
+    }
+
+    fn set_tooltip_from_title(&mut self, window_title: String) {
+        if self.emits_events && window_title != self.foreign_window_title {
+            event_output::emit(Event::Title(&window_title), self.app_index);
+        }
+        self.foreign_window_title.clone_from(&window_title);
+
+        if let Some(tray_icon) = self.tray_icon.as_mut() {
+            let tooltip = if self.foreign_window_hung {
+                format!("{window_title} (Not responding)")
+            } else {
+                window_title
+            };
+
+            let _ = tray_icon.set_tooltip(tooltip);
+        }
+    }
+
+    fn probe_foreign_window(&mut self) {
+        //! Updates the tooltip and runs `--on-hung` when the foreign window stops or resumes responding.
+
+        let hung = !self
+            .foreign_process_tree
+            .window_responding(self.hang_timeout_millis);
+        if hung == self.foreign_window_hung {
+            return;
+        }
+
+        self.foreign_window_hung = hung;
+
+        if hung {
+            if let Some(command_line) = self.on_hung_command.as_ref() {
+                let _ = event_command::run(command_line, &self.event_command_tokens());
+            }
+        }
+
+        if !self.awaiting_title {
+            let window_title = self
+                .foreign_process_tree
+                .window_title()
+                .unwrap_or_else(|_| "".to_string());
+            self.set_tooltip_from_title(window_title);
+        }
+    }
+
+    fn foreign_window_was_foreground(&self) -> bool {
+        //! Whether the foreign window is or just was the foreground window, before a click on the tray icon took that state.
+
+        self.foreign_window_foreground
+            || self
+                .foreground_loss_time
+                .is_some_and(|time| time.elapsed().as_millis() < FOREGROUND_LOSS_GRACE_MILLIS)
+    }
+
+    fn hide_on_focus_loss(&mut self) {
+        //! Hides the foreign window if another app's window is still in the foreground. Windows of this app, like the context menu, don't count.
+
+        self.focus_loss_timer = None;
+
+        if self.context_menu_open
+            || self.foreign_window_moving
+            || !self.foreign_process_tree.window_visible()
+            || self.foreign_process_tree.window_in_foreground()
+        {
+            return;
+        }
+
+        let mut foreground_process_id = 0;
+        unsafe {
+            GetWindowThreadProcessId(GetForegroundWindow(), Some(&mut foreground_process_id))
+        };
+        if foreground_process_id == unsafe { GetCurrentProcessId() } {
+            return;
+        }
+
+        self.foreign_process_tree.set_window_visible(false);
+        self.last_focus_loss_hide_time = Some(Instant::now());
+        self.handle_visibility_change();
+    }
+
+    fn hide_when_idle(&mut self) {
+        //! Hides the foreign window once it didn't have any activity for `--auto-hide-idle-ms`. Since another process's input can't be observed, input anywhere in the system counts as activity while the window is in the foreground.
+
+        let idle_duration = if let Some(duration) = self.auto_hide_idle_duration {
+            duration
+        } else {
+            return;
+        };
+
+        if self.foreign_window_moving || !self.foreign_process_tree.window_visible() {
+            return;
+        }
+
+        if self.foreign_process_tree.window_in_foreground() {
+            if let Some(input_time) = last_input_time() {
+                self.last_activity_time = self.last_activity_time.max(input_time);
+            }
+        }
+
+        if self.last_activity_time.elapsed() >= idle_duration {
+            self.foreign_process_tree.set_window_visible(false);
+            self.handle_visibility_change();
+        }
+    }
+
+    fn handle_visibility_change(&mut self) {
+        //! Applies `--icon-shown` or `--icon-hidden` according to the foreign window's current visibility, and reports a change of it with `--emit-events` and the control server's `status`. To be called whenever the visibility may have changed.
+
+        self.update_control_status();
+
+        if self.emits_events {
+            let visible = self.foreign_process_tree.window_visible();
+            if self.emitted_visible != Some(visible) {
+                self.emitted_visible = Some(visible);
+                event_output::emit(
+                    if visible { Event::Shown } else { Event::Hidden },
+                    self.app_index,
+                );
+            }
+        }
+
+        if self.shown_hicon.is_none() && self.hidden_hicon.is_none() {
+            return;
+        }
+
+        self.refresh_tray_icon();
+    }
+
+    fn update_control_status(&self) {
+        if let Some(control_server) = self.control_server.as_ref() {
+            control_server.set_status(ControlStatus {
+                found: self.foreign_process_tree.hwnd().is_some() && !self.foreign_window_destroyed,
+                visible: self.foreign_process_tree.window_visible(),
+                foreground: self.foreign_process_tree.window_in_foreground(),
+                max_event_lag_millis: self.foreign_process_tree.max_event_lag_millis(),
+            });
+        }
+    }
+
+    fn refresh_tray_icon(&mut self) {
+        //! Sets a copy of the icon for the foreign window's current visibility as the tray icon, with the badge, if any. Keeps the placeholder icon until the regular icon is loaded.
+
+        let tray_icon = if let Some(tray_icon) = self.tray_icon.as_mut() {
+            tray_icon
+        } else {
+            return;
+        };
+
+        let hicon = if self.foreign_process_tree.window_visible() {
+            self.shown_hicon
+        } else {
+            self.hidden_hicon
+        }
+        .or(self.small_hicon);
+
+        if let Some(hicon) = hicon {
+            // (The regular icon is used if there's no silhouette.)
+            let monochrome_hicon = if self.icon_monochrome {
+                create_monochrome_icon(hicon, self.tray_icon_size).ok()
+            } else {
+                None
+            };
+            let source_hicon = monochrome_hicon.unwrap_or(hicon);
+
+            // (An icon that can't be badged is better than none.)
+            let tray_hicon = match self.badge_text.as_deref() {
+                Some(text) => create_badged_icon(source_hicon, text, self.tray_icon_size)
+                    .or_else(|_| duplicate_hicon(source_hicon)),
+                None => duplicate_hicon(source_hicon),
+            };
+
+            if let Some(monochrome_hicon) = monochrome_hicon {
+                destroy_hicon(monochrome_hicon);
+            }
+
+            if let Ok(tray_hicon) = tray_hicon {
+                let _ = tray_icon.set_icon(tray_hicon);
+            }
+        }
+    }
+
+    fn set_badge(&mut self, text: &str) {
+        self.badge_text = Self::badge_text(text);
+        self.refresh_tray_icon();
+    }
+
+    fn badge_text(text: &str) -> Option<String> {
+        //! An empty text or `0` means no badge.
+
+        (!text.is_empty() && text != "0").then(|| text.to_string())
+    }
+
+    fn load_icons_async(&mut self, exe_path: PathBuf) {
+        //! Loads the icons from the executable file on a worker thread, since the shell functions can stall on network paths or with antivirus software. `CustomWindowMsg::IconsLoaded` is posted when they can be taken with `take_loaded_icons()`. Does nothing while icons are still being loaded.
+
+        if self.icon_loader.is_some() {
+            return;
+        }
+
+        let event_hwnd = self.base_window.hwnd();
+        let with_large_icon = self.foreign_window_needs_icon;
+        let small_icon_size = self.tray_icon_size;
+
+        self.icon_loader = Some(thread::spawn(move || {
+            let small_hicon = load_tray_monitor_icon(&exe_path, false, small_icon_size).ok();
+            let large_hicon = with_large_icon
+                .then(|| load_tray_monitor_icon(&exe_path, true, None).ok())
+                .flatten();
+
+            let _ = unsafe {
+                PostMessageW(
+                    event_hwnd,
+                    CustomWindowMsg::IconsLoaded as _,
+                    WPARAM(0),
+                    LPARAM(0),
+                )
+            };
+
+            [small_hicon, large_hicon]
+        }));
+    }
+
+    fn take_loaded_icons(&mut self) -> [Option<HICON>; 2] {
+        //! Waits for the worker thread of `load_icons_async()`, if any, and returns its icons, which must be destroyed.
+
+        self.icon_loader
+            .take()
+            .and_then(|icon_loader| icon_loader.join().ok())
+            .unwrap_or_default()
+    }
+
+    fn set_foreign_window_icon(&mut self) {
+        if self.foreign_window_needs_icon {
+            if let (Some(small_hicon), Some(large_hicon)) = (self.small_hicon, self.large_hicon) {
+                self.foreign_process_tree.set_icon(small_hicon, large_hicon);
+            }
+        }
+    }
+
+    fn event_command_tokens(&self) -> [(&'static str, String); 6] {
+        let (process_id, thread_id) = self
+            .foreign_process_tree
+            .window_process_thread_id()
+            .unwrap_or((0, 0));
+        let hwnd = self.foreign_process_tree.hwnd().unwrap_or(HWND(0));
+
+        [
+            (
+                "title",
+                self.foreign_process_tree
+                    .window_title()
+                    .unwrap_or_else(|_| "".to_string()),
+            ),
+            ("hwnd", hwnd.0.to_string()),
+            ("hwnd_hex", format!("{:#x}", hwnd.0)),
+            ("pid", process_id.to_string()),
+            ("tid", thread_id.to_string()),
+            (
+                "rect",
+                self.foreign_process_tree.window_rect().map_or_else(
+                    || "".to_string(),
+                    |rect| format!("{},{},{},{}", rect.left, rect.top, rect.right, rect.bottom),
+                ),
+            ),
+        ]
+    }
+
+    fn context_menu_entries(
+        header: Option<String>,
+        has_exe_path: bool,
+        awaiting_relaunch: bool,
+        folder_hicon: Option<HICON>,
+    ) -> Vec<MenuEntry<'static, ContextMenuItem>> {
+        let mut entries = Vec::new();
+
+        if let Some(header) = header {
+            entries.push(MenuEntry::Item {
+                id: ContextMenuItem::Header,
+                // (Prevents mnemonics.)
+                text: header.replace('&', "&&").into(),
+                enabled: false,
+                icon: None,
+            });
+            entries.push(MenuEntry::Separator);
+        }
+
+        if awaiting_relaunch {
+            // (Releasing just exits without a window.)
+            entries.extend([
+                MenuEntry::item(ContextMenuItem::Relaunch, "&Relaunch"),
+                MenuEntry::Separator,
+                MenuEntry::item(ContextMenuItem::ReleaseForeignWindowAndExit, "E&xit"),
+            ]);
+
+            return entries;
+        }
+
+        entries.extend([
+            MenuEntry::item(ContextMenuItem::ToggleForeignWindowVisible, "&Show/Hide"),
+            MenuEntry::Item {
+                id: ContextMenuItem::OpenExeFolder,
+                text: "Open &folder".into(),
+                enabled: has_exe_path,
+                icon: folder_hicon,
+            },
+            MenuEntry::Separator,
+            MenuEntry::item(ContextMenuItem::ReleaseForeignWindowAndExit, "&Release"),
+            MenuEntry::item(ContextMenuItem::CloseForeignWindowAndExit, "&Close"),
+        ]);
+
+        entries
+    }
+
+    fn show_context_menu(&mut self, x: i32, y: i32) {
+        //! `x` and `y` are the position from the tray icon event, which `--menu-position cursor` replaces with the cursor position.
+
+        let (x, y) = match self.menu_position {
+            MenuPosition::Icon => (x, y),
+            MenuPosition::Cursor => {
+                let mut cursor_pos = POINT::default();
+                match unsafe { GetCursorPos(&mut cursor_pos) } {
+                    Ok(()) => (cursor_pos.x, cursor_pos.y),
+                    Err(_) => (x, y),
+                }
+            }
+        };
+
+        let context_menu = if let Some(context_menu) = self.context_menu.as_mut() {
+            context_menu
+        } else {
+            return;
+        };
+
+        // (Rebuilt every time, since the header and the enabled state change.)
+        let header = self
+            .has_menu_header
+            .then(|| match self.foreign_process_tree.window_title() {
+                Ok(title) if !title.is_empty() => title,
+                _ => APP_NAME.to_string(),
+            });
+        let has_exe_path = self.foreign_process_tree.window_exe_path().is_some();
+        let default_item = if self.awaiting_relaunch {
+            ContextMenuItem::Relaunch
+        } else {
+            ContextMenuItem::ToggleForeignWindowVisible
+        };
+
+        let _ = context_menu.rebuild(
+            Self::context_menu_entries(
+                header,
+                has_exe_path,
+                self.awaiting_relaunch,
+                self.folder_hicon,
+            ),
+            default_item,
+        );
+
+        // (The menu's modal loop still dispatches messages.)
+        self.context_menu_open = true;
+        let item = context_menu.show(x, y);
+        self.context_menu_open = false;
+
+        match item {
+            Some(ContextMenuItem::ToggleForeignWindowVisible) => {
+                self.last_activity_time = Instant::now();
+                self.foreign_process_tree.toggle_window_visible();
+                self.handle_visibility_change();
+            }
+            Some(ContextMenuItem::OpenExeFolder) => self.open_exe_folder(),
+            Some(ContextMenuItem::ReleaseForeignWindowAndExit) => self.destroy(),
+            Some(ContextMenuItem::CloseForeignWindowAndExit) => self.close_foreign_window(),
+            Some(ContextMenuItem::Relaunch) => self.relaunch(),
+            Some(ContextMenuItem::Header) | None => {}
+        }
+    }
+
+    fn open_exe_folder(&self) {
+        //! Opens the folder of the foreign window's executable file in Explorer, with the file selected.
+
+        if let Some(exe_path) = self.foreign_process_tree.window_exe_path() {
+            let mut arg = OsString::from("/select,\"");
+            arg.push(exe_path);
+            arg.push("\"");
+
+            // (Explorer has its own command line parsing.)
+            let _ = Command::new("explorer.exe").raw_arg(arg).spawn();
+        }
+    }
+
+    fn notify_exit(&mut self) {
+        //! Shows a notification that the foreign window's app exited, then destroys this window after a moment. Destroys it right away, if the notification can't be shown.
+
+        if self.exit_notification_timer.is_some() {
+            return;
+        }
+
+        let text = self.exit_text();
+        let is_shown = self
+            .tray_icon
+            .as_mut()
+            .is_some_and(|tray_icon| tray_icon.show_balloon(APP_NAME, text).is_ok());
+        if is_shown {
+            self.exit_notification_timer = Timer::new(
+                self.base_window.hwnd(),
+                TimerId::BackgroundWindowExitNotification as _,
+                EXIT_NOTIFICATION_MILLIS,
+            )
+            .ok();
+        }
+
+        if self.exit_notification_timer.is_none() {
+            self.destroy_after_exit();
+        }
+    }
+
+    fn exit_text(&self) -> String {
+        if self.foreign_window_title.is_empty() {
+            "The app has exited.".to_string()
+        } else {
+            format!("{} has exited.", self.foreign_window_title)
+        }
+    }
+
+    fn handle_foreign_window_destroyed(&mut self) {
+        if mem::replace(&mut self.foreign_window_destroyed, true) {
+            return;
+        }
+
+        if self.emits_events {
+            event_output::emit(Event::Exited, self.app_index);
+        }
+        self.update_control_status();
+
+        if self.relaunch_cli.is_some() {
+            self.await_relaunch();
+        } else if self.notifies_on_exit {
+            self.notify_exit();
+        } else {
+            self.destroy_after_exit();
+        }
+    }
+
+    fn await_relaunch(&mut self) {
+        //! Keeps the tray icon with `--keep-alive-after-close`, showing in the tooltip that the app was closed, and registers `--relaunch-hotkey`.
+
+        self.awaiting_relaunch = true;
+        self.awaiting_title = false;
+        self.wait_for_title_timer = None;
+        self.probe_timer = None;
+        self.focus_loss_timer = None;
+        self.idle_check_timer = None;
+
+        let text = self.exit_text();
+        if let Some(tray_icon) = self.tray_icon.as_mut() {
+            if self.notifies_on_exit {
+                let _ = tray_icon.show_balloon(APP_NAME, text.clone());
+            }
+
+            let _ = tray_icon.set_tooltip(format!("{text} Click to relaunch."));
+        }
+
+        let hotkey = self
+            .relaunch_cli
+            .as_ref()
+            .and_then(|cli| cli.relaunch_hotkey);
+        if let Some(hotkey) = hotkey {
+            match HotkeyRegistration::new(self.base_window.hwnd(), RELAUNCH_HOTKEY_ID, hotkey) {
+                Ok(registration) => self.relaunch_hotkey_registration = Some(registration),
+                Err(error) => Self::show_warning(
+                    format!("Couldn't register the relaunch hotkey: {error}"),
+                    self.quiet_errors,
+                ),
+            }
+        }
+    }
+
+    fn relaunch(&mut self) {
+        //! Starts the foreign process tree anew after its window was closed with `--keep-alive-after-close`, and waits for the window like on startup.
+
+        let cli = match (self.awaiting_relaunch, self.relaunch_cli.as_ref()) {
+            (true, Some(cli)) => cli,
+            _ => return,
+        };
+
+        let foreign_process_tree =
+            match Self::new_foreign_process_tree(cli, self.base_window.hwnd()) {
+                Ok(foreign_process_tree) => foreign_process_tree,
+                Err(error) => {
+                    Self::show_warning(format!("{:#}", anyhow!(error)), self.quiet_errors);
+                    return;
+                }
+            };
+
+        self.on_found_hwnd_command = cli.on_found_hwnd.clone();
+        self.notifies_on_exit = cli.notify_on_exit;
+        // (The user relaunched the app to use it.)
+        self.hide_after_start = false;
+
+        // (Drops the old process tree, which unhooks the closed window.)
+        self.foreign_process_tree = foreign_process_tree;
+        self.awaiting_relaunch = false;
+        self.relaunch_hotkey_registration = None;
+        self.foreign_window_hung = false;
+        self.foreign_window_moving = false;
+        self.foreign_window_foreground = false;
+        self.foreground_loss_time = None;
+        self.title_matched = false;
+        self.foreign_window_title.clear();
+        self.foreign_window_destroyed = false;
+        self.emitted_visible = None;
+
+        // Show the waiting state until `ForeignWindowEvent::Found`.
+        if let Some(tray_icon) = self.tray_icon.as_mut() {
+            if let Ok(hicon) = load_placeholder_icon() {
+                let _ = tray_icon.set_icon(hicon);
+            }
+
+            let _ = tray_icon.set_tooltip(format!("{APP_NAME}: Waiting for the app..."));
+        }
+    }
+
+    fn show_warning(msg: String, quiet_errors: bool) {
+        if quiet_errors {
+            eprintln!("{APP_NAME}: {msg}");
+        } else {
+            win_msgbox::warning::<win_msgbox::Okay>(HSTRING::from(msg).as_ptr())
+                .title(HSTRING::from(APP_NAME).as_ptr())
+                .show()
+                .expect("improbable");
+        }
+    }
+
+    fn close_foreign_window(&mut self) {
+        if self.awaiting_relaunch {
+            return;
+        }
+
+        if self.foreign_process_tree.spawned() {
+            let close_method = self.close_method;
+            // (The user knows about this exit.)
+            self.notifies_on_exit = false;
+
+            match self.foreign_process_tree.close_window(close_method) {
+                // (Without the window, `ForeignWindowEvent::Destroyed` may have been missed.)
+                Ok(()) if self.foreign_process_tree.window_closed() => {
+                    self.handle_foreign_window_destroyed()
+                }
+                // (This should cause this app to exit also.)
+                Ok(()) => {}
+                Err(error) => Self::show_warning(
+                    format!("Couldn't close the foreign window: {error}"),
+                    self.quiet_errors,
+                ),
+            }
+        } else {
+            // (Cancels the delayed spawn.)
+            self.destroy();
+        }
+    }
+
+    fn destroy_after_exit(&mut self) {
+        //! Like `destroy()`, but with `--tray-min-lifetime-ms`, keeps the tray icon until it existed long enough.
+
+        let remaining_lifetime = self
+            .tray_min_lifetime
+            .saturating_sub(self.creation_time.elapsed());
+
+        if self.tray_icon.is_some() && !remaining_lifetime.is_zero() {
+            Timer::replace(
+                &mut self.destroy_timer,
+                self.base_window.hwnd(),
+                TimerId::BackgroundWindowDestroy as _,
+                remaining_lifetime.as_millis() as _,
+            );
+
+            if self.destroy_timer.is_some() {
+                // (Stops searching for a window while lingering.)
+                self.foreign_process_tree.release();
+                return;
+            }
+        }
+
+        self.destroy();
+    }
+
     fn destroy(&mut self) {
         let _ = unsafe { DestroyWindow(self.base_window.hwnd()) };
     }
@@ -116,9 +1144,20 @@ impl<'a> BackgroundWindow<'a> {
 
 impl Drop for BackgroundWindow<'_> {
     fn drop(&mut self) {
-        for hicon in [self.small_hicon, self.large_hicon] {
+        // (The icons may still be loading or the message about them may not have been handled anymore.)
+        for hicon in self.take_loaded_icons().into_iter().flatten() {
+            destroy_hicon(hicon);
+        }
+
+        for hicon in [
+            self.small_hicon,
+            self.large_hicon,
+            self.shown_hicon,
+            self.hidden_hicon,
+            self.folder_hicon,
+        ] {
             if let Some(hicon) = hicon {
-                let _ = unsafe { DestroyIcon(hicon) };
+                destroy_hicon(hicon);
             }
         }
     }
@@ -133,6 +1172,36 @@ impl<'a> OnWindowMsg for BackgroundWindow<'a> {
         lparam: LPARAM,
     ) -> Option<LRESULT> {
         match msg_id {
+            WM_TIMER if wparam.0 == TimerId::BackgroundWindowWaitForTitle as _ => {
+                // (Title didn't become non-empty in time.)
+                this.finish_foreign_window_configuration();
+                Some(LRESULT(0))
+            }
+            WM_TIMER if wparam.0 == TimerId::BackgroundWindowProbe as _ => {
+                this.probe_foreign_window();
+                Some(LRESULT(0))
+            }
+            WM_TIMER if wparam.0 == TimerId::BackgroundWindowDestroy as _ => {
+                this.destroy_timer = None;
+                this.destroy();
+                Some(LRESULT(0))
+            }
+            WM_TIMER if wparam.0 == TimerId::BackgroundWindowExitNotification as _ => {
+                this.destroy_after_exit();
+                Some(LRESULT(0))
+            }
+            WM_TIMER if wparam.0 == TimerId::BackgroundWindowIdleCheck as _ => {
+                this.hide_when_idle();
+                Some(LRESULT(0))
+            }
+            WM_TIMER if wparam.0 == TimerId::BackgroundWindowFocusLoss as _ => {
+                this.hide_on_focus_loss();
+                Some(LRESULT(0))
+            }
+            WM_HOTKEY if wparam.0 == RELAUNCH_HOTKEY_ID as _ => {
+                this.relaunch();
+                Some(LRESULT(0))
+            }
             WM_TIMER => this
                 .foreign_process_tree
                 .handle_timer_window_msg(wparam, lparam)
@@ -143,112 +1212,394 @@ impl<'a> OnWindowMsg for BackgroundWindow<'a> {
                 .map(|event| {
                     match event {
                         ForeignWindowEvent::Found => {
+                            this.update_control_status();
+                            if this.emits_events {
+                                let (process_id, _) = this
+                                    .foreign_process_tree
+                                    .window_process_thread_id()
+                                    .unwrap_or((0, 0));
+                                let hwnd = this.foreign_process_tree.hwnd().unwrap_or(HWND(0));
+                                event_output::emit(
+                                    Event::Found {
+                                        process_id,
+                                        hwnd: hwnd.0,
+                                    },
+                                    this.app_index,
+                                );
+                            }
+
                             // Configure tray icon.
                             let must_load_icon =
                                 this.small_hicon.is_none() && this.large_hicon.is_none();
 
-                            let exe_path = if must_load_icon {
-                                let exe_path = this.foreign_process_tree.window_exe_path();
-                                if let Some(path) = exe_path.as_ref() {
-                                    this.small_hicon = load_tray_monitor_icon(path, false).ok();
-                                    if let Some(hicon) = this.small_hicon {
-                                        let _ = this.tray_icon.set_icon(hicon);
+                            if must_load_icon {
+                                if let Some(exe_path) = this.foreign_process_tree.window_exe_path()
+                                {
+                                    if let Some(tray_icon) = this.tray_icon.as_mut() {
+                                        if let Ok(hicon) = load_placeholder_icon() {
+                                            let _ = tray_icon.set_icon(hicon);
+                                        }
                                     }
-                                }
 
-                                exe_path
+                                    this.load_icons_async(exe_path);
+                                }
                             } else {
-                                None
-                            };
+                                // (Replaces the placeholder icon after a relaunch.)
+                                this.refresh_tray_icon();
+                            }
 
-                            let window_title = this
-                                .foreign_process_tree
-                                .window_title()
-                                .unwrap_or_else(|_| "".to_string());
-                            let _ = this.tray_icon.set_tooltip(window_title);
+                            // (Foreground changes are only reported once the window is found.)
+                            this.foreign_window_foreground =
+                                this.foreign_process_tree.window_in_foreground();
 
-                            // Set window's icon.
-                            if this.foreign_window_needs_icon {
-                                if let (true, Some(exe_path)) = (must_load_icon, exe_path) {
-                                    this.large_hicon = load_tray_monitor_icon(exe_path, true).ok();
-                                }
+                            // Set window's icon. (Icons loaded asynchronously are set on arrival.)
+                            this.set_foreign_window_icon();
+
+                            // Adjust window style.
+                            if this.removed_foreign_window_style.0 != 0 {
+                                let style = this.removed_foreign_window_style;
+                                this.foreign_process_tree.remove_window_style(style);
+                            }
+
+                            if this.foreign_window_needs_no_taskbar_button {
+                                this.foreign_process_tree
+                                    .change_window_ex_style(WS_EX_TOOLWINDOW, WS_EX_APPWINDOW);
+                            }
+
+                            if let Some(corners) = this.foreign_window_corners {
+                                this.foreign_process_tree.set_window_corners(corners);
+                            }
+
+                            if let Some(caption) = this.foreign_window_caption.clone() {
+                                this.foreign_process_tree.set_window_title(&caption);
+                            }
+
+                            if this.auto_hide_idle_duration.is_some() {
+                                Timer::replace(
+                                    &mut this.idle_check_timer,
+                                    this.base_window.hwnd(),
+                                    TimerId::BackgroundWindowIdleCheck as _,
+                                    IDLE_CHECK_MILLIS,
+                                );
+                            }
+
+                            if let Some(millis) = this.probe_interval_millis {
+                                Timer::replace(
+                                    &mut this.probe_timer,
+                                    this.base_window.hwnd(),
+                                    TimerId::BackgroundWindowProbe as _,
+                                    millis,
+                                );
+                            }
 
-                                if let (Some(small_hicon), Some(large_hicon)) =
-                                    (this.small_hicon, this.large_hicon)
+                            if let Some(command_line) = this.on_found_hwnd_command.take() {
+                                if let Err(error) =
+                                    event_command::run(&command_line, &this.event_command_tokens())
                                 {
-                                    this.foreign_process_tree.set_icon(small_hicon, large_hicon);
+                                    Self::show_warning(
+                                        format!(
+                                            "Couldn't run the `--on-found-hwnd` command: {error}"
+                                        ),
+                                        this.quiet_errors,
+                                    );
                                 }
                             }
 
-                            // Hide window.
-                            if this.hide_after_start {
-                                this.foreign_process_tree.set_window_visible(false);
+                            // Set tooltip and hide window, possibly once the window has its real title.
+                            let has_title = this
+                                .foreign_process_tree
+                                .window_title()
+                                .is_ok_and(|title| !title.is_empty());
+
+                            if this.wait_for_title && !has_title {
+                                this.awaiting_title = true;
+                                Timer::replace(
+                                    &mut this.wait_for_title_timer,
+                                    this.base_window.hwnd(),
+                                    TimerId::BackgroundWindowWaitForTitle as _,
+                                    WAIT_FOR_TITLE_FALLBACK_MILLIS,
+                                );
+                            } else {
+                                this.finish_foreign_window_configuration();
                             }
                         }
                         ForeignWindowEvent::Minimized => {
-                            this.foreign_process_tree.set_window_visible(false)
+                            if let Some(command_line) = this.on_minimize_command.as_ref() {
+                                let _ =
+                                    event_command::run(command_line, &this.event_command_tokens());
+                            }
+
+                            // (Without a tray icon, the window couldn't be shown again.)
+                            if this.tray_icon.is_some() {
+                                this.foreign_process_tree.set_window_visible(false);
+                                this.handle_visibility_change();
+                            }
+                        }
+                        ForeignWindowEvent::Restored => {
+                            this.last_activity_time = Instant::now();
+                            // (Visibility is always queried from the window, so toggling stays in sync.)
+                            this.handle_visibility_change();
                         }
                         ForeignWindowEvent::TitleChanged => {
-                            let foreign_window_title = this
+                            let mut foreign_window_title = this
                                 .foreign_process_tree
                                 .window_title()
                                 .unwrap_or_else(|_| "".to_string());
-                            let _ = this.tray_icon.set_tooltip(foreign_window_title);
+
+                            // (Comparing prevents a feedback loop with the name change caused by setting the caption.)
+                            if let Some(caption) = this.foreign_window_caption.clone() {
+                                if this.must_force_caption && foreign_window_title != caption {
+                                    this.foreign_process_tree.set_window_title(&caption);
+                                    foreign_window_title = caption;
+                                }
+                            }
+
+                            if this.awaiting_title {
+                                if !foreign_window_title.is_empty() {
+                                    this.finish_foreign_window_configuration();
+                                }
+                            } else {
+                                this.focus_on_title_match(&foreign_window_title);
+                                this.set_tooltip_from_title(foreign_window_title);
+                            }
+                        }
+                        // (So that the rectangle is also remembered if this app is killed.)
+                        ForeignWindowEvent::Moved => this.save_state(),
+                        ForeignWindowEvent::MoveSizeStarted => {
+                            this.foreign_window_moving = true;
+                            this.focus_loss_timer = None;
+                        }
+                        ForeignWindowEvent::MoveSizeEnded => {
+                            this.foreign_window_moving = false;
+                            this.last_activity_time = Instant::now();
+
+                            // Re-evaluate, since focus loss during the drag was ignored.
+                            if this.hides_on_focus_loss {
+                                Timer::replace(
+                                    &mut this.focus_loss_timer,
+                                    this.base_window.hwnd(),
+                                    TimerId::BackgroundWindowFocusLoss as _,
+                                    FOCUS_LOSS_HIDE_DELAY_MILLIS,
+                                );
+                            }
+                        }
+                        ForeignWindowEvent::ForegroundChanged { is_ours } => {
+                            if this.foreign_window_foreground && !is_ours {
+                                this.foreground_loss_time = Some(Instant::now());
+                            }
+                            this.foreign_window_foreground = is_ours;
+                            this.update_control_status();
+
+                            if this.hides_on_focus_loss
+                                && !this.context_menu_open
+                                && !this.foreign_window_moving
+                            {
+                                // Restart timer.
+                                Timer::replace(
+                                    &mut this.focus_loss_timer,
+                                    this.base_window.hwnd(),
+                                    TimerId::BackgroundWindowFocusLoss as _,
+                                    FOCUS_LOSS_HIDE_DELAY_MILLIS,
+                                );
+                            }
                         }
-                        ForeignWindowEvent::Destroyed => this.destroy(),
+                        ForeignWindowEvent::Destroyed => this.handle_foreign_window_destroyed(),
                         ForeignWindowEvent::Internal => {}
                     }
 
                     LRESULT(0)
                 }),
+            id if id == CustomWindowMsg::Control as _ => {
+                if let Some(command) = ControlCommand::from_usize(wparam.0) {
+                    this.last_activity_time = Instant::now();
+
+                    match command {
+                        ControlCommand::Show | ControlCommand::Hide => {
+                            this.foreign_process_tree
+                                .set_window_visible(matches!(command, ControlCommand::Show));
+                            this.handle_visibility_change();
+                        }
+                        ControlCommand::Toggle => {
+                            this.foreign_process_tree.toggle_window_visible();
+                            this.handle_visibility_change();
+                        }
+                        ControlCommand::Close => this.close_foreign_window(),
+                        ControlCommand::Release => this.destroy(),
+                        ControlCommand::Badge => {
+                            if let Some(text) = this
+                                .control_server
+                                .as_ref()
+                                .and_then(ControlServer::take_badge)
+                            {
+                                this.set_badge(&text);
+                            }
+                        }
+                        ControlCommand::SendMessage => {
+                            if let Some(message) = this
+                                .control_server
+                                .as_ref()
+                                .and_then(ControlServer::take_message)
+                            {
+                                if let Err(error) = this.foreign_process_tree.send_message(
+                                    message.id,
+                                    WPARAM(message.wparam),
+                                    LPARAM(message.lparam),
+                                    this.sends_messages_sync,
+                                ) {
+                                    Self::show_warning(
+                                        format!("Couldn't send the message to the foreign window: {error}"),
+                                        this.quiet_errors,
+                                    );
+                                }
+                            }
+                        }
+                        ControlCommand::HideIcon | ControlCommand::ShowIcon => {
+                            if let Some(tray_icon) = this.tray_icon.as_mut() {
+                                let _ = tray_icon
+                                    .set_hidden(matches!(command, ControlCommand::HideIcon));
+                            }
+                        }
+                        // (Answered by the control server itself.)
+                        ControlCommand::Status => {}
+                    }
+                }
+
+                Some(LRESULT(0))
+            }
+            id if id == CustomWindowMsg::IconsLoaded as _ => {
+                // (The worker thread is about to end.)
+                let [small_hicon, large_hicon] = this.take_loaded_icons();
+
+                let old_hicons = [
+                    mem::replace(&mut this.small_hicon, small_hicon),
+                    mem::replace(&mut this.large_hicon, large_hicon),
+                ];
+
+                this.set_foreign_window_icon();
+                this.refresh_tray_icon();
+
+                // (After the replacements were applied, so that no window uses them anymore.)
+                for hicon in old_hicons.into_iter().flatten() {
+                    destroy_hicon(hicon);
+                }
+
+                Some(LRESULT(0))
+            }
             id if id == CustomWindowMsg::WaitingForForeignWindowError as _ => {
-                win_msgbox::error::<win_msgbox::Okay>(
-                    h!("Couldn't find the window with the specified class.").as_ptr(),
-                )
-                .title(HSTRING::from(APP_NAME).as_ptr())
-                .show()
-                .expect("improbable");
+                let error = this
+                    .foreign_process_tree
+                    .take_error()
+                    .unwrap_or(TrayValetError::WindowNotFound);
+                let msg = format!("{:#}", anyhow!(error));
+
+                if this.quiet_errors {
+                    eprintln!("{APP_NAME}: {msg}");
+                } else {
+                    win_msgbox::error::<win_msgbox::Okay>(HSTRING::from(msg).as_ptr())
+                        .title(HSTRING::from(APP_NAME).as_ptr())
+                        .show()
+                        .expect("improbable");
+                }
 
+                HAD_ERROR.with(|had_error| had_error.set(true));
                 this.destroy();
 
                 Some(LRESULT(0))
             }
+            // (The tray icon only lingers with `--tray-min-lifetime-ms` after the foreign app exited.)
+            id if id == CustomWindowMsg::TrayIcon as _ && this.destroy_timer.is_some() => {
+                Some(LRESULT(0))
+            }
             id if id == CustomWindowMsg::TrayIcon as _ => this
                 .tray_icon
-                .translate_window_msg(wparam, lparam)
+                .as_mut()
+                .and_then(|tray_icon| tray_icon.translate_window_msg(wparam, lparam))
                 .map(|event| {
                     match event {
-                        TrayIconEvent::Activated => {
-                            this.foreign_process_tree.toggle_window_visible();
+                        TrayIconEvent::Activated { x, y } if this.left_click_shows_menu => {
+                            this.show_context_menu(x as _, y as _)
                         }
-                        TrayIconEvent::ContextMenuRequested { x, y } => {
-                            this.context_menu.show(x as _, y as _)
+                        TrayIconEvent::Activated { .. } if this.awaiting_relaunch => {
+                            this.relaunch()
                         }
-                    }
+                        TrayIconEvent::Activated { .. } => {
+                            this.last_activity_time = Instant::now();
 
-                    LRESULT(0)
-                }),
-            WM_COMMAND => match base_window::translate_command_msg(wparam, lparam) {
-                CommandMsg::MenuItem { id } => ContextMenuItem::from_u16(id).map(|item| {
-                    match item {
-                        ContextMenuItem::ToggleForeignWindowVisible => {
-                            this.foreign_process_tree.toggle_window_visible();
+                            let is_cooling_down = this
+                                .last_toggle_time
+                                .is_some_and(|time| time.elapsed() < this.activate_cooldown);
+                            // (The click that took the focus is meant to hide the window, which already happened.)
+                            let was_just_hidden_on_focus_loss =
+                                this.last_focus_loss_hide_time.is_some_and(|time| {
+                                    time.elapsed().as_millis() < FOCUS_LOSS_ACTIVATION_GRACE_MILLIS
+                                });
+
+                            if !is_cooling_down && !was_just_hidden_on_focus_loss {
+                                this.last_toggle_time = Some(Instant::now());
+
+                                if this.raises_on_activation
+                                    && this.foreign_process_tree.window_visible()
+                                    && !this.foreign_window_was_foreground()
+                                {
+                                    this.foreign_process_tree.activate_window();
+                                } else {
+                                    this.foreign_process_tree.toggle_window_visible();
+                                    this.handle_visibility_change();
+                                }
+                            }
                         }
-                        ContextMenuItem::ReleaseForeignWindowAndExit => {
-                            this.destroy();
+                        TrayIconEvent::DoubleClicked
+                            if this.double_click_action == DoubleClickAction::Maximize =>
+                        {
+                            this.last_activity_time = Instant::now();
+                            this.last_toggle_time = Some(Instant::now());
+                            this.foreign_process_tree.show_window_maximized();
+                            this.handle_visibility_change();
                         }
-                        ContextMenuItem::CloseForeignWindowAndExit => {
-                            this.foreign_process_tree.close_window();
-                            // (This should cause this app to exit also.)
+                        TrayIconEvent::DoubleClicked => {}
+                        TrayIconEvent::ContextMenuRequested { x, y } => {
+                            this.show_context_menu(x as _, y as _)
                         }
                     }
 
                     LRESULT(0)
                 }),
-                _ => None,
+            // (The background window is never shown, but other apps or scripts can send it these, e.g., to hide the foreign window to the tray.)
+            WM_SYSCOMMAND => match translate_sys_command_msg(wparam, lparam) {
+                command @ (SystemCommand::Minimize | SystemCommand::Restore) => {
+                    this.last_activity_time = Instant::now();
+                    this.foreign_process_tree
+                        .set_window_visible(matches!(command, SystemCommand::Restore));
+                    this.handle_visibility_change();
+                    Some(LRESULT(0))
+                }
+                SystemCommand::Maximize => {
+                    this.last_activity_time = Instant::now();
+                    this.foreign_process_tree.show_window_maximized();
+                    this.handle_visibility_change();
+                    Some(LRESULT(0))
+                }
+                // (`DefWindowProcW()` turns `SC_CLOSE` into `WM_CLOSE`, which destroys the window, like "Release".)
+                SystemCommand::Close | SystemCommand::Other => None,
             },
+            WM_SETTINGCHANGE if this.icon_monochrome && is_theme_change(lparam) => {
+                this.refresh_tray_icon();
+                None
+            }
             WM_DESTROY => {
-                unsafe { PostQuitMessage(0) };
+                // (Before the foreign process tree is dropped, which shows the window again.)
+                this.save_state();
+
+                let remaining_count = WINDOW_COUNT.with(|count| {
+                    count.set(count.get() - 1);
+                    count.get()
+                });
+
+                if remaining_count == 0 {
+                    let exit_code = if HAD_ERROR.with(Cell::get) { 1 } else { 0 };
+                    unsafe { PostQuitMessage(exit_code) };
+                }
+
                 Some(LRESULT(0))
             }
             _ => None,
@@ -256,22 +1607,73 @@ impl<'a> OnWindowMsg for BackgroundWindow<'a> {
     }
 }
 
+fn last_input_time() -> Option<Instant> {
+    //! The time of the last keyboard or mouse input in the session.
+
+    let mut last_input_info = LASTINPUTINFO {
+        cbSize: size_of::<LASTINPUTINFO>() as _,
+        ..Default::default()
+    };
+    if !unsafe { GetLastInputInfo(&mut last_input_info) }.as_bool() {
+        return None;
+    }
+
+    // (The tick count wraps around after 49.7 days.)
+    let idle_millis = unsafe { GetTickCount() }.wrapping_sub(last_input_info.dwTime);
+    Instant::now().checked_sub(Duration::from_millis(idle_millis.into()))
+}
+
+fn is_theme_change(settings_lparam: LPARAM) -> bool {
+    //! Whether the `LPARAM` of `WM_SETTINGCHANGE` names the setting that changes with the light or dark theme.
+
+    settings_lparam.0 != 0
+        && unsafe { PCWSTR(settings_lparam.0 as _).to_string() }
+            .is_ok_and(|setting| setting == "ImmersiveColorSet")
+}
+
+fn fullscreen_app_running() -> bool {
+    //! Whether an app is in fullscreen mode or presentation mode is on, like the system checks before showing notifications.
+
+    matches!(
+        unsafe { SHQueryUserNotificationState() },
+        Ok(QUNS_BUSY | QUNS_RUNNING_D3D_FULL_SCREEN | QUNS_PRESENTATION_MODE)
+    )
+}
+
 #[repr(u32)]
 pub enum CustomWindowMsg {
     TrayIcon = WM_APP + 0,
     WinEventHook = WM_APP + 1,
     /// An error or timeout happened while waiting for the foreign window.
     WaitingForForeignWindowError = WM_APP + 3,
+    /// The icons loaded by `load_icons_async()` can be taken.
+    IconsLoaded = WM_APP + 4,
+    /// A `ControlCommand` as `WPARAM`, posted by the control server's thread.
+    Control = WM_APP + 5,
 }
 
 #[repr(usize)]
 pub enum TimerId {
     ForeignProcessTreeCheckForNewProcesses = 100, // Strangely, 0 and 1 are sent via `WM_TIMER` without calling `SetTimer()`.
+    BackgroundWindowWaitForTitle = 101,
+    ForeignProcessTreeDelaySpawn = 102,
+    ForeignProcessTreeWatchdog = 103,
+    BackgroundWindowProbe = 104,
+    ForeignProcessTreeMoveSettled = 105,
+    BackgroundWindowFocusLoss = 106,
+    BackgroundWindowExitNotification = 107,
+    BackgroundWindowIdleCheck = 108,
+    BackgroundWindowDestroy = 109,
 }
 
 #[derive(FromPrimitive, ToPrimitive)]
 enum ContextMenuItem {
     ToggleForeignWindowVisible,
+    OpenExeFolder,
     ReleaseForeignWindowAndExit,
     CloseForeignWindowAndExit,
+    /// Replaces the other items while awaiting a relaunch.
+    Relaunch,
+    /// The disabled item with the window title at the top.
+    Header,
 }
@@ -1,16 +1,72 @@
-use clap::Parser;
+use anyhow::{anyhow, Context, Result};
+use clap::{ArgGroup, Parser, ValueEnum};
+use regex::Regex;
+use std::{
+    env,
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+};
 
-#[derive(Parser)]
+use crate::{config, state, win32::hotkey::Hotkey};
+
+#[derive(Clone, Parser)]
 #[command(version)]
+#[command(group(ArgGroup::new("size_limits").multiple(true).args(["window_min_size", "window_max_size"])))]
 pub struct Cli {
-    /// The foreign top-level window's class name that'll be searched for in the foreign process tree. Can be found out with spy tools.
-    #[arg(long, required = true)]
-    pub win_class: String,
+    /// The foreign top-level window's class name that'll be searched for in the foreign process tree. Can be found out with spy tools or `--pick`. Several class names can be separated by commas or given by repeating the option, for apps whose class differs across versions or startup phases, e.g., `SplashClass,MainClass`. The first window with any of them is used.
+    #[arg(
+        long,
+        value_name = "CLASS",
+        value_delimiter = ',',
+        required_unless_present_any = ["config", "any_window", "pick"]
+    )]
+    pub win_class: Vec<String>,
+
+    /// Compares the window class case-insensitively, for apps that register their class with different casing across versions.
+    #[arg(long, requires = "win_class")]
+    pub class_ci: bool,
+
+    /// Instead of a window class, searches for the first visible top-level window in the foreign process tree that has a title bar and a title and isn't a tool window. Useful for single-window apps.
+    #[arg(long, conflicts_with = "win_class")]
+    pub any_window: bool,
+
+    /// The class name of a descendant window of the found window whose title is used for the tooltip, for apps that show their actual content in a child window under a generic frame, like MDI apps or embedding hosts. Showing, hiding and everything else still operates on the top-level frame. Until such a child exists, the frame's title is used.
+    #[arg(long, value_name = "CLASS", conflicts_with = "window_caption")]
+    pub match_child_class: Option<String>,
+
+    /// The ID of the tray icon, which, together with the hidden window, identifies it to the system. Keeping IDs stable and distinct between apps helps the system to remember how the user arranged the icons. The actual order can't be set programmatically. Defaults to 0.
+    #[arg(long, value_name = "ID", default_value_t = 0)]
+    pub tray_id: u32,
 
-    /// A path to the file with the icon that should be used instead of the icon from the executable file that's associated with the foreign window.
+    /// A path to the file with the icon that should be used instead of the icon from the executable file that's associated with the foreign window. Like all icon paths and the command, it can contain environment variables like `%USERPROFILE%`. Icon paths like `res://1` or `res://NAME` refer to an icon resource of Tray Valet's executable file.
     #[arg(long)]
     pub icon: Option<String>,
 
+    /// A path to an icon file for the tray icon while the foreign window is shown. Without `--icon-hidden`, the regular icon is used while it's hidden.
+    #[arg(long, value_name = "PATH")]
+    pub icon_shown: Option<String>,
+
+    /// A path to an icon file for the tray icon while the foreign window is hidden. Without `--icon-shown`, the regular icon is used while it's shown.
+    #[arg(long, value_name = "PATH")]
+    pub icon_hidden: Option<String>,
+
+    /// A short text like an unread count to draw on a red badge in the bottom-right corner of the tray icon. Can be changed at runtime with the `--control-port` command `badge TEXT`. An empty text or `0` shows no badge.
+    #[arg(long, value_name = "TEXT", conflicts_with = "no_tray")]
+    pub icon_badge: Option<String>,
+
+    /// Shows a single-color silhouette of the tray icon in white or black, depending on the taskbar's theme, like the system's tray icons. The color follows theme changes. Icons without transparency are shown as they are.
+    #[arg(long, conflicts_with = "no_tray")]
+    pub icon_monochrome: bool,
+
+    /// The width and height in pixels of the tray icon, instead of the size from the system metrics of the monitor with the taskbar. An escape hatch for third-party shells with other tray icon sizes.
+    #[arg(long, value_name = "PX", value_parser = clap::value_parser!(u32).range(1..))]
+    pub icon_size: Option<u32>,
+
+    /// Doesn't create a tray icon or context menu, for use as a mere window hider that shows the foreign window again when this app exits. Minimizing the foreign window then doesn't hide it, since it couldn't be shown again.
+    #[arg(long, conflicts_with_all = ["icon_shown", "icon_hidden", "menu_header"])]
+    pub no_tray: bool,
+
     /// When there's a discrepancy between the tray and the window icon, this switch can be used to apply the tray icon to the window.
     #[arg(long)]
     pub set_win_icon: bool,
@@ -19,6 +75,479 @@ pub struct Cli {
     #[arg(long)]
     pub dont_hide: bool,
 
-    /// The command and arguments to start the foreign process tree. Should always be used after a separating ` -- ` (surrounded by spaces). Not allowed to be empty.
+    /// Hides the foreign window again whenever it's shown or restored by other means than the tray icon, like by the app itself or via its taskbar button, so that it's only ever shown from the tray.
+    #[arg(long, conflicts_with = "no_tray")]
+    pub tray_only: bool,
+
+    /// Hides the foreign window the moment it's found, before setting it up, and again whenever the app shows it within a second after, to avoid a visible flash with apps that show their window briefly or repeatedly while starting, like `conhost powershell -WindowStyle Hidden`.
+    #[arg(long, conflicts_with = "dont_hide")]
+    pub hide_grace_on_start: bool,
+
+    /// Remembers whether the foreign window was shown or hidden and where it was when this app exits, and restores that the next time the window is found, instead of hiding it. The state is also saved whenever the window settled after moving, in case this app doesn't exit regularly. The state is kept per window class and command in `%APPDATA%\tray-valet\state.json`. A missing or corrupt file is ignored, as is a position that isn't on any monitor anymore. Nothing is remembered when the foreign window was closed.
+    #[arg(long, conflicts_with_all = ["snap_corner", "center_on_show"])]
+    pub remember_state: bool,
+
+    /// Defers setting the tooltip and hiding the foreign window until it has a non-empty title, for apps that set their title shortly after showing the window. Gives up waiting after a second.
+    #[arg(long)]
+    pub wait_for_title: bool,
+
+    /// A regular expression that makes the foreign window be shown and focused whenever its title starts to match, e.g., `waiting for input`, for background tools that need attention now and then. A title that already matches when the window is found doesn't count. Nothing happens while a fullscreen app or presentation is running.
+    #[arg(long, value_name = "REGEX", value_parser = Regex::new)]
+    pub focus_on_title: Option<Regex>,
+
+    /// Shows the foreign window's title as a disabled header at the top of the tray icon's context menu, to tell which app it controls.
+    #[arg(long)]
+    pub menu_header: bool,
+
+    /// Shows the context menu on left-click and on Space or Enter too, at the tray icon like on right-click. Its default item then toggles the foreign window.
+    #[arg(long, conflicts_with = "no_tray")]
+    pub left_click_menu: bool,
+
+    /// Where the context menu appears. Some non-standard trays report the cursor position instead of the tray icon's, or a position far off.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "POSITION",
+        default_value_t = MenuPosition::Icon,
+        conflicts_with = "no_tray"
+    )]
+    pub menu_position: MenuPosition,
+
+    /// A tray icon activation raises the foreign window instead of hiding it, if it's visible, but not the foreground window, e.g., because it's covered by other windows. (Then, another activation hides it.)
+    #[arg(long, conflicts_with_all = ["no_tray", "left_click_menu"])]
+    pub raise_on_click: bool,
+
+    /// Hides the foreign window as soon as another app's window becomes the foreground window, like a dropdown. Windows owned by the foreign window and the context menu don't count, and neither does focus loss while dragging the window.
+    #[arg(long, conflicts_with = "no_tray")]
+    pub hide_on_focus_loss: bool,
+
+    /// Hides the foreign window once it had no activity for this many milliseconds while shown, for a self-dismissing overlay. Since another app's input can't be observed, input anywhere counts while the window is in the foreground, and showing, moving and sizing it count too.
+    #[arg(long, value_name = "MILLIS", conflicts_with = "no_tray")]
+    pub auto_hide_idle_ms: Option<u32>,
+
+    /// Shows a notification at the tray icon when the foreign window is closed without using the context menu, e.g., when a forgotten background task finishes. This app then exits after a few seconds, unless `--keep-alive-after-close` is used.
+    #[arg(long, conflicts_with = "no_tray")]
+    pub notify_on_exit: bool,
+
+    /// The time in milliseconds within which further tray icon activations are ignored. Defaults to the system's double-click time, which can be unsuitable in remote sessions.
+    #[arg(long, value_name = "MILLIS")]
+    pub double_click_ms: Option<u32>,
+
+    /// The minimum time in milliseconds between toggles of the foreign window via the tray icon, to smooth out jittery clicking. Activations during it are ignored. Unlike `--double-click-ms`, which merges any activations, e.g., the two events of an Enter key press, it only starts with activations that toggled the window or maximized it on double-click, and doesn't suppress activations that open the context menu or relaunch the app.
+    #[arg(long, value_name = "MILLIS", default_value_t = 0)]
+    pub activate_cooldown_ms: u32,
+
+    /// The minimum time in milliseconds that the tray icon stays after it appeared, so that it doesn't just flash when a short-lived app exits right away. Only applies when the foreign app exits, not when exiting via the tray menu or otherwise.
+    #[arg(long, value_name = "MILLIS", default_value_t = 0)]
+    pub tray_min_lifetime_ms: u32,
+
+    /// What double-clicking the tray icon does in addition to the toggling by the first click.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "ACTION",
+        default_value_t = DoubleClickAction::Nothing,
+        conflicts_with_all = ["no_tray", "left_click_menu"]
+    )]
+    pub double_click: DoubleClickAction,
+
+    /// Removes the foreign window's minimize button once found. Best effort, since some apps reassert their window style. The original style is restored on exit.
+    #[arg(long)]
+    pub no_minimize_box: bool,
+
+    /// Removes the foreign window's maximize button once found. Best effort like `--no-minimize-box`.
+    #[arg(long)]
+    pub no_maximize_box: bool,
+
+    /// Removes the foreign window's resizable frame once found. Best effort like `--no-minimize-box`.
+    #[arg(long)]
+    pub no_resize: bool,
+
+    /// Replaces the foreign window's title once found, which also shows in the tooltip. The original title is restored on exit, unless the window changed its title meanwhile.
+    #[arg(long, value_name = "TEXT")]
+    pub window_caption: Option<String>,
+
+    /// Reapplies `--window-caption` whenever the foreign window changes its title again.
+    #[arg(long, requires = "window_caption")]
+    pub force_caption: bool,
+
+    /// The minimum size like `800x600` in pixels that the foreign window is resized to when found and when shown via the tray icon. Maximized windows are left alone, and snapped windows lose that state when resized. The original size is restored on exit.
+    #[arg(long, value_name = "WxH", value_parser = parse_size)]
+    pub window_min_size: Option<(i32, i32)>,
+
+    /// The maximum size like `800x600` in pixels, applied like `--window-min-size`.
+    #[arg(long, value_name = "WxH", value_parser = parse_size)]
+    pub window_max_size: Option<(i32, i32)>,
+
+    /// Also applies `--window-min-size` and `--window-max-size` whenever the foreign window is resized, since apps can resize themselves.
+    #[arg(long, requires = "size_limits")]
+    pub keep_size_limits: bool,
+
+    /// Moves the foreign window into this corner of the work area of the monitor with the taskbar whenever it's shown via the tray icon, keeping its size. Maximized windows are left alone. The original position is restored on exit, so that the app doesn't remember the corner.
+    #[arg(long, value_enum, value_name = "CORNER")]
+    pub snap_corner: Option<SnapCorner>,
+
+    /// Moves the foreign window into the center of the work area of the monitor with the mouse cursor whenever it's shown via the tray icon, keeping its size. Maximized and snapped windows are left alone. The original position is restored on exit.
+    #[arg(long, conflicts_with = "snap_corner")]
+    pub center_on_show: bool,
+
+    /// Doesn't move the foreign window back to where it was hidden, when it shows up elsewhere after being shown via the tray icon, which some apps cause after several hide/show cycles. Minimized, maximized and snapped windows are never moved back.
+    #[arg(long)]
+    pub no_position_pin: bool,
+
+    /// How many pixels any edge of the foreign window may be off after showing it before it's moved back to where it was hidden. Defaults to 8.
+    #[arg(
+        long,
+        value_name = "PIXELS",
+        default_value_t = 8,
+        conflicts_with = "no_position_pin"
+    )]
+    pub pin_tolerance: u32,
+
+    /// Skips the animation from the taskbar when showing the minimized foreign window via the tray icon. Since the animation setting is system-wide, it's only disabled for that moment.
+    #[arg(long)]
+    pub no_minimize_animation: bool,
+
+    /// Keeps the foreign window out of the taskbar, even while shown, by making it a tool window. Best effort like `--no-minimize-box`.
+    #[arg(long)]
+    pub no_taskbar_button: bool,
+
+    /// Makes the foreign window's corners round or square once found, on Windows 11. Only affects windows that the system composites and that aren't maximized. The default preference is restored on exit, since the original one can't be queried. Does nothing on Windows 10.
+    #[arg(long, value_enum, value_name = "STYLE")]
+    pub corners: Option<WindowCorners>,
+
+    /// Moves tracking to another main window of the same process, if the found window turns out to be a mere shell without title bar or size, like some apps show before their actual main window. Owned windows like dialogs don't count.
+    #[arg(long)]
+    pub follow_main_window: bool,
+
+    /// Also finds the window while it's hidden, for apps that create their main window hidden and only show it on demand. Only hidden windows with a title bar and title that aren't tool windows count, even with `--win-class`.
+    #[arg(long)]
+    pub include_hidden_windows: bool,
+
+    /// An image name like `powershell.exe` (case-insensitive) that descendant processes must have to be searched for the window. Other descendants are only tracked to find their own descendants.
+    #[arg(long)]
+    pub process_name_filter: Option<String>,
+
+    /// An image name like `app.exe` (case-insensitive), for when the started process is a wrapper like `cmd /c start app.exe` that exits before the window is found, leaving the actual app without a tracked parent. After the started process exited, processes with this image name that were started after it are searched for the window as well, anywhere in the system, until the regular timeout.
+    #[arg(long, value_name = "NAME")]
+    pub exe_match: Option<String>,
+
+    /// The number of generations of descendant processes to search for the window, with 0 meaning only the started process. Deeper descendants aren't tracked at all. Unbounded by default.
+    #[arg(long, value_name = "N")]
+    pub match_child_depth: Option<u32>,
+
+    /// Starts the foreign process tree detached from Tray Valet's console and Ctrl+C process group, so that neither affects the other. Meant for GUI apps. Console apps get no console, unless they create one themselves (like `conhost`).
+    #[arg(long)]
+    pub spawn_detached: bool,
+
+    /// Starts the foreign process suspended and only lets it run once Tray Valet watches it for windows, so that a window it shows right away can't be missed. Falls back to a regular start if that isn't possible.
+    #[arg(long)]
+    pub spawn_suspended: bool,
+
+    /// After the foreign window was closed, waits for the started process to exit and exits with its exit code, for scripts that wait for this app (e.g., with `start /wait` or `Start-Process -Wait`). Errors of this app take precedence. Releasing the window via the context menu doesn't wait.
+    #[arg(long)]
+    pub wait: bool,
+
+    /// Writes errors and warnings to stderr instead of showing message boxes that wait for the user, for use from scripts and scheduled tasks. Errors still lead to a nonzero exit code. Applies to all apps from `--config`.
+    #[arg(long)]
+    pub quiet_errors: bool,
+
+    /// The priority class of the started process, e.g., to keep a background tool from competing with foreground work. Only `idle` and `below-normal` are inherited by the processes it starts in turn; the other classes leave them at normal priority. By default, the process runs at normal priority, unless this app runs at idle or below-normal priority, which it then inherits.
+    #[arg(long, value_enum)]
+    pub priority: Option<ProcessPriority>,
+
+    /// Starts the foreign process tree without this app's environment variables, e.g., for reproducible launches that shouldn't be affected by the login environment. Most programs need at least `SystemRoot`, and many `PATH` and `TEMP`, which can be added with `--env`.
+    #[arg(long)]
+    pub clear_env: bool,
+
+    /// An environment variable like `NAME=VALUE` for the foreign process tree. Can be repeated.
+    #[arg(long, value_name = "NAME=VALUE", value_parser = parse_env_var)]
+    pub env: Vec<(String, String)>,
+
+    /// A path to a file that the started process's stdout and stderr are written to, which its descendants inherit, e.g., for postmortems of console tools. The file is overwritten on every launch. Can contain environment variables like `%USERPROFILE%`.
+    #[arg(long, value_name = "PATH")]
+    pub capture_output: Option<PathBuf>,
+
+    /// Appends to the `--capture-output` file instead of overwriting it.
+    #[arg(long, requires = "capture_output")]
+    pub append_output: bool,
+
+    /// The time in milliseconds to wait before starting the foreign process tree, e.g., to not contend with other apps at login. Quitting during the delay doesn't start it.
+    #[arg(long, value_name = "MILLIS")]
+    pub delay_spawn_ms: Option<u32>,
+
+    /// The interval in milliseconds of checking whether the foreign window still exists and belongs to the hooked thread, as a safety net for hooks that stop delivering events. If the window vanished, this app exits; if it moved to another thread, it's hooked anew. Off by default.
+    #[arg(long, value_name = "MILLIS")]
+    pub watchdog_ms: Option<u32>,
+
+    /// A command line that's run via `cmd /C` when the foreign window is minimized, before it's hidden. `{title}`, `{hwnd}` and `{rect}` are replaced with the window title, the decimal window handle and the window's `left,top,right,bottom` virtual-screen coordinates. The values are passed via environment variables like `TRAY_VALET_TITLE` with delayed expansion (`cmd /V:ON`), so that they can't inject commands; a literal `!` in the command line needs escaping.
+    #[arg(long, value_name = "CMD")]
+    pub on_minimize: Option<String>,
+
+    /// A command line that's run via `cmd /C` once the foreign window was found and is being monitored, so that external tools can work with the valid window handle. Besides the tokens of `--on-minimize`, `{hwnd_hex}`, `{pid}` and `{tid}` are replaced with the hexadecimal window handle and the IDs of the window's process and thread. Failure to run it isn't fatal.
+    #[arg(long, value_name = "CMD")]
+    pub on_found_hwnd: Option<String>,
+
+    /// Which part of a foreign window title that's too long for the tooltip is dropped.
+    #[arg(long, value_enum, default_value_t = TooltipTruncation::End)]
+    pub tooltip_truncate: TooltipTruncation,
+
+    /// The interval in milliseconds of checking whether the foreign window responds. While it doesn't, the tooltip says so.
+    #[arg(long, value_name = "MILLIS")]
+    pub probe_interval_ms: Option<u32>,
+
+    /// The time in milliseconds that the foreign window may take to respond to a probe. Probing doesn't block this app, so exceeding the time is only noticed at the next probe.
+    #[arg(
+        long,
+        value_name = "MILLIS",
+        default_value_t = 1000,
+        requires = "probe_interval_ms"
+    )]
+    pub hang_timeout_ms: u32,
+
+    /// A command line that's run via `cmd /C` when a probe finds the foreign window not responding anymore. Supports the same tokens as `--on-minimize`.
+    #[arg(long, value_name = "CMD", requires = "probe_interval_ms")]
+    pub on_hung: Option<String>,
+
+    /// How the context menu's "Close" item and `--exit-with-parent` close the foreign window.
+    #[arg(long, value_enum, default_value_t = CloseMethod::WmClose)]
+    pub close_method: CloseMethod,
+
+    /// Closes the foreign window with `--close-method` whenever this app exits, so that the foreign app doesn't outlive its tray icon. This includes the context menu's "Release" item. A hidden window is still shown again before, in case the app doesn't close, e.g., because the user cancels a confirmation dialog, except with `--close-method terminate`.
+    #[arg(long, conflicts_with = "no_tray")]
+    pub exit_with_parent: bool,
+
+    /// Keeps the tray icon after the foreign window was closed, so that the command can be started again via the context menu's "Relaunch" item, a click on the tray icon or `--relaunch-hotkey`, like a launcher for a single app. The context menu's "Exit" item then exits this app.
+    #[arg(long, conflicts_with_all = ["no_tray", "wait"])]
+    pub keep_alive_after_close: bool,
+
+    /// A key combination like `Ctrl+Alt+R` that relaunches the app with `--keep-alive-after-close`. It's only registered while the app is closed, so that other apps can use it meanwhile.
+    #[arg(
+        long,
+        value_name = "HOTKEY",
+        value_parser = Hotkey::parse,
+        requires = "keep_alive_after_close"
+    )]
+    pub relaunch_hotkey: Option<Hotkey>,
+
+    /// Accepts commands on this TCP port on `127.0.0.1`, one per line: `show`, `hide`, `toggle`, `close` or `release` (the latter two like the context menu items), `badge TEXT` like `--icon-badge`, `hide-icon` and `show-icon` to temporarily hide the tray icon, or, with `--allow-sendmsg`, `sendmsg ID WPARAM LPARAM`. Every command is answered with a line `ok` or `error: ...`, except for `status`, which is answered with a JSON object like `{"foreground":false,"found":true,"max_event_lag_ms":16,"visible":true}` about the foreign window. `max_event_lag_ms` is the longest observed delay of the hooks watching it, for diagnostics. For scripts in any language, e.g., `echo toggle | ncat 127.0.0.1 PORT`. Any local process can send commands, also ones of other users on the same machine.
+    #[arg(long, value_name = "PORT")]
+    pub control_port: Option<u16>,
+
+    /// Allows the `--control-port` command `sendmsg ID WPARAM LPARAM`, which posts an arbitrary window message to the foreign window, e.g., `sendmsg 0x111 40001 0` for a `WM_COMMAND` of a menu item. The numbers are decimal or hexadecimal with `0x`. The foreign app can't tell these messages apart from its own, so any local process can make it do anything it can be made to do via messages. Pointers in the parameters are meaningless in its process.
+    #[arg(long, requires = "control_port")]
+    pub allow_sendmsg: bool,
+
+    /// Sends the messages of `sendmsg` instead of posting them, so that they're handled before any later command. This app waits up to 5 seconds for the foreign window to handle each, unless it's hung, and is unresponsive meanwhile.
+    #[arg(long, requires = "allow_sendmsg")]
+    pub send_sync: bool,
+
+    /// Writes the foreign window's lifecycle events to stdout, one JSON object per line, for a parent process that orchestrates this app: `{"event":"found","hwnd":HWND,"pid":PID}`, `{"event":"shown"}`, `{"event":"hidden"}`, `{"event":"title","value":TITLE}` and `{"event":"exited"}`, each also with `"app":INDEX`, the zero-based index of the app in the `--config` file, or 0 without one. Later versions may add events and members. Nothing is written without a redirected stdout.
+    #[arg(long)]
+    pub emit_events: bool,
+
+    /// If the started process exits within half a second, adopts a matching window of any process, for single-instance apps that just hand over to their already running instance.
+    #[arg(long, requires = "win_class")]
+    pub adopt_existing_on_single_instance: bool,
+
+    /// A path to a file that the foreign app creates when it's ready, e.g., a lock file after a long initialization. The foreign window is only searched for once the file exists, which may already be the case at start, and the usual timeout for finding it counts from then. Can contain environment variables like `%USERPROFILE%`.
+    #[arg(long, value_name = "PATH")]
+    pub ready_file: Option<PathBuf>,
+
+    /// The time in milliseconds after starting the foreign app that the file of `--ready-file` may take to appear, after which waiting is given up with an error.
+    #[arg(
+        long,
+        value_name = "MILLIS",
+        default_value_t = 60000,
+        requires = "ready_file"
+    )]
+    pub ready_file_timeout_ms: u32,
+
+    /// Registers Tray Valet with the other arguments to run at login, then exits. Paths in the arguments should be absolute.
+    #[arg(long, conflicts_with = "uninstall_autostart")]
+    pub install_autostart: bool,
+
+    /// Removes the login entry that `--install-autostart` registered with the same other arguments, then exits.
+    #[arg(long)]
+    pub uninstall_autostart: bool,
+
+    /// Removes what `--remember-state` remembered about the apps and the login entry that `--install-autostart` registered with the same other arguments, then exits with a report of what was removed. Other apps' states are kept.
+    #[arg(long, conflicts_with_all = ["install_autostart", "uninstall_autostart"])]
+    pub reset: bool,
+
+    /// Lets you click any window under a crosshair cursor to show its class name and title along with a ready-to-use command line, then exits. The click doesn't reach the window. Right-click cancels.
+    #[arg(long, conflicts_with_all = ["install_autostart", "uninstall_autostart", "reset"])]
+    pub pick: bool,
+
+    /// A path to a TOML file with multiple apps to manage in one process, each in an `[[app]]` table. Its keys are the long options without `--`, plus `command` with the array of command and arguments. All other options are ignored.
+    #[arg(long, conflicts_with_all = ["win_class", "foreign_process_tree_args"])]
+    pub config: Option<PathBuf>,
+
+    /// A path to a UTF-8 text file with the command and arguments to start the foreign process tree, one per line, without any quoting. Empty lines are skipped. Avoids quoting troubles with long commands.
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with = "foreign_process_tree_args"
+    )]
+    pub args_file: Option<PathBuf>,
+
+    /// The command and arguments to start the foreign process tree. Should always be used after a separating ` -- ` (surrounded by spaces). Not allowed to be empty. Environment variables like `%USERPROFILE%` are expanded, also where the shell doesn't, e.g., in `--config` files.
     pub foreign_process_tree_args: Vec<String>,
 }
+
+impl Cli {
+    pub fn quiet_errors_in_args() -> bool {
+        //! Whether `--quiet-errors` was passed, checked without parsing, so that it also applies to parsing errors. Arguments after ` -- ` belong to the foreign process tree.
+
+        // (`env::args()` would panic on arguments that aren't valid Unicode, instead of letting parsing report them.)
+        env::args_os()
+            .skip(1)
+            .take_while(|arg| arg != OsStr::new("--"))
+            .any(|arg| arg == OsStr::new("--quiet-errors"))
+    }
+
+    pub fn into_app_clis(self) -> Result<Vec<Cli>> {
+        //! Returns the apps from the config file, if specified, or else the app specified on the command line.
+
+        let mut app_clis = if let Some(config_path) = self.config.as_ref() {
+            let mut app_clis = config::load_app_clis(config_path)?;
+            for cli in app_clis.iter_mut() {
+                cli.quiet_errors |= self.quiet_errors;
+            }
+
+            app_clis
+        } else {
+            vec![self]
+        };
+
+        for cli in app_clis.iter_mut() {
+            if cli.win_class.is_empty() && !cli.any_window {
+                return Err(anyhow!("Missing window class or `--any-window`."));
+            }
+
+            if let Some(args_path) = cli.args_file.as_ref() {
+                cli.foreign_process_tree_args = read_args_file(args_path)?;
+            }
+
+            if cli.foreign_process_tree_args.is_empty() {
+                return Err(anyhow!(
+                    "Missing command or command arguments after separating ` -- ` or in `--args-file`."
+                ));
+            }
+        }
+
+        Ok(app_clis)
+    }
+
+    pub fn state_key(&self) -> String {
+        //! The key of the app's state for `--remember-state`.
+
+        let window_classes = (!self.win_class.is_empty()).then(|| self.win_class.join(","));
+        state::state_key(window_classes.as_deref(), &self.foreign_process_tree_args)
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum CloseMethod {
+    /// Sends `WM_CLOSE` to the window, like its close button.
+    WmClose,
+    /// Sends Ctrl+C to the console of the window's process, for console programs that trap it. Falls back to `wm-close` without a console.
+    CtrlC,
+    /// Terminates the window's process without giving it a chance to clean up.
+    Terminate,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DoubleClickAction {
+    /// Ignores the second click like other activations within the double-click time.
+    Nothing,
+    /// Shows the foreign window maximized, also if it was just hidden by the first click.
+    Maximize,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MenuPosition {
+    /// At the position the tray reports with the event, which is normally the tray icon's.
+    Icon,
+    /// At the mouse cursor's current position.
+    Cursor,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum SnapCorner {
+    #[value(name = "tl")]
+    TopLeft,
+    #[value(name = "tr")]
+    TopRight,
+    #[value(name = "bl")]
+    BottomLeft,
+    #[value(name = "br")]
+    BottomRight,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum WindowCorners {
+    /// Lets the system decide, like for windows that don't express a preference.
+    Default,
+    Round,
+    /// Rounds with a smaller radius, like menus.
+    #[value(name = "roundsmall")]
+    RoundSmall,
+    Square,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ProcessPriority {
+    Idle,
+    BelowNormal,
+    Normal,
+    AboveNormal,
+    High,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum TooltipTruncation {
+    /// Cuts off the end.
+    End,
+    /// Keeps the start and the end around an ellipsis, e.g., for a document name at the start and a status at the end.
+    Middle,
+    /// Replaces the start with an ellipsis.
+    Start,
+}
+
+fn read_args_file(path: &Path) -> Result<Vec<String>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Couldn't read args file \"{}\".", path.display()))?;
+
+    // (Notepad used to write a BOM.)
+    let content = content.strip_prefix('\u{feff}').unwrap_or(&content);
+
+    Ok(content
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+fn parse_size(value: &str) -> Result<(i32, i32), String> {
+    let error = || format!("\"{value}\" isn't a size like 800x600.");
+
+    let (width, height) = value.split_once(['x', 'X']).ok_or_else(error)?;
+    match (width.trim().parse::<u16>(), height.trim().parse::<u16>()) {
+        (Ok(width), Ok(height)) => Ok((width.into(), height.into())),
+        _ => Err(error()),
+    }
+}
+
+fn parse_env_var(value: &str) -> Result<(String, String), String> {
+    // (Names of the hidden per-drive variables like `=C:` start with `=`.)
+    let separator_index = value
+        .char_indices()
+        .skip(1)
+        .find_map(|(index, char)| (char == '=').then_some(index));
+
+    match separator_index {
+        Some(index) => Ok((value[..index].to_string(), value[index + 1..].to_string())),
+        None => Err(format!("\"{value}\" isn't like NAME=VALUE.")),
+    }
+}
@@ -0,0 +1,86 @@
+use anyhow::{anyhow, Context, Result};
+use clap::Parser;
+use std::{fs, path::Path};
+use toml::{Table, Value};
+
+use crate::cli::Cli;
+
+pub fn load_app_clis<T>(path: T) -> Result<Vec<Cli>>
+where
+    T: AsRef<Path>,
+{
+    //! Reads a TOML file with `[[app]]` tables, each describing an app like the command line would. Every key is the name of a long option without the leading `--`, e.g., `win-class = "ConsoleWindowClass"` or `set-win-icon = true`. The key `command` holds the array with the command and arguments to start the foreign process tree.
+
+    let path = path.as_ref();
+    let table: Table = fs::read_to_string(path)
+        .with_context(|| format!("Couldn't read config file \"{}\".", path.display()))?
+        .parse()
+        .with_context(|| format!("Couldn't parse config file \"{}\".", path.display()))?;
+
+    let apps = match table.get("app") {
+        Some(Value::Array(apps)) if !apps.is_empty() => apps,
+        _ => return Err(anyhow!("Config file doesn't contain any `[[app]]` tables.")),
+    };
+
+    apps.iter()
+        .enumerate()
+        .map(|(index, app)| {
+            let app_number = index + 1;
+
+            let app = app
+                .as_table()
+                .ok_or_else(|| anyhow!("App {app_number} in config file isn't a table."))?;
+            let cli = Cli::try_parse_from(app_args(app)?)
+                .with_context(|| format!("Invalid app {app_number} in config file."))?;
+
+            if cli.config.is_some() {
+                return Err(anyhow!(
+                    "App {app_number} in config file can't reference another config file."
+                ));
+            }
+
+            Ok(cli)
+        })
+        .collect()
+}
+
+fn app_args(app: &Table) -> Result<Vec<String>> {
+    // (First argument is the binary name.)
+    let mut args = vec![env!("CARGO_PKG_NAME").to_string()];
+    let mut command_args = Vec::new();
+
+    for (key, value) in app {
+        match (key.as_str(), value) {
+            ("command", Value::Array(values)) => {
+                command_args = values.iter().map(value_arg).collect::<Result<_>>()?;
+            }
+            (_, Value::Boolean(true)) => args.push(format!("--{key}")),
+            (_, Value::Boolean(false)) => {}
+            // Repeatable options.
+            (_, Value::Array(values)) => {
+                for value in values {
+                    args.push(format!("--{key}"));
+                    args.push(value_arg(value)?);
+                }
+            }
+            (_, value) => {
+                args.push(format!("--{key}"));
+                args.push(value_arg(value)?);
+            }
+        }
+    }
+
+    args.push("--".to_string());
+    args.extend(command_args);
+
+    Ok(args)
+}
+
+fn value_arg(value: &Value) -> Result<String> {
+    match value {
+        Value::String(string) => Ok(string.clone()),
+        Value::Integer(integer) => Ok(integer.to_string()),
+        Value::Float(float) => Ok(float.to_string()),
+        _ => Err(anyhow!("Unsupported config value `{value}`.")),
+    }
+}
@@ -0,0 +1,455 @@
+use num_derive::FromPrimitive;
+use serde_json::{json, Value};
+use std::{
+    collections::VecDeque,
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+use windows::Win32::{
+    Foundation::{HWND, LPARAM, WPARAM},
+    UI::WindowsAndMessaging::PostMessageW,
+};
+
+/// How often a connection is checked for shutdown while waiting for the next command.
+const READ_TIMEOUT: Duration = Duration::from_millis(200);
+/// How long shutting down waits for the connection that wakes up the worker thread.
+const WAKE_UP_TIMEOUT: Duration = Duration::from_millis(500);
+/// The maximum length of a command line in bytes, including the line break. A longer line closes the connection.
+const MAX_LINE_LEN: u64 = 1024;
+
+/// A TCP server on `127.0.0.1` that accepts newline-delimited commands like `toggle` or `badge 3` and posts them to a window. Every command is answered with a line `ok` or `error: ...`, except for `status`. Shut down when dropped.
+///
+/// There's no authentication: Any local process can connect, also ones of other users on the same machine, e.g., via Remote Desktop. So the commands are limited to ones that can't do more harm than closing or releasing the foreign window, unless `sendmsg` is allowed.
+pub struct ControlServer {
+    port: u16,
+    shutdown: Arc<AtomicBool>,
+    status: Arc<Mutex<ControlStatus>>,
+    badge: Arc<Mutex<Option<String>>>,
+    /// `None`, if `sendmsg` isn't allowed.
+    messages: Option<Arc<Mutex<VecDeque<ForeignMessage>>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ControlServer {
+    pub fn new(
+        port: u16,
+        hwnd: HWND,
+        window_msg_id: u32,
+        allows_messages: bool,
+    ) -> io::Result<Self> {
+        //! The window receives the message with a `ControlCommand` as `WPARAM`. For `ControlCommand::Badge`, the argument must be retrieved via `take_badge()`, and for `ControlCommand::SendMessage`, via `take_message()`. `sendmsg` is only accepted with `allows_messages`. Connections are served one after another on a worker thread. Connections from other addresses than loopback ones are rejected, although they shouldn't be possible with the binding.
+
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        // (The system chooses a port for port 0.)
+        let port = listener.local_addr()?.port();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let status = Arc::new(Mutex::new(ControlStatus::default()));
+        let badge = Arc::new(Mutex::new(None));
+        let messages = allows_messages.then(|| Arc::new(Mutex::new(VecDeque::new())));
+
+        let thread = {
+            let shutdown = Arc::clone(&shutdown);
+            let status = Arc::clone(&status);
+            let badge = Arc::clone(&badge);
+            let messages = messages.clone();
+            // (`HWND` isn't `Send`.)
+            let hwnd_value = hwnd.0;
+
+            thread::spawn(move || {
+                serve(
+                    listener,
+                    &shutdown,
+                    &status,
+                    &badge,
+                    messages.as_deref(),
+                    HWND(hwnd_value),
+                    window_msg_id,
+                )
+            })
+        };
+
+        Ok(Self {
+            port,
+            shutdown,
+            status,
+            badge,
+            messages,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn set_status(&self, status: ControlStatus) {
+        //! What `status` reports from now on, since the window can't be asked synchronously from the worker thread.
+
+        *self.status.lock().unwrap_or_else(PoisonError::into_inner) = status;
+    }
+
+    pub fn take_badge(&self) -> Option<String> {
+        //! The argument of the last `ControlCommand::Badge` that wasn't taken yet. (Passed this way instead of via `LPARAM`, since a posted message is discarded along with its pointer when the window is destroyed.)
+
+        self.badge
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+    }
+
+    pub fn take_message(&self) -> Option<ForeignMessage> {
+        //! The oldest message of a `ControlCommand::SendMessage` that wasn't taken yet.
+
+        self.messages
+            .as_ref()?
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .pop_front()
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+
+        // Wake up the blocking `accept()`. (A connection being served notices the shutdown on its next read timeout.)
+        let woken_up = TcpStream::connect_timeout(
+            &SocketAddr::from((Ipv4Addr::LOCALHOST, self.port)),
+            WAKE_UP_TIMEOUT,
+        )
+        .is_ok();
+
+        // (Otherwise, joining could block forever. The detached thread then ends with the next connection, or with the process.)
+        if let Some(thread) = self.thread.take().filter(|_| woken_up) {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, FromPrimitive)]
+pub enum ControlCommand {
+    Show,
+    Hide,
+    Toggle,
+    /// Like the context menu's "Close" item.
+    Close,
+    /// Like the context menu's "Release" item.
+    Release,
+    /// Sets the tray icon's badge to the argument. An empty argument or `0` removes it.
+    Badge,
+    /// Sends the `ForeignMessage` in the argument to the foreign window.
+    SendMessage,
+    /// Hides the tray icon without removing it, e.g., during a presentation.
+    HideIcon,
+    ShowIcon,
+    /// Answered by the server itself with the `ControlStatus` as a JSON object, instead of being posted.
+    Status,
+}
+
+/// The foreign window's state as last set by the window, reported by `status` like `{"foreground":false,"found":true,"max_event_lag_ms":16,"visible":true}`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ControlStatus {
+    pub found: bool,
+    pub visible: bool,
+    /// Also true if a window owned by the foreign window is in the foreground, like its dialogs.
+    pub foreground: bool,
+    /// For diagnosing how far behind the win event hooks are running.
+    pub max_event_lag_millis: u32,
+}
+
+/// An arbitrary window message for the foreign window, from an argument like `0x8001 1 0`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ForeignMessage {
+    pub id: u32,
+    pub wparam: usize,
+    pub lparam: isize,
+}
+
+impl ForeignMessage {
+    fn parse(argument: &str) -> Option<Self> {
+        //! Expects the message ID, `WPARAM` and `LPARAM`, separated by whitespace, each decimal or hexadecimal with `0x`. The message ID must be below `0x10000`, like all window messages.
+
+        let mut values = argument.split_whitespace();
+        let message = Self {
+            id: parse_integer::<u16>(values.next()?)?.into(),
+            wparam: parse_integer(values.next()?)?,
+            lparam: parse_integer(values.next()?)?,
+        };
+
+        values.next().is_none().then_some(message)
+    }
+}
+
+fn parse_integer<T>(text: &str) -> Option<T>
+where
+    T: TryFrom<i128>,
+{
+    let (is_negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    // (`from_str_radix()` would accept another sign.)
+    if digits.starts_with(['+', '-']) {
+        return None;
+    }
+
+    let value = match digits.strip_prefix("0x") {
+        Some(hex_digits) => i128::from_str_radix(hex_digits, 16).ok()?,
+        None => digits.parse::<i128>().ok()?,
+    };
+
+    T::try_from(if is_negative { -value } else { value }).ok()
+}
+
+impl ControlStatus {
+    fn to_json(self) -> Value {
+        json!({
+            "found": self.found,
+            "visible": self.visible,
+            "foreground": self.foreground,
+            "max_event_lag_ms": self.max_event_lag_millis,
+        })
+    }
+}
+
+fn parse_command(line: &str) -> Option<(ControlCommand, &str)> {
+    //! Returns the command with its argument, which is empty for commands without one. The command name is case-insensitive. Surrounding whitespace, including `\r` from `\r\n`, is ignored.
+
+    let line = line.trim();
+    let (name, argument) = line
+        .split_once(char::is_whitespace)
+        .map_or((line, ""), |(name, argument)| (name, argument.trim()));
+
+    let command = match name.to_ascii_lowercase().as_str() {
+        "show" => ControlCommand::Show,
+        "hide" => ControlCommand::Hide,
+        "toggle" => ControlCommand::Toggle,
+        "close" => ControlCommand::Close,
+        "release" => ControlCommand::Release,
+        "status" => ControlCommand::Status,
+        "hide-icon" => ControlCommand::HideIcon,
+        "show-icon" => ControlCommand::ShowIcon,
+        "badge" => return Some((ControlCommand::Badge, argument)),
+        "sendmsg" => return Some((ControlCommand::SendMessage, argument)),
+        _ => return None,
+    };
+
+    argument.is_empty().then_some((command, argument))
+}
+
+fn serve(
+    listener: TcpListener,
+    shutdown: &AtomicBool,
+    status: &Mutex<ControlStatus>,
+    badge: &Mutex<Option<String>>,
+    messages: Option<&Mutex<VecDeque<ForeignMessage>>>,
+    hwnd: HWND,
+    window_msg_id: u32,
+) {
+    for stream in listener.incoming() {
+        if shutdown.load(Ordering::Relaxed) {
+            break;
+        }
+
+        let stream = if let Ok(stream) = stream {
+            stream
+        } else {
+            continue;
+        };
+
+        if !stream
+            .peer_addr()
+            .is_ok_and(|address| address.ip().is_loopback())
+        {
+            continue;
+        }
+
+        let _ = serve_connection(
+            stream,
+            shutdown,
+            status,
+            badge,
+            messages,
+            hwnd,
+            window_msg_id,
+        );
+    }
+}
+
+fn serve_connection(
+    stream: TcpStream,
+    shutdown: &AtomicBool,
+    status: &Mutex<ControlStatus>,
+    badge: &Mutex<Option<String>>,
+    messages: Option<&Mutex<VecDeque<ForeignMessage>>>,
+    hwnd: HWND,
+    window_msg_id: u32,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+
+    while !shutdown.load(Ordering::Relaxed) {
+        // (The limit keeps a client from filling the memory with an endless line.)
+        match reader
+            .by_ref()
+            .take(MAX_LINE_LEN - line.len() as u64)
+            .read_line(&mut line)
+        {
+            // (Closed by the client.)
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(error) if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                continue
+            }
+            Err(error) => return Err(error),
+        }
+
+        // (A timeout may have interrupted the line. What was read so far stays in the buffer.)
+        if !line.ends_with('\n') {
+            if line.len() as u64 >= MAX_LINE_LEN {
+                writeln!(writer, "error: line too long")?;
+                break;
+            }
+
+            continue;
+        }
+
+        if line.trim().is_empty() {
+            line.clear();
+            continue;
+        }
+
+        let reply = match parse_command(&line) {
+            Some((ControlCommand::Status, _)) => {
+                let status = *status.lock().unwrap_or_else(PoisonError::into_inner);
+                status.to_json().to_string()
+            }
+            Some((ControlCommand::SendMessage, _)) if messages.is_none() => {
+                "error: sendmsg isn't allowed".to_string()
+            }
+            Some((ControlCommand::SendMessage, argument))
+                if ForeignMessage::parse(argument).is_none() =>
+            {
+                "error: expected a message ID, WPARAM and LPARAM".to_string()
+            }
+            Some((command, argument)) => {
+                if command == ControlCommand::Badge {
+                    *badge.lock().unwrap_or_else(PoisonError::into_inner) =
+                        Some(argument.to_string());
+                }
+                let messages = messages.filter(|_| command == ControlCommand::SendMessage);
+                if let (Some(messages), Some(message)) = (messages, ForeignMessage::parse(argument))
+                {
+                    messages
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .push_back(message);
+                }
+
+                let result =
+                    unsafe { PostMessageW(hwnd, window_msg_id, WPARAM(command as _), LPARAM(0)) };
+                match result {
+                    Ok(()) => "ok".to_string(),
+                    Err(error) => {
+                        // (Otherwise, the next `sendmsg` would send this message instead.)
+                        if let Some(messages) = messages {
+                            messages
+                                .lock()
+                                .unwrap_or_else(PoisonError::into_inner)
+                                .pop_back();
+                        }
+
+                        format!("error: {}", error.message())
+                    }
+                }
+            }
+            None => format!("error: unknown command \"{}\"", line.trim()),
+        };
+        line.clear();
+
+        writeln!(writer, "{reply}")?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_command, ControlCommand, ControlStatus, ForeignMessage};
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(
+            parse_command("toggle\n"),
+            Some((ControlCommand::Toggle, ""))
+        );
+        assert_eq!(
+            parse_command("  Show\r\n"),
+            Some((ControlCommand::Show, ""))
+        );
+        assert_eq!(
+            parse_command("RELEASE"),
+            Some((ControlCommand::Release, ""))
+        );
+        assert_eq!(
+            parse_command("hide-icon\n"),
+            Some((ControlCommand::HideIcon, ""))
+        );
+        assert_eq!(parse_command("hide now\n"), None);
+        assert_eq!(parse_command("maximize\n"), None);
+        assert_eq!(parse_command(""), None);
+    }
+
+    #[test]
+    fn parses_badge_argument() {
+        assert_eq!(
+            parse_command("badge  12 new\r\n"),
+            Some((ControlCommand::Badge, "12 new"))
+        );
+        assert_eq!(parse_command("Badge\n"), Some((ControlCommand::Badge, "")));
+    }
+
+    #[test]
+    fn parses_message() {
+        assert_eq!(
+            parse_command("sendmsg 0x8001 1 -1\n"),
+            Some((ControlCommand::SendMessage, "0x8001 1 -1"))
+        );
+        assert_eq!(
+            ForeignMessage::parse("0x8001 1 -1"),
+            Some(ForeignMessage {
+                id: 0x8001,
+                wparam: 1,
+                lparam: -1,
+            })
+        );
+        assert_eq!(ForeignMessage::parse("0x10000 0 0"), None);
+        assert_eq!(ForeignMessage::parse("1 -1 0"), None);
+        assert_eq!(ForeignMessage::parse("1 --1 0"), None);
+        assert_eq!(ForeignMessage::parse("1 0"), None);
+        assert_eq!(ForeignMessage::parse("1 0 0 0"), None);
+    }
+
+    #[test]
+    fn serializes_status() {
+        assert_eq!(
+            parse_command("status\n"),
+            Some((ControlCommand::Status, ""))
+        );
+        assert_eq!(
+            ControlStatus {
+                found: true,
+                visible: true,
+                foreground: false,
+                max_event_lag_millis: 16,
+            }
+            .to_json()
+            .to_string(),
+            r#"{"foreground":false,"found":true,"max_event_lag_ms":16,"visible":true}"#
+        );
+    }
+}
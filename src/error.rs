@@ -0,0 +1,59 @@
+use std::{error::Error, fmt, io};
+
+/// The failures of setting up a tray icon for a foreign window, so that they can be told apart without parsing messages. Variants with a cause are worded as a prefix to it, which `anyhow`'s alternate formatting appends.
+#[derive(Debug)]
+pub enum TrayValetError {
+    /// The foreign process tree couldn't be started.
+    Spawn(io::Error),
+    /// The foreign window didn't appear in time.
+    WindowNotFound,
+    /// The file of `--ready-file` didn't appear in time.
+    ReadyFileTimeout { path: String },
+    /// The foreign process created with `--spawn-suspended` couldn't be resumed.
+    Resume(windows::core::Error),
+    /// A win event hook couldn't be set.
+    HookFailed(windows::core::Error),
+    /// An icon file couldn't be loaded.
+    IconLoad {
+        path: String,
+        source: windows::core::Error,
+    },
+    /// The tray icon couldn't be added to the taskbar.
+    TrayRegister(windows::core::Error),
+    /// The background window or its context menu couldn't be created.
+    WindowCreation(windows::core::Error),
+    /// The TCP port for `--control-port` couldn't be opened.
+    ControlPort(io::Error),
+}
+
+impl fmt::Display for TrayValetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Spawn(_) => write!(f, "Couldn't start the foreign process tree"),
+            Self::WindowNotFound => write!(f, "Couldn't find the window with the specified class"),
+            Self::ReadyFileTimeout { path } => {
+                write!(f, "The ready file \"{path}\" didn't appear in time")
+            }
+            Self::Resume(_) => write!(f, "Couldn't resume the suspended foreign process"),
+            Self::HookFailed(_) => write!(f, "Couldn't monitor the foreign window"),
+            Self::IconLoad { path, .. } => write!(f, "Couldn't load the icon from \"{path}\""),
+            Self::TrayRegister(_) => write!(f, "Couldn't add the tray icon"),
+            Self::WindowCreation(_) => write!(f, "Couldn't create the background window"),
+            Self::ControlPort(_) => write!(f, "Couldn't open the control port"),
+        }
+    }
+}
+
+impl Error for TrayValetError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Spawn(error) | Self::ControlPort(error) => Some(error),
+            Self::WindowNotFound | Self::ReadyFileTimeout { .. } => None,
+            Self::Resume(error)
+            | Self::HookFailed(error)
+            | Self::IconLoad { source: error, .. }
+            | Self::TrayRegister(error)
+            | Self::WindowCreation(error) => Some(error),
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use std::{
+    io,
+    os::windows::process::CommandExt,
+    process::{Command, Stdio},
+};
+use windows::Win32::System::Threading::CREATE_NO_WINDOW;
+
+pub fn run(command_line: &str, tokens: &[(&str, String)]) -> io::Result<()> {
+    //! Runs the user-specified command line via `cmd /C` without waiting for it and without a console window. Every token like `{title}` is replaced with a delayed-expansion reference to an environment variable like `TRAY_VALET_TITLE` holding its value, so that characters like `&` or `"` in the value aren't interpreted by `cmd`. (Regular `%VAR%` expansion happens before `cmd` parses the command line, which would reintroduce the problem.)
+
+    let mut command = Command::new("cmd");
+    command
+        .arg("/V:ON")
+        .arg("/C")
+        .raw_arg(substitute_tokens(command_line, tokens))
+        .stdin(Stdio::null())
+        .creation_flags(CREATE_NO_WINDOW.0);
+
+    for (name, value) in tokens {
+        command.env(env_var_name(name), value);
+    }
+
+    command.spawn()?;
+
+    Ok(())
+}
+
+fn substitute_tokens(command_line: &str, tokens: &[(&str, String)]) -> String {
+    tokens
+        .iter()
+        .fold(command_line.to_string(), |command_line, (name, _)| {
+            command_line.replace(&format!("{{{name}}}"), &format!("!{}!", env_var_name(name)))
+        })
+}
+
+fn env_var_name(token_name: &str) -> String {
+    format!("TRAY_VALET_{}", token_name.to_uppercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::substitute_tokens;
+
+    #[test]
+    fn substitutes_tokens() {
+        assert_eq!(
+            substitute_tokens(
+                "notify.exe \"{title}\" {hwnd} {title} {unknown}",
+                &[("title", "A & B".to_string()), ("hwnd", "1234".to_string())]
+            ),
+            "notify.exe \"!TRAY_VALET_TITLE!\" !TRAY_VALET_HWND! !TRAY_VALET_TITLE! {unknown}"
+        );
+    }
+}
@@ -0,0 +1,74 @@
+use serde_json::{json, Value};
+use std::io::{self, Write};
+
+/// A lifecycle event of the foreign window for `--emit-events`, written as a JSON object on its own line. Every object has an `"event"` member with the event's name and an `"app"` member with the app's zero-based index in the `--config` file (0 without one), plus the members shown with the variants. Names and members won't change, but new ones may be added, which parents should ignore.
+pub enum Event<'a> {
+    /// `{"event":"found","hwnd":5678,"pid":1234}` once the window was found. `hwnd` is decimal.
+    Found { process_id: u32, hwnd: isize },
+    /// `{"event":"shown"}` when the window was shown.
+    Shown,
+    /// `{"event":"hidden"}` when the window was hidden.
+    Hidden,
+    /// `{"event":"title","value":"..."}` when the window's title changed.
+    Title(&'a str),
+    /// `{"event":"exited"}` when the window was destroyed.
+    Exited,
+}
+
+impl Event<'_> {
+    fn to_json(&self, app_index: usize) -> Value {
+        let mut value = match self {
+            Self::Found { process_id, hwnd } => {
+                json!({ "event": "found", "pid": process_id, "hwnd": hwnd })
+            }
+            Self::Shown => json!({ "event": "shown" }),
+            Self::Hidden => json!({ "event": "hidden" }),
+            Self::Title(title) => json!({ "event": "title", "value": title }),
+            Self::Exited => json!({ "event": "exited" }),
+        };
+        value["app"] = app_index.into();
+
+        value
+    }
+}
+
+pub fn emit(event: Event, app_index: usize) {
+    //! Writes the event to stdout and flushes it, so that the parent gets it right away. Best effort, since there may be no stdout, or the parent may have closed its end of the pipe.
+
+    let mut stdout = io::stdout().lock();
+    let _ = writeln!(stdout, "{}", event.to_json(app_index)).and_then(|()| stdout.flush());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Event;
+
+    #[test]
+    fn serializes_events_in_one_line() {
+        for (event, line) in [
+            (
+                Event::Found {
+                    process_id: 1234,
+                    hwnd: 5678,
+                },
+                r#"{"app":0,"event":"found","hwnd":5678,"pid":1234}"#,
+            ),
+            (Event::Hidden, r#"{"app":0,"event":"hidden"}"#),
+            (
+                Event::Title("Line 1\nLine \"2\""),
+                r#"{"app":0,"event":"title","value":"Line 1\nLine \"2\""}"#,
+            ),
+            (Event::Exited, r#"{"app":0,"event":"exited"}"#),
+        ] {
+            assert_eq!(event.to_json(0).to_string(), line);
+        }
+    }
+
+    #[test]
+    fn identifies_app() {
+        assert_eq!(
+            Event::Shown.to_json(2).to_string(),
+            r#"{"app":2,"event":"shown"}"#
+        );
+    }
+}
@@ -1,147 +1,636 @@
-use anyhow::Result;
+use nohash_hasher::IntMap;
 use std::{
+    cell::Cell,
     ffi::{OsStr, OsString},
+    fs::OpenOptions,
     io,
-    mem::size_of,
-    os::windows::prelude::OsStringExt,
-    path::PathBuf,
-    process::Command,
-    time::Instant,
+    mem::{self, size_of},
+    os::windows::{prelude::OsStringExt, process::CommandExt},
+    path::{Path, PathBuf},
+    process::{Child, Command},
+    ptr,
+    rc::Rc,
+    slice,
+    sync::{Mutex, OnceLock, PoisonError},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 use windows::{
-    core::PWSTR,
+    core::{w, HSTRING, PCWSTR, PWSTR},
     Win32::{
         Foundation::{
             CloseHandle, SetLastError, BOOL, ERROR_INSUFFICIENT_BUFFER,
-            ERROR_INVALID_WINDOW_HANDLE, E_FAIL, HWND, LPARAM, MAX_PATH, S_OK, WIN32_ERROR, WPARAM,
+            ERROR_INVALID_WINDOW_HANDLE, E_FAIL, FILETIME, HANDLE, HWND, LPARAM, LRESULT, MAX_PATH,
+            POINT, RECT, S_OK, WIN32_ERROR, WPARAM,
+        },
+        Graphics::{
+            Dwm::{
+                DwmGetWindowAttribute, DwmSetWindowAttribute, DWMWA_EXTENDED_FRAME_BOUNDS,
+                DWMWA_WINDOW_CORNER_PREFERENCE, DWMWCP_DEFAULT, DWMWCP_DONOTROUND, DWMWCP_ROUND,
+                DWMWCP_ROUNDSMALL, DWM_WINDOW_CORNER_PREFERENCE,
+            },
+            Gdi::{
+                GetMonitorInfoW, MonitorFromPoint, MonitorFromRect, MONITORINFO,
+                MONITOR_DEFAULTTONEAREST, MONITOR_DEFAULTTONULL,
+            },
         },
         System::{
+            Console::{
+                AttachConsole, FreeConsole, GenerateConsoleCtrlEvent, GetConsoleProcessList,
+                SetConsoleCtrlHandler, ATTACH_PARENT_PROCESS, CTRL_C_EVENT,
+            },
             Diagnostics::ToolHelp::{
                 CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W,
                 TH32CS_SNAPPROCESS,
             },
+            SystemInformation::{GetSystemTimeAsFileTime, GetTickCount},
             Threading::{
-                OpenProcess, QueryFullProcessImageNameW, PROCESS_NAME_FORMAT,
-                PROCESS_QUERY_LIMITED_INFORMATION,
+                GetProcessTimes, OpenProcess, OpenThread, QueryFullProcessImageNameW, ResumeThread,
+                TerminateProcess, ABOVE_NORMAL_PRIORITY_CLASS, BELOW_NORMAL_PRIORITY_CLASS,
+                CREATE_NEW_PROCESS_GROUP, CREATE_SUSPENDED, DETACHED_PROCESS, HIGH_PRIORITY_CLASS,
+                IDLE_PRIORITY_CLASS, NORMAL_PRIORITY_CLASS, PROCESS_CREATION_FLAGS,
+                PROCESS_NAME_FORMAT, PROCESS_QUERY_LIMITED_INFORMATION, PROCESS_TERMINATE,
+                THREAD_SUSPEND_RESUME,
             },
         },
-        UI::WindowsAndMessaging::{
-            DestroyIcon, EnumWindows, GetClassNameW, GetWindowPlacement, GetWindowTextLengthW,
-            GetWindowTextW, GetWindowThreadProcessId, IsWindowVisible, KillTimer, PostMessageW,
-            SetForegroundWindow, SetTimer, ShowWindow, CHILDID_SELF, EVENT_OBJECT_CREATE,
-            EVENT_OBJECT_DESTROY, EVENT_OBJECT_NAMECHANGE, EVENT_OBJECT_SHOW,
-            EVENT_SYSTEM_MINIMIZESTART, HICON, ICON_BIG, ICON_SMALL, OBJID_WINDOW, SW_HIDE,
-            SW_RESTORE, SW_SHOW, SW_SHOWMAXIMIZED, SW_SHOWMINIMIZED, WINDOWPLACEMENT, WM_CLOSE,
-            WM_SETICON, WPF_RESTORETOMAXIMIZED,
+        UI::{
+            HiDpi::GetDpiForWindow,
+            WindowsAndMessaging::{
+                EnumChildWindows, EnumWindows, GetAncestor, GetClassNameW, GetCursorPos,
+                GetForegroundWindow, GetPropW, GetWindow, GetWindowLongPtrW, GetWindowPlacement,
+                GetWindowRect, GetWindowTextLengthW, GetWindowTextW, GetWindowThreadProcessId,
+                IsChild, IsIconic, IsWindow, IsWindowVisible, IsZoomed, PostMessageW, RemovePropW,
+                SendMessageCallbackW, SendMessageTimeoutW, SetForegroundWindow, SetPropW,
+                SetWindowLongPtrW, SetWindowPos, ShowWindow, SystemParametersInfoW, ANIMATIONINFO,
+                CHILDID_SELF, EVENT_OBJECT_CREATE, EVENT_OBJECT_DESTROY,
+                EVENT_OBJECT_LOCATIONCHANGE, EVENT_OBJECT_NAMECHANGE, EVENT_OBJECT_SHOW,
+                EVENT_SYSTEM_FOREGROUND, EVENT_SYSTEM_MINIMIZEEND, EVENT_SYSTEM_MINIMIZESTART,
+                EVENT_SYSTEM_MOVESIZEEND, EVENT_SYSTEM_MOVESIZESTART, GA_ROOT, GA_ROOTOWNER,
+                GWL_EXSTYLE, GWL_STYLE, GW_OWNER, HICON, ICON_BIG, ICON_SMALL, OBJID_WINDOW,
+                SMTO_ABORTIFHUNG, SPI_GETANIMATION, SPI_SETANIMATION, SWP_FRAMECHANGED,
+                SWP_NOACTIVATE, SWP_NOMOVE, SWP_NOSIZE, SWP_NOZORDER, SW_HIDE, SW_RESTORE, SW_SHOW,
+                SW_SHOWMAXIMIZED, SW_SHOWMINIMIZED, SW_SHOWNA, SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS,
+                WINDOWPLACEMENT, WINDOW_EX_STYLE, WINDOW_STYLE, WM_CLOSE, WM_GETICON, WM_NULL,
+                WM_SETICON, WM_SETTEXT, WPF_RESTORETOMAXIMIZED, WS_CAPTION, WS_EX_TOOLWINDOW,
+            },
         },
     },
 };
 
 use crate::{
     background_window::TimerId,
-    win32::win_event_hook::{ProcessThreadSet, WinEvent, WinEventHook},
+    cli::{CloseMethod, ProcessPriority, SnapCorner, WindowCorners},
+    error::TrayValetError,
+    win32::{
+        environment::expand_env_vars,
+        icon::get_tray_monitor,
+        timer::Timer,
+        win_event_hook::{ProcessThreadSet, WinEvent, WinEventHook},
+    },
 };
 
 const TIMEOUT_MILLIS: u128 = 2000;
+/// How soon the spawned process must exit to be considered a single-instance app handing over to its running instance.
+const SINGLE_INSTANCE_EXIT_MILLIS: u128 = 500;
+/// How long the window's location must stay unchanged for `ForeignWindowEvent::Moved`.
+const MOVE_SETTLE_MILLIS: u32 = 200;
+/// How long after discovery showing the window is undone with `set_hidden_on_discovery()`.
+const DISCOVERY_HIDE_GRACE_MILLIS: u128 = 1000;
+/// The distance in pixels at 96 DPI between the window and the edges of the work area with `set_snap_corner()`.
+const SNAP_MARGIN: i32 = 8;
+/// How long to wait for the console control event sent by `CloseMethod::CtrlC` to be delivered before this process stops ignoring it again.
+const CTRL_C_DELIVERY_MILLIS: u64 = 100;
+/// How long setting the window's title may block, unless the window is already considered hung.
+const SET_TITLE_TIMEOUT_MILLIS: u32 = 1000;
+/// The window property with the event window of the instance that adopted the window, so that other instances skip it.
+const CLAIM_PROP_NAME: PCWSTR = w!("TrayValet.Claim");
+/// The event ID of the synthetic win event for `ForeignWindowEvent::Moved`. (Outside of the ranges of system-defined events, since `EVENT_SYSTEM_MOVESIZEEND` is also received for real.)
+const EVENT_MOVE_SETTLED: u32 = 0x7FFF_0001;
+
+/// Whether this process's console, if any, was inherited from the parent process. A console of its own can't be reattached to after `CloseMethod::CtrlC` detached from it.
+static CONSOLE_INHERITED: OnceLock<bool> = OnceLock::new();
+/// Held while `CloseMethod::CtrlC` switches the console, which is shared by all threads.
+static CONSOLE_SWITCH: Mutex<()> = Mutex::new(());
 
 pub struct ForeignProcessTree {
+    /// The command and arguments. Emptied on spawn.
+    spawn_args: Vec<OsString>,
+    spawn_detached: bool,
+    spawns_suspended: bool,
+    /// `None` to inherit the priority class.
+    priority: Option<ProcessPriority>,
+    clear_env: bool,
+    /// Set after clearing. Emptied on spawn.
+    env_vars: Vec<(String, String)>,
+    /// The file for stdout and stderr.
+    output_path: Option<PathBuf>,
+    append_output: bool,
+    spawned: bool,
+    adopt_existing_window: bool,
+    /// A file whose existence the search for the window waits for. `None` once it exists.
+    ready_file: Option<PathBuf>,
+    /// When the ready file was noticed, from which on the timeout for finding the window counts.
+    ready_file_time: Option<Instant>,
+    /// How long the ready file may take to appear, counted from spawning.
+    ready_file_timeout_millis: u32,
+    keeps_root_process: bool,
+    /// Kept until it exits, if needed for `keep_root_process`, `adopt_existing_window` or `exe_match`.
+    root_process: Option<Child>,
+    /// Milliseconds since spawning when the root process was noticed to have exited.
+    root_process_exit_millis: Option<u128>,
+    root_process_exit_code: Option<i32>,
+    /// The system time of spawning, as `FILETIME` value.
+    spawn_file_time: u64,
+
+    /// Processes that are searched for the window.
     known_process_ids: Vec<u32>,
+    /// The generation of every tracked process, with 0 for the spawned process. Also contains descendants not matching the process name filter, which are only tracked to find their own descendants.
+    process_depths: IntMap<u32, u32>,
+    process_name_filter: Option<String>,
+    max_child_depth: Option<u32>,
+    /// An image name of processes that are tracked like the spawned process once it exited, if they were started after it.
+    exe_match: Option<String>,
 
     event_hwnd: HWND,
 
     win_event_hook: WinEventHook,
+    /// A global hook for foreground window changes, if enabled.
+    foreground_hook: Option<WinEventHook>,
+    follows_main_window: bool,
+    /// A hook for windows shown by the window's process, once the window is monitored with `follows_main_window`.
+    follow_hook: Option<WinEventHook>,
     win_event_window_msg_id: u32,
 
     time_waited: Instant,
     error_window_msg_id: u32,
+    /// The cause of the last error window message, until taken with `take_error()`.
+    error: Option<TrayValetError>,
 
-    window_class: String,
+    delay_spawn_timer: Option<Timer>,
+    check_for_new_processes_timer: Option<Timer>,
+    move_settled_timer: Option<Timer>,
+    watchdog_timer: Option<Timer>,
+
+    /// Any of them matches. Empty to match any main window.
+    window_classes: Vec<String>,
+    window_class_ignores_case: bool,
     hwnd: Option<HWND>,
     hook_process_thread_id: Option<(u32, u32)>,
     window_exe_path: Option<PathBuf>,
-    small_hicon: Option<HICON>,
-    large_hicon: Option<HICON>,
+    /// The style bits that `remove_window_style()` cleared, which were set before.
+    removed_window_style: Option<isize>,
+    /// The window, answered flag and send time of the last probe message of `window_responding()`.
+    probe: Option<(HWND, Rc<Cell<bool>>, Instant)>,
+    /// The worker thread of the last `CloseMethod::CtrlC`, which is waited for on drop.
+    ctrl_c_thread: Option<JoinHandle<()>>,
+    original_window_ex_style: Option<isize>,
+    /// Whether `set_window_corners()` changed the corner preference.
+    window_corners_changed: bool,
+    /// Small and big icon. Zero, if the window had none of its own.
+    original_hicons: Option<(HICON, HICON)>,
+    size_limits: Option<WindowSizeLimits>,
+    /// The window's size before it was first clamped to `size_limits`.
+    original_window_size: Option<(i32, i32)>,
+    snap_corner: Option<SnapCorner>,
+    centers_on_show: bool,
+    /// The window's top-left corner before it was first snapped to `snap_corner` or centered.
+    original_window_position: Option<(i32, i32)>,
+    /// How many pixels the window's edges may be off after showing before it's moved back to where it was hidden. `None` to not move it back.
+    position_pin_tolerance: Option<u32>,
+    /// The window's rectangle right before `set_window_visible()` last hid it, unless it was minimized, maximized or snapped.
+    rect_before_hide: Option<RECT>,
+    /// Whether showing a minimized window is done without the restore animation.
+    suppresses_restore_animation: bool,
+    keeps_hidden: bool,
+    hides_on_discovery: bool,
+    /// Whether hidden windows that look like main windows are found, too.
+    includes_hidden_windows: bool,
+    reports_moves: bool,
+    /// The class of a descendant window whose title is used instead of the window's own, for MDI and embedding hosts.
+    title_child_class: Option<String>,
+    /// How the window is closed on drop, instead of being shown again.
+    close_method_on_drop: Option<CloseMethod>,
+    /// When the window was hidden on discovery.
+    discovery_hide_time: Option<Instant>,
+    /// The visibility last requested via `set_window_visible()`. Initially `true`, since the window is found when shown.
+    intended_visible: bool,
+    /// Whether `init_hwnd_monitoring()` succeeded, after which showing isn't a discovery anymore.
+    hwnd_monitored: bool,
+    /// The original title and the one that replaced it.
+    original_window_title: Option<(String, String)>,
+    /// The maximum observed time between a win event happening and it being translated.
+    max_event_lag_millis: u32,
+    /// The interval of checking whether the window still exists and belongs to the hooked thread.
+    watchdog_millis: Option<u32>,
+    /// Whether `release()` was called, after which events and timers are ignored.
+    released: bool,
 }
 
 impl ForeignProcessTree {
     pub unsafe fn new<I, S>(
         args: I,
-        window_class: &str,
+        spawn_options: SpawnOptions,
+        search_options: SearchOptions,
+        tracking_options: TrackingOptions,
         event_hwnd: HWND,
         win_event_window_msg_id: u32,
         error_window_msg_id: u32,
-    ) -> Result<Self>
+    ) -> Result<Self, TrayValetError>
     where
         I: IntoIterator<Item = S>,
         S: AsRef<OsStr>,
     {
         //! The `WM_TIMER` window message must be handled by calling through to the appropriate method.
         //!
+        //! Environment variables like `%USERPROFILE%` in `args` are expanded. Failures after construction, like not finding the window in time, are reported via the error window message, with the cause from `take_error()`.
+        //!
         //! # Safety
         //! The win event hook window message must be handled appropriately by the window procedure by retrieving the `Box` from the raw pointer.
 
-        let mut args_iter = args.into_iter();
-        let program = args_iter
-            .next()
-            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, ""))?;
-        let process = Command::new(program).args(args_iter).spawn()?;
-        let process_id = process.id();
-
-        let mut win_event_hook = unsafe {
-            WinEventHook::new(ProcessThreadSet::All, event_hwnd, win_event_window_msg_id)
-        };
-        win_event_hook
-            .add_filtered_event(EVENT_OBJECT_CREATE, ProcessThreadSet::Process(process_id))?;
-        win_event_hook
-            .add_filtered_event(EVENT_OBJECT_SHOW, ProcessThreadSet::Process(process_id))?;
+        let SpawnOptions {
+            spawn_detached,
+            spawn_suspended,
+            keep_root_process,
+            priority,
+            clear_env,
+            env_vars,
+            output_path,
+            append_output,
+            spawn_delay_millis,
+        } = spawn_options;
+        let SearchOptions {
+            window_classes,
+            window_class_ignores_case,
+            process_name_filter,
+            max_child_depth,
+            exe_match,
+            adopt_existing_window,
+            ready_file,
+            ready_file_timeout_millis,
+        } = search_options;
+        let TrackingOptions {
+            watchdog_millis,
+            size_limits,
+        } = tracking_options;
+
+        // (Before any process is spawned that could share the console.)
+        CONSOLE_INHERITED.get_or_init(console_inherited);
+
+        let args: Vec<OsString> = args
+            .into_iter()
+            .map(|arg| expand_env_vars(arg.as_ref()))
+            .collect();
+        if args.is_empty() {
+            return Err(TrayValetError::Spawn(io::ErrorKind::NotFound.into()));
+        }
 
         let mut instance = Self {
-            known_process_ids: vec![process_id],
+            spawn_args: args,
+            spawn_detached,
+            spawns_suspended: spawn_suspended,
+            priority,
+            clear_env,
+            env_vars,
+            output_path,
+            append_output,
+            spawned: false,
+            adopt_existing_window,
+            ready_file: ready_file.map(|path| PathBuf::from(expand_env_vars(path.as_os_str()))),
+            ready_file_time: None,
+            ready_file_timeout_millis,
+            keeps_root_process: keep_root_process,
+            root_process: None,
+            root_process_exit_millis: None,
+            root_process_exit_code: None,
+            spawn_file_time: 0,
+
+            known_process_ids: Vec::new(),
+            process_depths: IntMap::default(),
+            process_name_filter,
+            max_child_depth,
+            exe_match,
 
             event_hwnd,
 
-            win_event_hook: win_event_hook,
+            // (Events are added on spawn.)
+            win_event_hook: unsafe {
+                WinEventHook::new(ProcessThreadSet::All, event_hwnd, win_event_window_msg_id)
+            },
+            foreground_hook: None,
+            follows_main_window: false,
+            follow_hook: None,
             win_event_window_msg_id,
 
             time_waited: Instant::now(),
             error_window_msg_id,
+            error: None,
+
+            delay_spawn_timer: None,
+            check_for_new_processes_timer: None,
+            move_settled_timer: None,
+            watchdog_timer: None,
 
-            window_class: window_class.to_string(),
+            window_classes,
+            window_class_ignores_case,
             hwnd: None,
             hook_process_thread_id: None,
             window_exe_path: None,
-            small_hicon: None,
-            large_hicon: None,
+            removed_window_style: None,
+            probe: None,
+            ctrl_c_thread: None,
+            original_window_ex_style: None,
+            window_corners_changed: false,
+            original_hicons: None,
+            size_limits,
+            original_window_size: None,
+            snap_corner: None,
+            centers_on_show: false,
+            original_window_position: None,
+            position_pin_tolerance: None,
+            rect_before_hide: None,
+            suppresses_restore_animation: false,
+            keeps_hidden: false,
+            hides_on_discovery: false,
+            includes_hidden_windows: false,
+            reports_moves: false,
+            title_child_class: None,
+            close_method_on_drop: None,
+            discovery_hide_time: None,
+            intended_visible: true,
+            hwnd_monitored: false,
+            original_window_title: None,
+            max_event_lag_millis: 0,
+            watchdog_millis: watchdog_millis.filter(|&millis| millis != 0),
+            released: false,
         };
 
-        if let Some(foreign_hwnd) = instance.find_window_in_process(process_id) {
-            instance.hwnd = Some(foreign_hwnd);
-            instance.init_hwnd_monitoring()?;
-        } else {
-            let _ = unsafe {
-                SetTimer(
+        match spawn_delay_millis {
+            Some(millis) if millis != 0 => {
+                instance.delay_spawn_timer = Timer::new(
                     event_hwnd,
-                    TimerId::ForeignProcessTreeCheckForNewProcesses as _,
-                    100, /*ms*/
-                    None,
+                    TimerId::ForeignProcessTreeDelaySpawn as _,
+                    millis,
                 )
-            };
+                .ok();
+            }
+            _ => instance.spawn()?,
         }
 
         Ok(instance)
     }
 
+    fn spawn(&mut self) -> Result<(), TrayValetError> {
+        let mut args_iter = mem::take(&mut self.spawn_args).into_iter();
+        let program = args_iter
+            .next()
+            .ok_or_else(|| TrayValetError::Spawn(io::ErrorKind::NotFound.into()))?;
+        let mut command = Command::new(program);
+        command.args(args_iter);
+        if self.clear_env {
+            command.env_clear();
+        }
+        command.envs(mem::take(&mut self.env_vars));
+        if let Some(output_path) = self.output_path.as_ref() {
+            let file = OpenOptions::new()
+                .create(true)
+                .write(true)
+                .append(self.append_output)
+                .truncate(!self.append_output)
+                .open(expand_env_vars(output_path.as_os_str()))
+                .map_err(TrayValetError::Spawn)?;
+            let error_file = file.try_clone().map_err(TrayValetError::Spawn)?;
+
+            // (Also works without a console, like in release builds, since the handles are inherited directly.)
+            command.stdout(file).stderr(error_file);
+        }
+        let mut creation_flags = PROCESS_CREATION_FLAGS(0);
+        if self.spawn_detached {
+            creation_flags |= DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP;
+        }
+        if let Some(priority) = self.priority {
+            creation_flags |= match priority {
+                ProcessPriority::Idle => IDLE_PRIORITY_CLASS,
+                ProcessPriority::BelowNormal => BELOW_NORMAL_PRIORITY_CLASS,
+                ProcessPriority::Normal => NORMAL_PRIORITY_CLASS,
+                ProcessPriority::AboveNormal => ABOVE_NORMAL_PRIORITY_CLASS,
+                ProcessPriority::High => HIGH_PRIORITY_CLASS,
+            };
+        }
+
+        self.spawn_file_time = file_time_to_u64(unsafe { GetSystemTimeAsFileTime() });
+        // Creating the process suspended closes the race of it showing its window before the hooks below are installed. (`Command` can't resume it, but it keeps the process handle for exit tracking.)
+        let suspended_spawn_result = self.spawns_suspended.then(|| {
+            command.creation_flags((creation_flags | CREATE_SUSPENDED).0);
+            command.spawn()
+        });
+        let (mut process, is_suspended) = match suspended_spawn_result {
+            Some(Ok(process)) => (process, true),
+            suspended_spawn_result => {
+                command.creation_flags(creation_flags.0);
+                let process = command.spawn().map_err(|error| {
+                    // (The error of the suspended attempt is kept, since it may be the actual cause.)
+                    TrayValetError::Spawn(match suspended_spawn_result {
+                        Some(Err(suspended_error)) => io::Error::new(
+                            error.kind(),
+                            format!("{error} (when created suspended: {suspended_error})"),
+                        ),
+                        _ => error,
+                    })
+                })?;
+
+                (process, false)
+            }
+        };
+        let process_id = process.id();
+        self.spawned = true;
+
+        let hook_result = self
+            .win_event_hook
+            .add_filtered_event(EVENT_OBJECT_CREATE, ProcessThreadSet::Process(process_id))
+            .and_then(|()| {
+                self.win_event_hook
+                    .add_filtered_event(EVENT_OBJECT_SHOW, ProcessThreadSet::Process(process_id))
+            });
+
+        // (Also if the hooks failed, since the process would otherwise be stuck.)
+        if is_suspended {
+            if let Err(error) = resume_process(process_id) {
+                let _ = process.kill();
+                return Err(TrayValetError::Resume(error));
+            }
+        }
+
+        if self.keeps_root_process || self.adopt_existing_window || self.exe_match.is_some() {
+            self.root_process = Some(process);
+        }
+
+        hook_result.map_err(TrayValetError::HookFailed)?;
+
+        self.known_process_ids.push(process_id);
+        self.process_depths.insert(process_id, 0);
+        self.time_waited = Instant::now();
+        // (The file may already exist.)
+        self.check_ready_file();
+
+        if let Some(foreign_hwnd) = self.find_window_in_process(process_id) {
+            self.hwnd = Some(foreign_hwnd);
+            self.init_hwnd_monitoring()
+                .map_err(TrayValetError::HookFailed)?;
+        } else {
+            self.check_for_new_processes_timer = Timer::new(
+                self.event_hwnd,
+                TimerId::ForeignProcessTreeCheckForNewProcesses as _,
+                100, /*ms*/
+            )
+            .ok();
+        }
+
+        Ok(())
+    }
+
+    pub fn spawned(&self) -> bool {
+        //! Whether the process tree was started, which `spawn_delay_millis` defers.
+
+        self.spawned
+    }
+
+    pub fn max_event_lag_millis(&self) -> u32 {
+        //! The longest time that passed so far between a win event happening and it being translated, which shows how far behind the out-of-context hooks are running.
+
+        self.max_event_lag_millis
+    }
+
+    pub fn take_error(&mut self) -> Option<TrayValetError> {
+        //! The cause of the last error window message, e.g., `TrayValetError::WindowNotFound` after the timeout.
+
+        self.error.take()
+    }
+
+    fn report_error(&mut self, error: TrayValetError) {
+        self.error = Some(error);
+
+        let _ = unsafe {
+            PostMessageW(
+                self.event_hwnd,
+                self.error_window_msg_id,
+                WPARAM(0),
+                LPARAM(0),
+            )
+        };
+    }
+
+    pub fn release(&mut self) {
+        //! Restores the window like dropping does and stops monitoring it and searching for it, for when the owner outlives its use. A delayed spawn doesn't happen anymore. Further calls and dropping do nothing.
+
+        if mem::replace(&mut self.released, true) {
+            return;
+        }
+
+        self.delay_spawn_timer = None;
+        self.check_for_new_processes_timer = None;
+        self.move_settled_timer = None;
+        self.watchdog_timer = None;
+
+        // (Nothing to restore, if the window was already destroyed.)
+        if self
+            .hwnd
+            .is_some_and(|hwnd| unsafe { IsWindow(hwnd) }.as_bool())
+        {
+            self.restore_window_style();
+            self.restore_window_ex_style();
+            self.restore_window_corners();
+            self.restore_icon();
+            self.restore_window_title();
+            // (Prevents clamping and snapping again when showing.)
+            self.size_limits = None;
+            self.snap_corner = None;
+            self.centers_on_show = false;
+            // (A window whose process is terminated would only flash.)
+            let is_terminated = matches!(self.close_method_on_drop, Some(CloseMethod::Terminate))
+                && self
+                    .window_process_thread_id()
+                    .is_some_and(|(process_id, _)| Self::terminate_process(process_id).is_ok());
+            if !is_terminated {
+                self.set_window_visible(true);
+            }
+            self.restore_window_size();
+            self.restore_window_position();
+
+            // (Shown before, in case the app doesn't close, e.g., because the user cancels a confirmation.)
+            if let Some(method) = self.close_method_on_drop.filter(|_| !is_terminated) {
+                let _ = self.close_window(method);
+            }
+        }
+
+        if let Some(hwnd) = self.hwnd {
+            self.release_window_claim(hwnd);
+        }
+    }
+
+    pub fn released(&self) -> bool {
+        self.released
+    }
+
     pub fn handle_timer_window_msg(&mut self, wparam: WPARAM, _lparam: LPARAM) -> bool {
         //! Returns `true`, if the message was handled.
 
+        // (Messages of killed timers may still be queued.)
+        if self.released {
+            return false;
+        }
+
         let timer_id = wparam.0;
+        if timer_id == TimerId::ForeignProcessTreeDelaySpawn as _ {
+            self.delay_spawn_timer = None;
+
+            if let Err(error) = self.spawn() {
+                self.report_error(error);
+            }
+
+            return true;
+        }
+
+        if timer_id == TimerId::ForeignProcessTreeMoveSettled as _ {
+            self.move_settled_timer = None;
+
+            if let (Some(hwnd), Some((_, thread_id))) = (self.hwnd, self.hook_process_thread_id) {
+                self.post_synthetic_win_event(EVENT_MOVE_SETTLED, hwnd, thread_id);
+            }
+
+            return true;
+        }
+
+        if timer_id == TimerId::ForeignProcessTreeWatchdog as _ {
+            self.check_hwnd_monitoring();
+            return true;
+        }
+
         if timer_id != TimerId::ForeignProcessTreeCheckForNewProcesses as _ {
             return false;
         }
 
-        let mut has_error = false;
+        // (The window may have been shown before the file appeared, so that no new process or win event leads to it anymore.)
+        if self.check_ready_file() {
+            if let Some(foreign_hwnd) = self
+                .known_process_ids
+                .iter()
+                .find_map(|&process_id| self.find_window_in_process(process_id))
+            {
+                self.adopt_window(foreign_hwnd);
+                self.check_for_new_processes_timer = None;
+                return true;
+            }
+        }
+
+        let mut error = None;
         let mut must_stop_timer = false;
 
+        let root_process_just_exited = self.poll_root_process_exit();
+        let exe_match = self
+            .exe_match
+            .clone()
+            .filter(|_| self.root_process_exit_millis.is_some());
+
         if let Ok(h_snapshot) = unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0) } {
             let mut process_entry = PROCESSENTRY32W::default();
             process_entry.dwSize = size_of::<PROCESSENTRY32W>() as _;
@@ -149,14 +638,36 @@ impl ForeignProcessTree {
                 unsafe { Process32FirstW(h_snapshot, &mut process_entry) };
 
             while let Ok(()) = next_process_result {
-                if self
-                    .known_process_ids
-                    .contains(&process_entry.th32ParentProcessID)
+                let parent_depth = self
+                    .process_depths
+                    .get(&process_entry.th32ParentProcessID)
+                    .copied();
+                let is_new_descendant = parent_depth.is_some()
+                    && !self
+                        .process_depths
+                        .contains_key(&process_entry.th32ProcessID);
+                let depth = parent_depth.map_or(0, |depth| depth + 1);
+                // (Checked last, since it requires opening the process.)
+                let is_wrapped_process = parent_depth.is_none()
+                    && exe_match.as_deref().is_some_and(|name| {
+                        Self::exe_file_name_matches(&process_entry.szExeFile, name)
+                    })
                     && !self
-                        .known_process_ids
-                        .contains(&process_entry.th32ProcessID)
+                        .process_depths
+                        .contains_key(&process_entry.th32ProcessID)
+                    && self.was_started_after_spawn(process_entry.th32ProcessID);
+
+                if is_new_descendant && self.max_child_depth.is_some_and(|max| depth > max) {
+                    // (Not tracked, so that its own descendants are excluded also.)
+                } else if is_new_descendant
+                    && !self.matches_process_name_filter(&process_entry.szExeFile)
                 {
+                    self.process_depths
+                        .insert(process_entry.th32ProcessID, depth);
+                } else if is_new_descendant || is_wrapped_process {
                     self.known_process_ids.push(process_entry.th32ProcessID);
+                    self.process_depths
+                        .insert(process_entry.th32ProcessID, depth);
 
                     let _ = self.win_event_hook.add_filtered_event(
                         EVENT_OBJECT_CREATE,
@@ -172,8 +683,8 @@ impl ForeignProcessTree {
                     {
                         self.hwnd = Some(foreign_hwnd);
 
-                        if let Err(_) = self.init_hwnd_monitoring() {
-                            has_error = true;
+                        if let Err(hook_error) = self.init_hwnd_monitoring() {
+                            error = Some(TrayValetError::HookFailed(hook_error));
                         }
 
                         must_stop_timer = true;
@@ -189,37 +700,247 @@ impl ForeignProcessTree {
             let _ = unsafe { CloseHandle(h_snapshot) };
         }
 
-        if self.hwnd == None && self.time_waited.elapsed().as_millis() > TIMEOUT_MILLIS {
-            has_error = true;
+        if self.hwnd == None && !must_stop_timer && root_process_just_exited {
+            if let Some(foreign_hwnd) = self.find_existing_window_on_quick_exit() {
+                self.adopt_window(foreign_hwnd);
+                must_stop_timer = true;
+            }
+        }
+
+        // (Waiting for the ready file has its own, longer timeout.)
+        let (wait_start_time, timeout_millis) = if self.ready_file.is_some() {
+            (self.time_waited, self.ready_file_timeout_millis as u128)
+        } else {
+            (
+                self.ready_file_time.unwrap_or(self.time_waited),
+                TIMEOUT_MILLIS,
+            )
+        };
+        if self.hwnd == None && wait_start_time.elapsed().as_millis() > timeout_millis {
+            error = Some(match self.ready_file.as_deref() {
+                Some(path) => TrayValetError::ReadyFileTimeout {
+                    path: path.display().to_string(),
+                },
+                None => TrayValetError::WindowNotFound,
+            });
             must_stop_timer = true;
         }
 
-        if has_error {
-            let _ = unsafe {
-                PostMessageW(
-                    self.event_hwnd,
-                    self.error_window_msg_id,
-                    WPARAM(0),
-                    LPARAM(0),
-                )
-            };
+        if let Some(error) = error {
+            self.report_error(error);
         }
 
         if must_stop_timer {
-            let _ = unsafe {
-                KillTimer(
-                    self.event_hwnd,
-                    TimerId::ForeignProcessTreeCheckForNewProcesses as _,
-                )
-            };
+            self.check_for_new_processes_timer = None;
+        }
+
+        true
+    }
+
+    fn check_ready_file(&mut self) -> bool {
+        //! Returns `true` once, when the ready file is noticed to exist.
+
+        if !self.ready_file.as_deref().is_some_and(Path::exists) {
+            return false;
         }
 
+        self.ready_file = None;
+        self.ready_file_time = Some(Instant::now());
+
+        true
+    }
+
+    fn poll_root_process_exit(&mut self) -> bool {
+        //! Returns `true` once, when the spawned process is noticed to have exited.
+
+        let exit_status = match self.root_process.as_mut().map(Child::try_wait) {
+            Some(Ok(Some(exit_status))) => exit_status,
+            _ => return false,
+        };
+
+        self.root_process = None;
+        self.root_process_exit_millis = Some(self.time_waited.elapsed().as_millis());
+        self.root_process_exit_code = exit_status.code();
+
         true
     }
 
+    pub fn wait_for_root_process_exit(&mut self) -> Option<i32> {
+        //! Blocks until the spawned process exited and returns its exit code. `None` if its handle wasn't kept (see `keep_root_process`) or waiting failed.
+
+        if let Some(mut root_process) = self.root_process.take() {
+            self.root_process_exit_code = root_process.wait().ok().and_then(|status| status.code());
+        }
+
+        self.root_process_exit_code
+    }
+
+    fn find_existing_window_on_quick_exit(&self) -> Option<HWND> {
+        //! Searches all processes, if the spawned process exited quickly, which single-instance apps do after handing over to their already running instance.
+
+        let is_quick = self.root_process_exit_millis? <= SINGLE_INSTANCE_EXIT_MILLIS;
+
+        if self.adopt_existing_window && is_quick && self.ready_file.is_none() {
+            Self::find_window_in_any_process(
+                &self.window_classes,
+                self.window_class_ignores_case,
+                self.includes_hidden_windows,
+                self.event_hwnd,
+            )
+        } else {
+            None
+        }
+    }
+
+    fn was_started_after_spawn(&self, process_id: u32) -> bool {
+        //! Prevents adopting an instance that already ran before.
+
+        let h_process =
+            match unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, process_id) } {
+                Ok(h_process) => h_process,
+                Err(_) => return false,
+            };
+
+        let mut creation_time = FILETIME::default();
+        let mut exit_time = FILETIME::default();
+        let mut kernel_time = FILETIME::default();
+        let mut user_time = FILETIME::default();
+        let result = unsafe {
+            GetProcessTimes(
+                h_process,
+                &mut creation_time,
+                &mut exit_time,
+                &mut kernel_time,
+                &mut user_time,
+            )
+        };
+
+        let _ = unsafe { CloseHandle(h_process) };
+
+        result.is_ok() && file_time_to_u64(creation_time) >= self.spawn_file_time
+    }
+
+    fn adopt_window(&mut self, hwnd: HWND) {
+        let mut process_id = 0;
+        let thread_id = unsafe { GetWindowThreadProcessId(hwnd, Some(&mut process_id)) };
+        self.hwnd = Some(hwnd);
+        self.hook_process_thread_id = Some((process_id, thread_id));
+
+        // Since the window is already shown, a synthetic event completes the regular discovery.
+        self.post_synthetic_win_event(EVENT_OBJECT_SHOW, hwnd, thread_id);
+    }
+
+    fn post_synthetic_win_event(&self, event_id: u32, hwnd: HWND, thread_id: u32) {
+        //! Posts a win event for the window itself, as if it came from the hook.
+
+        let boxed_win_event_ptr = Box::into_raw(Box::new(WinEvent {
+            event_id,
+            hwnd,
+            object_id: OBJID_WINDOW.0,
+            child_id: CHILDID_SELF as _,
+            thread_id,
+            time_millis: unsafe { GetTickCount() },
+        }));
+
+        if let Err(_) = unsafe {
+            PostMessageW(
+                self.event_hwnd,
+                self.win_event_window_msg_id,
+                WPARAM(0),
+                LPARAM(boxed_win_event_ptr as _),
+            )
+        } {
+            drop(unsafe { Box::from_raw(boxed_win_event_ptr) });
+        }
+    }
+
+    fn matches_process_name_filter(&self, exe_file: &[u16]) -> bool {
+        match self.process_name_filter.as_ref() {
+            Some(filter) => Self::exe_file_name_matches(exe_file, filter),
+            None => true,
+        }
+    }
+
+    fn exe_file_name_matches(exe_file: &[u16], name: &str) -> bool {
+        //! Compares a zero-terminated `PROCESSENTRY32W::szExeFile` case-insensitively.
+
+        let len = exe_file
+            .iter()
+            .position(|&char| char == 0)
+            .unwrap_or(exe_file.len());
+        String::from_utf16_lossy(&exe_file[..len]).eq_ignore_ascii_case(name)
+    }
+
     fn find_window_in_process(&self, process_id: u32) -> Option<HWND> {
+        if self.ready_file.is_some() {
+            return None;
+        }
+
+        Self::find_window(
+            process_id,
+            &self.window_classes,
+            self.window_class_ignores_case,
+            self.includes_hidden_windows,
+            self.event_hwnd,
+        )
+    }
+
+    fn find_window(
+        process_id: u32,
+        window_classes: &[String],
+        ignore_case: bool,
+        include_hidden: bool,
+        event_hwnd: HWND,
+    ) -> Option<HWND> {
+        Self::find_top_level_window(&mut |hwnd| {
+            Self::is_matching_window(
+                hwnd,
+                process_id,
+                window_classes,
+                ignore_case,
+                include_hidden,
+            ) && !Self::is_claimed_by_other(hwnd, event_hwnd)
+        })
+    }
+
+    fn find_window_in_any_process(
+        window_classes: &[String],
+        ignore_case: bool,
+        include_hidden: bool,
+        event_hwnd: HWND,
+    ) -> Option<HWND> {
+        Self::find_top_level_window(&mut |hwnd| {
+            Self::is_discoverable(hwnd, include_hidden)
+                && Self::window_kind_matches(hwnd, window_classes, ignore_case)
+                && !Self::is_claimed_by_other(hwnd, event_hwnd)
+        })
+    }
+
+    fn is_claimed_by_other(hwnd: HWND, event_hwnd: HWND) -> bool {
+        //! Whether another Tray Valet instance, or another app from the same `--config`, adopted the window. Claims whose event window doesn't exist anymore, e.g., because the instance crashed, don't count.
+
+        let claiming_hwnd = HWND(unsafe { GetPropW(hwnd, CLAIM_PROP_NAME) }.0);
+
+        claiming_hwnd.0 != 0
+            && claiming_hwnd != event_hwnd
+            && unsafe { IsWindow(claiming_hwnd) }.as_bool()
+    }
+
+    fn claim_window(&self, hwnd: HWND) {
+        //! Best effort, since UIPI prevents it for windows of elevated processes.
+
+        let _ = unsafe { SetPropW(hwnd, CLAIM_PROP_NAME, HANDLE(self.event_hwnd.0)) };
+    }
+
+    fn release_window_claim(&self, hwnd: HWND) {
+        if !Self::is_claimed_by_other(hwnd, self.event_hwnd) {
+            let _ = unsafe { RemovePropW(hwnd, CLAIM_PROP_NAME) };
+        }
+    }
+
+    fn find_top_level_window(predicate: &mut dyn FnMut(HWND) -> bool) -> Option<HWND> {
         let mut hwnd = None;
-        let mut exchange_tuple = (self, process_id, &mut hwnd);
+        let mut exchange_tuple = (predicate, &mut hwnd);
         let _ = unsafe {
             EnumWindows(
                 Some(Self::enum_windows_callback),
@@ -230,18 +951,29 @@ impl ForeignProcessTree {
         hwnd
     }
 
-    extern "system" fn enum_windows_callback(top_level_hwnd: HWND, lparam: LPARAM) -> BOOL {
-        let (this, required_process_id, out_hwnd) =
-            unsafe { &mut *(lparam.0 as *mut (&Self, u32, &mut Option<HWND>)) };
+    fn find_descendant_window(
+        parent_hwnd: HWND,
+        predicate: &mut dyn FnMut(HWND) -> bool,
+    ) -> Option<HWND> {
+        let mut hwnd = None;
+        let mut exchange_tuple = (predicate, &mut hwnd);
+        let _ = unsafe {
+            EnumChildWindows(
+                parent_hwnd,
+                Some(Self::enum_windows_callback),
+                LPARAM(&mut exchange_tuple as *mut _ as _),
+            )
+        };
+
+        hwnd
+    }
 
-        let mut process_id = 0;
-        unsafe { GetWindowThreadProcessId(top_level_hwnd, Some(&mut process_id)) };
+    extern "system" fn enum_windows_callback(hwnd: HWND, lparam: LPARAM) -> BOOL {
+        let (predicate, out_hwnd) =
+            unsafe { &mut *(lparam.0 as *mut (&mut dyn FnMut(HWND) -> bool, &mut Option<HWND>)) };
 
-        if process_id == *required_process_id
-            && unsafe { IsWindowVisible(top_level_hwnd).as_bool() }
-            && this.verify_window_class(top_level_hwnd)
-        {
-            **out_hwnd = Some(top_level_hwnd);
+        if predicate(hwnd) {
+            **out_hwnd = Some(hwnd);
 
             // Stop enumeration.
             false.into()
@@ -251,15 +983,86 @@ impl ForeignProcessTree {
         }
     }
 
-    fn verify_window_class(&self, hwnd: HWND) -> bool {
-        let mut buffer = vec![0; 256];
-        let len = unsafe { GetClassNameW(hwnd, &mut buffer) } as usize;
-        if len != 0 {
-            let class_name = String::from_utf16_lossy(&buffer[..len]);
-            class_name == self.window_class
+    fn is_matching_window(
+        hwnd: HWND,
+        required_process_id: u32,
+        window_classes: &[String],
+        ignore_case: bool,
+        include_hidden: bool,
+    ) -> bool {
+        let mut process_id = 0;
+        unsafe { GetWindowThreadProcessId(hwnd, Some(&mut process_id)) };
+
+        process_id == required_process_id
+            && Self::is_discoverable(hwnd, include_hidden)
+            && Self::window_kind_matches(hwnd, window_classes, ignore_case)
+    }
+
+    fn is_discoverable(hwnd: HWND, include_hidden: bool) -> bool {
+        //! Hidden windows only count as main windows, also when matching by class, since apps create lots of hidden helper windows, like tool windows or ones for receiving messages.
+
+        unsafe { IsWindowVisible(hwnd).as_bool() }
+        || include_hidden && Self::is_main_window(hwnd)
+    }
+
+    fn verify_window_kind(&self, hwnd: HWND) -> bool {
+        Self::window_kind_matches(hwnd, &self.window_classes, self.window_class_ignores_case)
+    }
+
+    fn window_kind_matches(hwnd: HWND, window_classes: &[String], ignore_case: bool) -> bool {
+        //! Without window classes, only checks whether the window looks like a main window. Doesn't check visibility, since the hook path also uses this before the window is shown.
+
+        if window_classes.is_empty() {
+            Self::is_main_window(hwnd)
         } else {
-            false
+            Self::window_class_matches(hwnd, window_classes, ignore_case)
+        }
+    }
+
+    fn could_become_window_kind(&self, hwnd: HWND) -> bool {
+        //! Like `verify_window_kind()`, but for a window that was just created and may not have its title yet. (The title is then checked on `EVENT_OBJECT_SHOW`.)
+
+        if self.window_classes.is_empty() {
+            Self::has_main_window_style(hwnd)
+        } else {
+            Self::window_class_matches(hwnd, &self.window_classes, self.window_class_ignores_case)
+        }
+    }
+
+    fn is_main_window(hwnd: HWND) -> bool {
+        //! A top-level window with a title bar and title that isn't a tool window. The title bar excludes most splash screens.
+
+        Self::has_main_window_style(hwnd) && unsafe { GetWindowTextLengthW(hwnd) } > 0
+    }
+
+    fn has_main_window_style(hwnd: HWND) -> bool {
+        let is_top_level = unsafe { GetAncestor(hwnd, GA_ROOT) } == hwnd;
+        let style = unsafe { GetWindowLongPtrW(hwnd, GWL_STYLE) };
+        let ex_style = unsafe { GetWindowLongPtrW(hwnd, GWL_EXSTYLE) };
+
+        is_top_level
+            && style & WS_CAPTION.0 as isize == WS_CAPTION.0 as isize
+            && ex_style & WS_EX_TOOLWINDOW.0 as isize == 0
+    }
+
+    fn window_class_matches(hwnd: HWND, window_classes: &[String], ignore_case: bool) -> bool {
+        //! Whether the window has any of the classes. Compares exactly, unless `ignore_case` is set, although the system treats class names case-insensitively.
+
+        // Class names are limited to 256 characters.
+        let mut buffer = vec![0; 257];
+        let len = unsafe { GetClassNameW(hwnd, &mut buffer) } as usize;
+        if len == 0 {
+            return false;
         }
+
+        let class_name = String::from_utf16_lossy(&buffer[..len]);
+        window_classes.iter().any(|window_class| {
+            if ignore_case {
+                class_name.to_lowercase() == window_class.to_lowercase()
+            } else {
+                class_name == *window_class
+            }
+        })
     }
 
     pub fn translate_win_event(
@@ -268,6 +1071,22 @@ impl ForeignProcessTree {
         lparam: LPARAM,
     ) -> Option<ForeignWindowEvent> {
         let win_event = unsafe { *Box::from_raw(lparam.0 as *mut WinEvent) };
+        if self.released {
+            return Some(ForeignWindowEvent::Internal);
+        }
+
+        self.max_event_lag_millis = self.max_event_lag_millis.max(win_event.lag_millis());
+
+        // (From `foreground_hook`, for any window.)
+        if win_event.event_id == EVENT_SYSTEM_FOREGROUND {
+            return Some(if self.hwnd.is_some() {
+                ForeignWindowEvent::ForegroundChanged {
+                    is_ours: self.is_window_or_owned(win_event.hwnd),
+                }
+            } else {
+                ForeignWindowEvent::Internal
+            });
+        }
 
         match self.hwnd {
             // When `conhost.exe` is run with the parameter `powershell.exe`, `GetWindowThreadProcessId()` reports `conhost.exe` as the owning process on `EVENT_OBJECT_CREATE`. But starting with `EVENT_OBJECT_SHOW` at the latest, `powershell.exe` is reported as the owning process (which is also the information you see in spy tools). However, when using the process and thread ID from `GetWindowThreadProcessId()` on `EVENT_OBJECT_SHOW` for `SetWinEventHook()`, `GetLastError()` after `SetWinEventHook()` reports `ERROR_INVALID_THREAD_ID`. `EVENT_OBJECT_SHOW` is even sent with command `conhost powershell -WindowStyle Hidden`, because the window briefly appears. (`conhost.exe` may possibly use `ConsoleControl()` to change the window owner.)
@@ -275,7 +1094,10 @@ impl ForeignProcessTree {
                 && win_event.object_id == OBJID_WINDOW.0
                 && win_event.child_id == CHILDID_SELF as _ =>
             {
-                if self.verify_window_class(win_event.hwnd) {
+                if self.ready_file.is_none()
+                    && self.could_become_window_kind(win_event.hwnd)
+                    && !Self::is_claimed_by_other(win_event.hwnd, self.event_hwnd)
+                {
                     let mut process_id = 0;
                     let thread_id =
                         unsafe { GetWindowThreadProcessId(win_event.hwnd, Some(&mut process_id)) };
@@ -291,41 +1113,89 @@ impl ForeignProcessTree {
                 match win_event.event_id {
                     EVENT_OBJECT_SHOW
                         if win_event.object_id == OBJID_WINDOW.0
-                            && win_event.child_id == CHILDID_SELF as _ =>
+                            && win_event.child_id == CHILDID_SELF as _
+                            && !self.hwnd_monitored =>
                     {
+                        // (Without a title by now, it's no main window after all.)
+                        if !self.verify_window_kind(hwnd) {
+                            self.hwnd = None;
+                            self.hook_process_thread_id = None;
+                            return Some(ForeignWindowEvent::Internal);
+                        }
+
+                        // Before the setup, which takes a moment, to keep the window from flashing.
+                        if self.hides_on_discovery {
+                            self.intended_visible = false;
+                            Self::set_hwnd_visible(hwnd, false);
+                            self.discovery_hide_time = Some(Instant::now());
+                        }
+
                         let return_value = match self.init_hwnd_monitoring() {
-                            Ok(()) => Some(ForeignWindowEvent::Found),
-                            Err(_) => {
-                                let _ = unsafe {
-                                    PostMessageW(
-                                        self.event_hwnd,
-                                        self.error_window_msg_id,
-                                        WPARAM(0),
-                                        LPARAM(0),
-                                    )
-                                };
+                            Ok(()) => {
+                                self.clamp_window_size();
+                                Some(ForeignWindowEvent::Found)
+                            }
+                            Err(error) => {
+                                self.report_error(TrayValetError::HookFailed(error));
 
                                 Some(ForeignWindowEvent::Internal)
                             }
                         };
 
-                        let _ = unsafe {
-                            KillTimer(
-                                self.event_hwnd,
-                                TimerId::ForeignProcessTreeCheckForNewProcesses as _,
-                            )
-                        };
+                        self.check_for_new_processes_timer = None;
 
                         return_value
                     }
-                    // Start of time of being minimized, not start of minimization animation.
-                    EVENT_SYSTEM_MINIMIZESTART => Some(ForeignWindowEvent::Minimized),
-                    EVENT_OBJECT_NAMECHANGE
+                    // Once monitored, only with `keeps_hidden` or via `follow_hook`.
+                    EVENT_OBJECT_SHOW
                         if win_event.object_id == OBJID_WINDOW.0
                             && win_event.child_id == CHILDID_SELF as _ =>
                     {
-                        Some(ForeignWindowEvent::TitleChanged)
+                        self.rehide_window();
+                        Some(ForeignWindowEvent::Internal)
                     }
+                    // Start of time of being minimized, not start of minimization animation.
+                    EVENT_SYSTEM_MINIMIZESTART => Some(ForeignWindowEvent::Minimized),
+                    // Also sent when the window is restored by other means than this app.
+                    EVENT_SYSTEM_MINIMIZEEND => {
+                        if self.rehide_window() {
+                            Some(ForeignWindowEvent::Internal)
+                        } else {
+                            Some(ForeignWindowEvent::Restored)
+                        }
+                    }
+                    EVENT_OBJECT_NAMECHANGE
+                        if win_event.object_id == OBJID_WINDOW.0
+                            && win_event.child_id == CHILDID_SELF as _ =>
+                    {
+                        Some(ForeignWindowEvent::TitleChanged)
+                    }
+                    EVENT_OBJECT_LOCATIONCHANGE
+                        if win_event.object_id == OBJID_WINDOW.0
+                            && win_event.child_id == CHILDID_SELF as _ =>
+                    {
+                        // (Resizing causes the event again, but then the size is within the limits.)
+                        if self
+                            .size_limits
+                            .is_some_and(|limits| limits.reapply_on_resize)
+                        {
+                            self.clamp_window_size();
+                        }
+
+                        // Restart timer.
+                        Timer::replace(
+                            &mut self.move_settled_timer,
+                            self.event_hwnd,
+                            TimerId::ForeignProcessTreeMoveSettled as _,
+                            MOVE_SETTLE_MILLIS,
+                        );
+
+                        Some(ForeignWindowEvent::Internal)
+                    }
+                    EVENT_SYSTEM_MOVESIZESTART => Some(ForeignWindowEvent::MoveSizeStarted),
+                    EVENT_SYSTEM_MOVESIZEEND => Some(ForeignWindowEvent::MoveSizeEnded),
+                    // Only synthetic, after the location stopped changing.
+                    EVENT_MOVE_SETTLED => Some(ForeignWindowEvent::Moved),
                     EVENT_OBJECT_DESTROY
                         if win_event.object_id == OBJID_WINDOW.0
                             && win_event.child_id == CHILDID_SELF as _ =>
@@ -335,10 +1205,126 @@ impl ForeignProcessTree {
                     _ => None,
                 }
             }
+            Some(_)
+                if win_event.event_id == EVENT_OBJECT_NAMECHANGE
+                    && win_event.object_id == OBJID_WINDOW.0
+                    && win_event.child_id == CHILDID_SELF as _
+                    && self.is_title_child(win_event.hwnd) =>
+            {
+                Some(ForeignWindowEvent::TitleChanged)
+            }
+            Some(hwnd)
+                if win_event.event_id == EVENT_OBJECT_SHOW
+                    && win_event.object_id == OBJID_WINDOW.0
+                    && win_event.child_id == CHILDID_SELF as _
+                    && self.follow_hook.is_some()
+                    && Self::is_shell_window(hwnd)
+                    && self.is_followable_window(win_event.hwnd) =>
+            {
+                Some(self.follow_window(win_event.hwnd))
+            }
             _ => None,
         }
     }
 
+    fn is_shell_window(hwnd: HWND) -> bool {
+        //! A window without title bar or without size.
+
+        let style = unsafe { GetWindowLongPtrW(hwnd, GWL_STYLE) };
+        let mut rect = RECT::default();
+        let has_size = unsafe { GetWindowRect(hwnd, &mut rect) }.is_ok()
+            && rect.right > rect.left
+            && rect.bottom > rect.top;
+
+        style & WS_CAPTION.0 as isize != WS_CAPTION.0 as isize || !has_size
+    }
+
+    fn is_followable_window(&self, hwnd: HWND) -> bool {
+        //! Owned windows are excluded, since they're dialogs and the like.
+
+        self.verify_window_kind(hwnd)
+            && unsafe { GetWindow(hwnd, GW_OWNER) } == HWND(0)
+            && !Self::is_shell_window(hwnd)
+            && !Self::is_claimed_by_other(hwnd, self.event_hwnd)
+    }
+
+    fn follow_window(&mut self, new_hwnd: HWND) -> ForeignWindowEvent {
+        //! Moves tracking to the new window after undoing the changes to the current one.
+
+        let mut process_id = 0;
+        let thread_id = unsafe { GetWindowThreadProcessId(new_hwnd, Some(&mut process_id)) };
+        if thread_id == 0 {
+            return ForeignWindowEvent::Internal;
+        }
+
+        self.restore_window_style();
+        self.restore_window_ex_style();
+        self.restore_icon();
+        self.restore_window_title();
+        self.restore_window_size();
+        self.restore_window_position();
+        if let Some(hwnd) = self.hwnd {
+            self.release_window_claim(hwnd);
+        }
+
+        self.hwnd = Some(new_hwnd);
+        self.hook_process_thread_id = Some((process_id, thread_id));
+
+        match self.init_hwnd_monitoring() {
+            Ok(()) => {
+                self.clamp_window_size();
+                ForeignWindowEvent::Found
+            }
+            Err(error) => {
+                self.report_error(TrayValetError::HookFailed(error));
+
+                ForeignWindowEvent::Internal
+            }
+        }
+    }
+
+    fn check_hwnd_monitoring(&mut self) {
+        //! Verifies that the hooks are still attached to the window's thread, since out-of-context hooks can silently stop delivering, e.g., if the window is recreated by another thread.
+
+        let (hwnd, hook_process_thread_id) = if let (Some(hwnd), Some(hook_process_thread_id)) =
+            (self.hwnd, self.hook_process_thread_id)
+        {
+            (hwnd, hook_process_thread_id)
+        } else {
+            return;
+        };
+
+        let mut process_id = 0;
+        let thread_id = if unsafe { IsWindow(hwnd) }.as_bool() {
+            unsafe { GetWindowThreadProcessId(hwnd, Some(&mut process_id)) }
+        } else {
+            0
+        };
+
+        if thread_id == 0 {
+            // (The destruction event was missed.)
+            self.watchdog_timer = None;
+            self.post_synthetic_win_event(EVENT_OBJECT_DESTROY, hwnd, hook_process_thread_id.1);
+        } else if (process_id, thread_id) != hook_process_thread_id {
+            self.hook_process_thread_id = Some((process_id, thread_id));
+
+            if let Err(error) = self.init_hwnd_monitoring() {
+                self.report_error(TrayValetError::HookFailed(error));
+            }
+        }
+    }
+
+    fn start_watchdog(&mut self) {
+        if let Some(millis) = self.watchdog_millis {
+            Timer::replace(
+                &mut self.watchdog_timer,
+                self.event_hwnd,
+                TimerId::ForeignProcessTreeWatchdog as _,
+                millis,
+            );
+        }
+    }
+
     fn init_hwnd_monitoring(&mut self) -> Result<(), windows::core::Error> {
         let (foreign_hwnd, (hook_process_id, hook_thread_id)) =
             if let (Some(hwnd), Some(hook_process_thread_id)) =
@@ -349,6 +1335,8 @@ impl ForeignProcessTree {
                 return Err(E_FAIL.into());
             };
 
+        self.claim_window(foreign_hwnd);
+
         // Set up win event hook.
         self.win_event_hook = unsafe {
             WinEventHook::new(
@@ -357,13 +1345,53 @@ impl ForeignProcessTree {
                 self.win_event_window_msg_id,
             )
         };
-        self.win_event_hook.add_event(EVENT_SYSTEM_MINIMIZESTART)?;
+        self.win_event_hook
+            .add_event_range(EVENT_SYSTEM_MINIMIZESTART, EVENT_SYSTEM_MINIMIZEEND)?;
         self.win_event_hook.add_event(EVENT_OBJECT_NAMECHANGE)?;
         self.win_event_hook.add_event(EVENT_OBJECT_DESTROY)?;
+        // Frequent while dragging; debounced to `ForeignWindowEvent::Moved`. (Only registered when needed, since every window movement would otherwise wake this process.)
+        if self.tracks_moves() {
+            self.win_event_hook.add_event(EVENT_OBJECT_LOCATIONCHANGE)?;
+        }
+        // Only for moving and sizing by the user. (Not sent for programmatic moves.)
+        self.win_event_hook
+            .add_event_range(EVENT_SYSTEM_MOVESIZESTART, EVENT_SYSTEM_MOVESIZEEND)?;
+        if self.keeps_hidden || self.hides_on_discovery {
+            self.win_event_hook.add_event(EVENT_OBJECT_SHOW)?;
+        }
 
-        // Find .exe path.
+        if self.follows_main_window {
+            let mut follow_hook = unsafe {
+                WinEventHook::new(
+                    ProcessThreadSet::Process(hook_process_id),
+                    self.event_hwnd,
+                    self.win_event_window_msg_id,
+                )
+            };
+            follow_hook.add_event(EVENT_OBJECT_SHOW)?;
+            self.follow_hook = Some(follow_hook);
+        }
+        self.start_watchdog();
+        self.hwnd_monitored = true;
+
+        // Find .exe path. (Not fatal, since it's only needed for the icon, and can fail for protected processes.)
+        self.window_exe_path = Self::query_exe_path(foreign_hwnd).ok();
+
+        Ok(())
+    }
+
+    fn tracks_moves(&self) -> bool {
+        //! Whether an option needs to know about location changes of the window.
+
+        self.reports_moves
+            || self
+                .size_limits
+                .is_some_and(|limits| limits.reapply_on_resize)
+    }
+
+    pub fn query_exe_path(hwnd: HWND) -> Result<PathBuf, windows::core::Error> {
         let mut window_process_id = 0;
-        unsafe { GetWindowThreadProcessId(foreign_hwnd, Some(&mut window_process_id)) };
+        unsafe { GetWindowThreadProcessId(hwnd, Some(&mut window_process_id)) };
 
         let h_process =
             unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, true, window_process_id)? };
@@ -397,34 +1425,55 @@ impl ForeignProcessTree {
             return Err(error);
         }
 
-        self.window_exe_path =
-            Some(OsString::from_wide(&buffer[..buffer_len_then_string_len as usize]).into());
-
-        Ok(())
+        Ok(OsString::from_wide(&buffer[..buffer_len_then_string_len as usize]).into())
     }
 
     pub fn set_icon(&mut self, small_hicon: HICON, large_hicon: HICON) {
+        //! The original icons are restored on drop.
+
         if let Some(hwnd) = self.hwnd {
-            for (size, hicon) in [(ICON_SMALL, small_hicon), (ICON_BIG, large_hicon)] {
-                let _ =
-                    unsafe { PostMessageW(hwnd, WM_SETICON, WPARAM(size as _), LPARAM(hicon.0)) };
+            if self.original_hicons.is_none() {
+                let [original_small_hicon, original_large_hicon] =
+                    [ICON_SMALL, ICON_BIG].map(|size| {
+                        let mut hicon = 0;
+                        let _ = unsafe {
+                            SendMessageTimeoutW(
+                                hwnd,
+                                WM_GETICON,
+                                WPARAM(size as _),
+                                LPARAM(0),
+                                SMTO_ABORTIFHUNG,
+                                1000, /*ms*/
+                                Some(&mut hicon),
+                            )
+                        };
+                        HICON(hicon as _)
+                    });
+
+                self.original_hicons = Some((original_small_hicon, original_large_hicon));
             }
+
+            Self::post_icon(hwnd, small_hicon, large_hicon);
         }
     }
 
-    pub fn window_visible(&self) -> bool {
-        if let Some(hwnd) = self.hwnd {
-            unsafe { IsWindowVisible(hwnd).as_bool() }
-        } else {
-            false
+    fn restore_icon(&mut self) {
+        if let (Some(hwnd), Some((small_hicon, large_hicon))) =
+            (self.hwnd, self.original_hicons.take())
+        {
+            // (Zero icons make the window fall back to its class icons.)
+            Self::post_icon(hwnd, small_hicon, large_hicon);
         }
     }
 
-    pub fn set_window_visible(&mut self, new_visible: bool) {
-        let currently_visible = self.window_visible();
-        if new_visible == currently_visible {
-            return;
+    fn post_icon(hwnd: HWND, small_hicon: HICON, large_hicon: HICON) {
+        for (size, hicon) in [(ICON_SMALL, small_hicon), (ICON_BIG, large_hicon)] {
+            let _ = unsafe { PostMessageW(hwnd, WM_SETICON, WPARAM(size as _), LPARAM(hicon.0)) };
         }
+    }
+
+    pub fn remove_window_style(&mut self, style: WINDOW_STYLE) {
+        //! Best effort, since some apps reassert their window style. The removed bits are restored on drop.
 
         let hwnd = if let Some(hwnd) = self.hwnd {
             hwnd
@@ -432,93 +1481,1406 @@ impl ForeignProcessTree {
             return;
         };
 
-        let show_cmd = if currently_visible {
-            SW_HIDE
-        } else {
-            let mut window_placement = WINDOWPLACEMENT::default();
-            window_placement.length = size_of::<WINDOWPLACEMENT>() as _;
-            let _ = unsafe { GetWindowPlacement(hwnd, &mut window_placement) };
+        let current_style = unsafe { GetWindowLongPtrW(hwnd, GWL_STYLE) };
+        *self.removed_window_style.get_or_insert(0) |= current_style & style.0 as isize;
 
-            let is_minimized = window_placement.showCmd == SW_SHOWMINIMIZED.0 as _;
-            if is_minimized {
-                let was_maximized = (window_placement.flags & WPF_RESTORETOMAXIMIZED).0 != 0;
-                if was_maximized {
-                    SW_SHOWMAXIMIZED
-                } else {
-                    //TODO: SOMETIME: Report Windows 10 bug: After `SW_RESTORE` and `SW_SHOWNORMAL`, a previously invisible - but not minimized - arranged window is clearly not arranged anymore, but visually in a *restored* state, while `IsWindowArranged()` erroneously continues to return `TRUE` (at least after hiding and showing a few times). Only after moving the window just a tiny bit, `IsWindowArranged()` returns `FALSE`. The documentation of both of the flags as well as the remarks on `IsWindowArranged()` also object to the experienced behavior. (This code avoids the bug by using `SW_SHOW` instead of `SW_RESTORE` for unminimized windows.)
-                    SW_RESTORE
-                }
-            } else {
-                // As opposed to `SW_RESTORE`, prevents a not minimized window in arranged state from becoming not arranged anymore. (This branch also runs when `GetWindowPlacement()` fails.)
-                SW_SHOW
-            }
-        };
+        unsafe { SetWindowLongPtrW(hwnd, GWL_STYLE, current_style & !(style.0 as isize)) };
+        Self::apply_frame_change(hwnd);
+    }
 
-        unsafe {
-            ShowWindow(hwnd, show_cmd);
-            SetForegroundWindow(hwnd);
+    fn restore_window_style(&mut self) {
+        //! Only sets the removed bits again, since others like `WS_VISIBLE` may have changed meanwhile.
+
+        if let (Some(hwnd), Some(removed_style)) = (self.hwnd, self.removed_window_style.take()) {
+            let current_style = unsafe { GetWindowLongPtrW(hwnd, GWL_STYLE) };
+            unsafe { SetWindowLongPtrW(hwnd, GWL_STYLE, current_style | removed_style) };
+            Self::apply_frame_change(hwnd);
         }
     }
 
-    pub fn toggle_window_visible(&mut self) {
-        let visible = self.window_visible();
-        self.set_window_visible(!visible);
+    pub fn change_window_ex_style(
+        &mut self,
+        added_ex_style: WINDOW_EX_STYLE,
+        removed_ex_style: WINDOW_EX_STYLE,
+    ) {
+        //! Best effort like `remove_window_style()`. The original extended style is restored on drop.
+
+        let hwnd = if let Some(hwnd) = self.hwnd {
+            hwnd
+        } else {
+            return;
+        };
+
+        let current_ex_style = unsafe { GetWindowLongPtrW(hwnd, GWL_EXSTYLE) };
+        if self.original_window_ex_style.is_none() {
+            self.original_window_ex_style = Some(current_ex_style);
+        }
+
+        Self::set_ex_style_while_hidden(
+            hwnd,
+            current_ex_style & !(removed_ex_style.0 as isize) | added_ex_style.0 as isize,
+        );
     }
 
-    pub fn window_exe_path(&self) -> Option<PathBuf> {
-        self.window_exe_path.clone()
+    fn restore_window_ex_style(&mut self) {
+        if let (Some(hwnd), Some(ex_style)) = (self.hwnd, self.original_window_ex_style.take()) {
+            Self::set_ex_style_while_hidden(hwnd, ex_style);
+        }
     }
 
-    pub fn window_title(&self) -> Result<String, windows::core::Error> {
+    pub fn set_window_corners(&mut self, corners: WindowCorners) {
+        //! Does nothing before Windows 11, which doesn't know the attribute. The default preference is restored on drop, since the original one can't be queried.
+
         let hwnd = if let Some(hwnd) = self.hwnd {
             hwnd
         } else {
-            return Err(ERROR_INVALID_WINDOW_HANDLE.into());
+            return;
         };
 
-        unsafe { SetLastError(WIN32_ERROR(0)) };
-        let len = unsafe { GetWindowTextLengthW(hwnd) } as usize;
-        if len == 0 {
-            let error = windows::core::Error::from_win32();
-            return if error.code() == S_OK {
-                Ok("".to_string())
-            } else {
-                Err(error)
-            };
+        let preference = match corners {
+            WindowCorners::Default => DWMWCP_DEFAULT,
+            WindowCorners::Round => DWMWCP_ROUND,
+            WindowCorners::RoundSmall => DWMWCP_ROUNDSMALL,
+            WindowCorners::Square => DWMWCP_DONOTROUND,
+        };
+
+        if Self::set_corner_preference(hwnd, preference).is_ok() {
+            self.window_corners_changed = true;
         }
+    }
 
-        let mut buffer = vec![0; len + 1];
-        let copied_len = unsafe { GetWindowTextW(hwnd, &mut buffer) } as usize;
-        if copied_len == len {
-            Ok(String::from_utf16_lossy(&buffer[..len]))
-        } else {
-            Err(windows::core::Error::from_win32())
+    fn restore_window_corners(&mut self) {
+        if let Some(hwnd) = self.hwnd {
+            if mem::take(&mut self.window_corners_changed) {
+                let _ = Self::set_corner_preference(hwnd, DWMWCP_DEFAULT);
+            }
+        }
+    }
+
+    fn set_corner_preference(
+        hwnd: HWND,
+        preference: DWM_WINDOW_CORNER_PREFERENCE,
+    ) -> Result<(), windows::core::Error> {
+        unsafe {
+            DwmSetWindowAttribute(
+                hwnd,
+                DWMWA_WINDOW_CORNER_PREFERENCE,
+                &preference as *const _ as _,
+                size_of::<DWM_WINDOW_CORNER_PREFERENCE>() as _,
+            )
+        }
+    }
+
+    fn set_ex_style_while_hidden(hwnd: HWND, ex_style: isize) {
+        // The taskbar only notices changes to `WS_EX_TOOLWINDOW` and `WS_EX_APPWINDOW` when the window is shown.
+        let was_visible = unsafe { IsWindowVisible(hwnd).as_bool() };
+        if was_visible {
+            unsafe { ShowWindow(hwnd, SW_HIDE) };
+        }
+
+        unsafe { SetWindowLongPtrW(hwnd, GWL_EXSTYLE, ex_style) };
+        Self::apply_frame_change(hwnd);
+
+        if was_visible {
+            unsafe { ShowWindow(hwnd, SW_SHOWNA) };
         }
     }
 
-    pub fn close_window(&mut self) {
+    fn apply_frame_change(hwnd: HWND) {
+        // Style changes only take effect after this call.
+        let _ = unsafe {
+            SetWindowPos(
+                hwnd,
+                HWND(0),
+                0,
+                0,
+                0,
+                0,
+                SWP_FRAMECHANGED | SWP_NOMOVE | SWP_NOSIZE | SWP_NOZORDER | SWP_NOACTIVATE,
+            )
+        };
+    }
+
+    pub fn window_closed(&self) -> bool {
+        //! Whether the window was found and doesn't exist anymore.
+
+        self.hwnd
+            .is_some_and(|hwnd| !unsafe { IsWindow(hwnd) }.as_bool())
+    }
+
+    pub fn window_visible(&self) -> bool {
         if let Some(hwnd) = self.hwnd {
-            let _ = unsafe { PostMessageW(hwnd, WM_CLOSE, WPARAM(0), LPARAM(0)) };
+            unsafe { IsWindowVisible(hwnd).as_bool() }
+        } else {
+            false
         }
     }
-}
 
-impl Drop for ForeignProcessTree {
-    fn drop(&mut self) {
-        self.set_window_visible(true);
+    pub fn set_restore_animation_suppressed(&mut self, suppressed: bool) {
+        //! Makes showing a minimized window skip the animation from the taskbar, for snappier toggling. (Hiding is never animated.) Since the animation setting is system-wide, it's only disabled for the moment of showing.
+
+        self.suppresses_restore_animation = suppressed;
+    }
+
+    pub fn set_foreground_tracked(&mut self, tracked: bool) -> Result<(), windows::core::Error> {
+        //! Makes every change of the foreground window in the system lead to `ForeignWindowEvent::ForegroundChanged`, once the window is found.
+
+        self.foreground_hook = if tracked {
+            let mut hook = unsafe {
+                WinEventHook::new(
+                    ProcessThreadSet::All,
+                    self.event_hwnd,
+                    self.win_event_window_msg_id,
+                )
+            };
+            hook.add_event(EVENT_SYSTEM_FOREGROUND)?;
+
+            Some(hook)
+        } else {
+            None
+        };
+
+        Ok(())
+    }
+
+    pub fn set_main_window_followed(&mut self, followed: bool) {
+        //! Makes tracking move to another main window of the same process, if the found window turns out to be a mere shell without title bar or size, which some apps show before their actual main window. The new window then leads to `ForeignWindowEvent::Found` again. Must be set before the window is found.
+
+        self.follows_main_window = followed;
+    }
+
+    pub fn set_kept_hidden(&mut self, kept_hidden: bool) {
+        //! Makes the window be hidden again whenever it's shown or restored by other means than `set_window_visible()`, like by the app itself. Since win events arrive asynchronously, the visibility last requested via `set_window_visible()` decides, so that this doesn't fight its own showing.
+
+        self.keeps_hidden = kept_hidden;
+    }
+
+    pub fn set_hidden_on_discovery(&mut self, hidden: bool) {
+        //! Makes the window be hidden the moment it's found, before `ForeignWindowEvent::Found`, and again whenever it's shown by other means than `set_window_visible()` during a short grace period after, for apps that show their window briefly or repeatedly while starting. Must be set before the window is found.
+
+        self.hides_on_discovery = hidden;
+    }
+
+    pub fn set_hidden_windows_included(&mut self, included: bool) {
+        //! Makes finding the window also consider hidden windows that look like main windows, for apps that create their main window hidden and only show it on demand. What happens after the window was found is unaffected. Must be set before the window is found.
+
+        self.includes_hidden_windows = included;
+    }
+
+    pub fn set_moves_reported(&mut self, reported: bool) {
+        //! Makes the window's location changes be reported with `ForeignWindowEvent::Moved` once they settled. Must be set before the window is found.
+
+        self.reports_moves = reported;
+    }
+
+    pub fn set_closed_on_drop(&mut self, method: Option<CloseMethod>) {
+        //! Makes dropping close the window with this method after restoring it. Only with `CloseMethod::Terminate`, the window isn't shown again, unless terminating fails; with the other methods, the app may not close, e.g., because the user cancels a confirmation.
+
+        self.close_method_on_drop = method;
+    }
+
+    pub fn set_title_child_class(&mut self, class: Option<String>) {
+        //! Makes `window_title()` and `ForeignWindowEvent::TitleChanged` refer to the first descendant window with this class, while the window is still shown, hidden and monitored as usual. The descendant is searched anew each time, since it may come and go. Without one, the window's own title is used.
+
+        self.title_child_class = class;
+    }
+
+    fn title_hwnd(&self) -> Option<HWND> {
+        let hwnd = self.hwnd?;
+        let class = if let Some(class) = self.title_child_class.as_deref() {
+            class
+        } else {
+            return Some(hwnd);
+        };
+
+        let child_hwnd = Self::find_descendant_window(hwnd, &mut |child_hwnd| {
+            Self::window_class_matches(
+                child_hwnd,
+                slice::from_ref(class),
+                self.window_class_ignores_case,
+            )
+        });
+
+        Some(child_hwnd.unwrap_or(hwnd))
+    }
+
+    fn is_title_child(&self, hwnd: HWND) -> bool {
+        self.title_child_class.is_some()
+            && self
+                .hwnd
+                .is_some_and(|parent_hwnd| unsafe { IsChild(parent_hwnd, hwnd) }.as_bool())
+            && self.title_hwnd() == Some(hwnd)
+    }
+
+    fn rehide_window(&mut self) -> bool {
+        //! With `keeps_hidden` or during the grace period of `hides_on_discovery`, hides the window, if it's not meant to be visible. Returns whether that was the case.
 
-        for hicon in [self.small_hicon, self.large_hicon] {
-            if let Some(hicon) = hicon {
-                let _ = unsafe { DestroyIcon(hicon) };
+        let is_in_discovery_grace = self
+            .discovery_hide_time
+            .is_some_and(|time| time.elapsed().as_millis() < DISCOVERY_HIDE_GRACE_MILLIS);
+
+        match self.hwnd {
+            Some(hwnd)
+                if (self.keeps_hidden || is_in_discovery_grace) && !self.intended_visible =>
+            {
+                Self::set_hwnd_visible(hwnd, false);
+                true
             }
+            _ => false,
         }
     }
-}
 
-pub enum ForeignWindowEvent {
-    Found,
-    Minimized,
-    TitleChanged,
-    Destroyed,
-    Internal,
+    pub fn window_in_foreground(&self) -> bool {
+        //! Also true if the foreground window is owned by the window, like its dialogs.
+
+        self.is_window_or_owned(unsafe { GetForegroundWindow() })
+    }
+
+    fn is_window_or_owned(&self, other_hwnd: HWND) -> bool {
+        self.hwnd.is_some_and(|hwnd| {
+            other_hwnd == hwnd || unsafe { GetAncestor(other_hwnd, GA_ROOTOWNER) } == hwnd
+        })
+    }
+
+    pub fn activate_window(&mut self) {
+        //! Brings the visible window to the foreground without changing anything else.
+
+        if let Some(hwnd) = self.hwnd {
+            let _ = unsafe { SetForegroundWindow(hwnd) };
+        }
+    }
+
+    pub fn set_snap_corner(&mut self, corner: Option<SnapCorner>) {
+        //! Makes showing the window move it into the corner of the work area of the monitor with the taskbar. The original position is restored on drop.
+
+        self.snap_corner = corner;
+    }
+
+    pub fn set_centered_on_show(&mut self, centered: bool) {
+        //! Makes showing the window center it in the work area of the monitor with the mouse cursor. The original position is restored on drop.
+
+        self.centers_on_show = centered;
+    }
+
+    pub fn set_window_visible(&mut self, new_visible: bool) {
+        if let Some(hwnd) = self.hwnd {
+            self.intended_visible = new_visible;
+
+            // (Restored when dropped, also if showing panics.)
+            let _suppression = if self.suppresses_restore_animation
+                && new_visible
+                && unsafe { IsIconic(hwnd) }.as_bool()
+            {
+                MinimizeAnimationSuppression::new()
+            } else {
+                None
+            };
+
+            if !new_visible && unsafe { IsWindowVisible(hwnd) }.as_bool() {
+                self.rect_before_hide = Self::unsnapped_normal_rect(hwnd);
+            }
+
+            Self::set_hwnd_visible(hwnd, new_visible);
+
+            if new_visible {
+                self.pin_window_position();
+                self.clamp_window_size();
+                self.snap_to_corner();
+                self.center_on_cursor_monitor();
+            }
+        }
+    }
+
+    pub fn set_position_pin_tolerance(&mut self, tolerance: Option<u32>) {
+        //! Makes showing via `set_window_visible()` move the window back to where it was hidden, if any of its edges is off by more than `tolerance` pixels, since some apps make their window drift after several hide/show cycles. Minimized, maximized and snapped windows are left alone.
+
+        self.position_pin_tolerance = tolerance;
+    }
+
+    fn pin_window_position(&mut self) {
+        let (hwnd, tolerance, old_rect) = if let (Some(hwnd), Some(tolerance), Some(old_rect)) = (
+            self.hwnd,
+            self.position_pin_tolerance,
+            self.rect_before_hide.take(),
+        ) {
+            (hwnd, tolerance, old_rect)
+        } else {
+            return;
+        };
+
+        // (A rectangle on no monitor anymore, e.g., after one was disconnected, would put the window off-screen.)
+        if unsafe { MonitorFromRect(&old_rect, MONITOR_DEFAULTTONULL) }.is_invalid() {
+            return;
+        }
+
+        if let Some(rect) = Self::unsnapped_normal_rect(hwnd) {
+            if rect_drifted(old_rect, rect, tolerance) {
+                self.set_window_rect(old_rect);
+            }
+        }
+    }
+
+    fn unsnapped_normal_rect(hwnd: HWND) -> Option<RECT> {
+        //! The window's rectangle, unless it's minimized, maximized or snapped to a screen edge.
+
+        if unsafe { IsIconic(hwnd).as_bool() || IsZoomed(hwnd).as_bool() } {
+            return None;
+        }
+
+        let mut rect = RECT::default();
+        unsafe { GetWindowRect(hwnd, &mut rect) }.ok()?;
+
+        // A window snapped to a screen edge has another size than its restored size. (`IsWindowArranged()` isn't reliable; see `set_hwnd_visible()`.)
+        let mut window_placement = WINDOWPLACEMENT::default();
+        window_placement.length = size_of::<WINDOWPLACEMENT>() as _;
+        unsafe { GetWindowPlacement(hwnd, &mut window_placement) }.ok()?;
+        let normal_rect = window_placement.rcNormalPosition;
+        if (rect.right - rect.left, rect.bottom - rect.top)
+            != (
+                normal_rect.right - normal_rect.left,
+                normal_rect.bottom - normal_rect.top,
+            )
+        {
+            return None;
+        }
+
+        Some(rect)
+    }
+
+    fn clamp_window_size(&mut self) {
+        //! Resizes the window into `size_limits`, keeping its top-left corner. Maximized and minimized windows are left alone; a snapped window loses that state, like with `set_window_rect()`.
+
+        let (hwnd, limits) = if let (Some(hwnd), Some(limits)) = (self.hwnd, self.size_limits) {
+            (hwnd, limits)
+        } else {
+            return;
+        };
+
+        if unsafe { IsIconic(hwnd).as_bool() || IsZoomed(hwnd).as_bool() } {
+            return;
+        }
+
+        let mut rect = RECT::default();
+        if let Err(_) = unsafe { GetWindowRect(hwnd, &mut rect) } {
+            return;
+        }
+
+        let size = (rect.right - rect.left, rect.bottom - rect.top);
+        let mut new_size = size;
+        if let Some((max_width, max_height)) = limits.max {
+            new_size = (new_size.0.min(max_width), new_size.1.min(max_height));
+        }
+        if let Some((min_width, min_height)) = limits.min {
+            new_size = (new_size.0.max(min_width), new_size.1.max(min_height));
+        }
+
+        if new_size == size {
+            return;
+        }
+
+        if self.original_window_size.is_none() {
+            self.original_window_size = Some(size);
+        }
+
+        Self::resize_hwnd(hwnd, new_size);
+    }
+
+    fn restore_window_size(&mut self) {
+        if let (Some(hwnd), Some(size)) = (self.hwnd, self.original_window_size.take()) {
+            if unsafe { !IsIconic(hwnd).as_bool() && !IsZoomed(hwnd).as_bool() } {
+                Self::resize_hwnd(hwnd, size);
+            }
+        }
+    }
+
+    fn snap_to_corner(&mut self) {
+        //! Moves the window into `snap_corner`, keeping its size. Maximized and minimized windows are left alone.
+
+        let (hwnd, corner) = if let (Some(hwnd), Some(corner)) = (self.hwnd, self.snap_corner) {
+            (hwnd, corner)
+        } else {
+            return;
+        };
+
+        if unsafe { IsIconic(hwnd).as_bool() || IsZoomed(hwnd).as_bool() } {
+            return;
+        }
+
+        let mut monitor_info = MONITORINFO {
+            cbSize: size_of::<MONITORINFO>() as _,
+            ..Default::default()
+        };
+        if !unsafe { GetMonitorInfoW(get_tray_monitor(), &mut monitor_info) }.as_bool() {
+            return;
+        }
+        let work_rect = monitor_info.rcWork;
+
+        let mut rect = RECT::default();
+        if let Err(_) = unsafe { GetWindowRect(hwnd, &mut rect) } {
+            return;
+        }
+
+        let frame_rect = Self::visible_frame_rect(hwnd, rect);
+
+        let margin = SNAP_MARGIN * unsafe { GetDpiForWindow(hwnd) } as i32 / 96;
+        let x = match corner {
+            SnapCorner::TopLeft | SnapCorner::BottomLeft => {
+                work_rect.left + margin - (frame_rect.left - rect.left)
+            }
+            SnapCorner::TopRight | SnapCorner::BottomRight => {
+                work_rect.right - margin - (frame_rect.right - rect.left)
+            }
+        };
+        let y = match corner {
+            SnapCorner::TopLeft | SnapCorner::TopRight => {
+                work_rect.top + margin - (frame_rect.top - rect.top)
+            }
+            SnapCorner::BottomLeft | SnapCorner::BottomRight => {
+                work_rect.bottom - margin - (frame_rect.bottom - rect.top)
+            }
+        };
+
+        if (x, y) == (rect.left, rect.top) {
+            return;
+        }
+
+        if self.original_window_position.is_none() {
+            self.original_window_position = Some((rect.left, rect.top));
+        }
+
+        Self::move_hwnd(hwnd, (x, y));
+    }
+
+    fn center_on_cursor_monitor(&mut self) {
+        //! Moves the window into the center of the work area of the monitor with the mouse cursor, keeping its size. Maximized, minimized and snapped windows are left alone.
+
+        let hwnd = match self.hwnd {
+            Some(hwnd) if self.centers_on_show => hwnd,
+            _ => return,
+        };
+
+        let rect = if let Some(rect) = Self::unsnapped_normal_rect(hwnd) {
+            rect
+        } else {
+            return;
+        };
+
+        // (Falls back to the monitor with the taskbar.)
+        let mut cursor_pos = POINT::default();
+        let hmonitor = match unsafe { GetCursorPos(&mut cursor_pos) } {
+            Ok(()) => unsafe { MonitorFromPoint(cursor_pos, MONITOR_DEFAULTTONEAREST) },
+            Err(_) => get_tray_monitor(),
+        };
+
+        let mut monitor_info = MONITORINFO {
+            cbSize: size_of::<MONITORINFO>() as _,
+            ..Default::default()
+        };
+        if !unsafe { GetMonitorInfoW(hmonitor, &mut monitor_info) }.as_bool() {
+            return;
+        }
+        let work_rect = monitor_info.rcWork;
+
+        // Center the visible frame.
+        let frame_rect = Self::visible_frame_rect(hwnd, rect);
+        let x = (work_rect.left + work_rect.right - (frame_rect.right - frame_rect.left)) / 2
+            - (frame_rect.left - rect.left);
+        let y = (work_rect.top + work_rect.bottom - (frame_rect.bottom - frame_rect.top)) / 2
+            - (frame_rect.top - rect.top);
+
+        if (x, y) == (rect.left, rect.top) {
+            return;
+        }
+
+        if self.original_window_position.is_none() {
+            self.original_window_position = Some((rect.left, rect.top));
+        }
+
+        Self::move_hwnd(hwnd, (x, y));
+    }
+
+    fn visible_frame_rect(hwnd: HWND, rect: RECT) -> RECT {
+        //! The visible frame is smaller than the window rectangle `rect` by the invisible resize borders. Falls back to `rect`.
+
+        let mut frame_rect = rect;
+        let _ = unsafe {
+            DwmGetWindowAttribute(
+                hwnd,
+                DWMWA_EXTENDED_FRAME_BOUNDS,
+                &mut frame_rect as *mut _ as _,
+                size_of::<RECT>() as _,
+            )
+        };
+
+        frame_rect
+    }
+
+    fn restore_window_position(&mut self) {
+        if let (Some(hwnd), Some(position)) = (self.hwnd, self.original_window_position.take()) {
+            if unsafe { !IsIconic(hwnd).as_bool() && !IsZoomed(hwnd).as_bool() } {
+                Self::move_hwnd(hwnd, position);
+            }
+        }
+    }
+
+    fn move_hwnd(hwnd: HWND, (x, y): (i32, i32)) {
+        let _ = unsafe {
+            SetWindowPos(
+                hwnd,
+                HWND(0),
+                x,
+                y,
+                0,
+                0,
+                SWP_NOSIZE | SWP_NOZORDER | SWP_NOACTIVATE,
+            )
+        };
+    }
+
+    fn resize_hwnd(hwnd: HWND, (width, height): (i32, i32)) {
+        let _ = unsafe {
+            SetWindowPos(
+                hwnd,
+                HWND(0),
+                0,
+                0,
+                width,
+                height,
+                SWP_NOMOVE | SWP_NOZORDER | SWP_NOACTIVATE,
+            )
+        };
+    }
+
+    fn set_hwnd_visible(hwnd: HWND, new_visible: bool) {
+        let currently_visible = unsafe { IsWindowVisible(hwnd).as_bool() };
+        if new_visible == currently_visible {
+            return;
+        }
+
+        let show_cmd = if currently_visible {
+            SW_HIDE
+        } else {
+            let mut window_placement = WINDOWPLACEMENT::default();
+            window_placement.length = size_of::<WINDOWPLACEMENT>() as _;
+            let _ = unsafe { GetWindowPlacement(hwnd, &mut window_placement) };
+
+            let is_minimized = window_placement.showCmd == SW_SHOWMINIMIZED.0 as _;
+            if is_minimized {
+                let was_maximized = (window_placement.flags & WPF_RESTORETOMAXIMIZED).0 != 0;
+                if was_maximized {
+                    SW_SHOWMAXIMIZED
+                } else {
+                    //TODO: SOMETIME: Report Windows 10 bug: After `SW_RESTORE` and `SW_SHOWNORMAL`, a previously invisible - but not minimized - arranged window is clearly not arranged anymore, but visually in a *restored* state, while `IsWindowArranged()` erroneously continues to return `TRUE` (at least after hiding and showing a few times). Only after moving the window just a tiny bit, `IsWindowArranged()` returns `FALSE`. The documentation of both of the flags as well as the remarks on `IsWindowArranged()` also object to the experienced behavior. (This code avoids the bug by using `SW_SHOW` instead of `SW_RESTORE` for unminimized windows.)
+                    SW_RESTORE
+                }
+            } else {
+                // As opposed to `SW_RESTORE`, prevents a not minimized window in arranged state from becoming not arranged anymore. (This branch also runs when `GetWindowPlacement()` fails.)
+                SW_SHOW
+            }
+        };
+
+        unsafe {
+            ShowWindow(hwnd, show_cmd);
+            SetForegroundWindow(hwnd);
+        }
+    }
+
+    pub fn show_window_maximized(&mut self) {
+        //! Shows the window like `set_window_visible(true)`, which restores a minimized window to its previous state, and then maximizes it, if it isn't already.
+
+        self.set_window_visible(true);
+
+        if let Some(hwnd) = self.hwnd {
+            if !unsafe { IsZoomed(hwnd) }.as_bool() {
+                unsafe { ShowWindow(hwnd, SW_SHOWMAXIMIZED) };
+            }
+        }
+    }
+
+    pub fn toggle_window_visible(&mut self) {
+        let visible = self.window_visible();
+        self.set_window_visible(!visible);
+    }
+
+    pub fn window_rect(&self) -> Option<RECT> {
+        //! The window's rectangle in virtual-screen coordinates, including the frame.
+
+        let hwnd = self.hwnd?;
+        let mut rect = RECT::default();
+        unsafe { GetWindowRect(hwnd, &mut rect) }.ok()?;
+
+        Some(rect)
+    }
+
+    pub fn window_normal_rect(&self) -> Option<RECT> {
+        //! Like `window_rect()`, but `None` while the window is minimized or maximized, when its rectangle isn't the one it's restored to.
+
+        let hwnd = self.hwnd?;
+        if unsafe { IsIconic(hwnd).as_bool() || IsZoomed(hwnd).as_bool() } {
+            return None;
+        }
+
+        self.window_rect()
+    }
+
+    pub fn set_window_rect(&mut self, rect: RECT) {
+        //! Moves and resizes the window. Like when the user moves the window, a snapped (arranged) window loses that state.
+
+        if let Some(hwnd) = self.hwnd {
+            let _ = unsafe {
+                SetWindowPos(
+                    hwnd,
+                    HWND(0),
+                    rect.left,
+                    rect.top,
+                    rect.right - rect.left,
+                    rect.bottom - rect.top,
+                    SWP_NOZORDER | SWP_NOACTIVATE,
+                )
+            };
+        }
+    }
+
+    pub fn window_responding(&mut self, timeout_millis: u32) -> bool {
+        //! Whether the window answered the last no-op probe message within the timeout, or the timeout didn't elapse yet. Doesn't block: A new probe message is only sent once the last one was answered, which the message loop notices. `true` if there's no window.
+
+        let hwnd = if let Some(hwnd) = self.hwnd {
+            hwnd
+        } else {
+            self.probe = None;
+            return true;
+        };
+
+        if let Some((probe_hwnd, answered, send_time)) = self.probe.as_ref() {
+            if *probe_hwnd == hwnd && !answered.get() {
+                return send_time.elapsed().as_millis() <= timeout_millis as u128;
+            }
+        }
+
+        // (The callback takes over one reference.)
+        let answered = Rc::new(Cell::new(false));
+        let answered_ptr = Rc::into_raw(Rc::clone(&answered));
+        let result = unsafe {
+            SendMessageCallbackW(
+                hwnd,
+                WM_NULL,
+                WPARAM(0),
+                LPARAM(0),
+                Some(Self::probe_callback),
+                answered_ptr as _,
+            )
+        };
+
+        self.probe = match result {
+            Ok(()) => Some((hwnd, answered, Instant::now())),
+            Err(_) => {
+                drop(unsafe { Rc::from_raw(answered_ptr) });
+                None
+            }
+        };
+
+        true
+    }
+
+    extern "system" fn probe_callback(_hwnd: HWND, _msg_id: u32, data: usize, _lresult: LRESULT) {
+        //! Called on this thread by the message loop once the foreign window handled the probe message.
+
+        let answered = unsafe { Rc::from_raw(data as *const Cell<bool>) };
+        answered.set(true);
+    }
+
+    pub fn send_message(
+        &mut self,
+        msg_id: u32,
+        wparam: WPARAM,
+        lparam: LPARAM,
+        sync: bool,
+    ) -> Result<Option<LRESULT>, windows::core::Error> {
+        //! Posts an arbitrary message to the window, or, with `sync`, sends it and returns the result, aborting if the window is hung. Returns `None` when posting or when there's no window.
+        //!
+        //! Use with care: The foreign app can't tell the message apart from its own messages. Parameters that are pointers are meaningless in the other process, except for some system messages that are marshaled when sent (which fail when posted).
+
+        let hwnd = if let Some(hwnd) = self.hwnd {
+            hwnd
+        } else {
+            return Ok(None);
+        };
+
+        if sync {
+            let mut result = 0;
+            let success = unsafe {
+                SendMessageTimeoutW(
+                    hwnd,
+                    msg_id,
+                    wparam,
+                    lparam,
+                    SMTO_ABORTIFHUNG,
+                    5000, /*ms*/
+                    Some(&mut result),
+                )
+            };
+
+            if success.0 == 0 {
+                Err(windows::core::Error::from_win32())
+            } else {
+                Ok(Some(LRESULT(result as _)))
+            }
+        } else {
+            unsafe { PostMessageW(hwnd, msg_id, wparam, lparam)? };
+            Ok(None)
+        }
+    }
+
+    pub fn hwnd(&self) -> Option<HWND> {
+        self.hwnd
+    }
+
+    pub fn window_process_thread_id(&self) -> Option<(u32, u32)> {
+        //! The IDs of the process and thread the window belongs to, once found.
+
+        self.hook_process_thread_id
+    }
+
+    pub fn window_exe_path(&self) -> Option<PathBuf> {
+        self.window_exe_path.clone()
+    }
+
+    pub fn set_window_title(&mut self, title: &str) {
+        //! The original title is restored on drop, unless the window changed its title meanwhile.
+
+        let hwnd = if let Some(hwnd) = self.hwnd {
+            hwnd
+        } else {
+            return;
+        };
+
+        let original_title = match self.original_window_title.take() {
+            Some((original_title, _)) => original_title,
+            None => Self::hwnd_title(hwnd).unwrap_or_default(),
+        };
+        self.original_window_title = Some((original_title, title.to_string()));
+
+        Self::set_hwnd_title(hwnd, title);
+    }
+
+    fn restore_window_title(&mut self) {
+        if let (Some(hwnd), Some((original_title, title))) =
+            (self.hwnd, self.original_window_title.take())
+        {
+            if Self::hwnd_title(hwnd).is_ok_and(|current_title| current_title == title) {
+                Self::set_hwnd_title(hwnd, &original_title);
+            }
+        }
+    }
+
+    fn set_hwnd_title(hwnd: HWND, title: &str) {
+        // (`SetWindowTextW()` would block indefinitely on a hung window, since it sends `WM_SETTEXT` to another process. The text is marshaled when sent.)
+        let title = HSTRING::from(title);
+        let _ = unsafe {
+            SendMessageTimeoutW(
+                hwnd,
+                WM_SETTEXT,
+                WPARAM(0),
+                LPARAM(title.as_ptr() as _),
+                SMTO_ABORTIFHUNG,
+                SET_TITLE_TIMEOUT_MILLIS,
+                None,
+            )
+        };
+    }
+
+    pub fn window_title(&self) -> Result<String, windows::core::Error> {
+        //! With `set_title_child_class()`, the title of the descendant window, if it exists.
+
+        match self.title_hwnd() {
+            Some(hwnd) => Self::hwnd_title(hwnd),
+            None => Err(ERROR_INVALID_WINDOW_HANDLE.into()),
+        }
+    }
+
+    fn hwnd_title(hwnd: HWND) -> Result<String, windows::core::Error> {
+        unsafe { SetLastError(WIN32_ERROR(0)) };
+        let len = unsafe { GetWindowTextLengthW(hwnd) } as usize;
+        if len == 0 {
+            let error = windows::core::Error::from_win32();
+            return if error.code() == S_OK {
+                Ok("".to_string())
+            } else {
+                Err(error)
+            };
+        }
+
+        let mut buffer = vec![0; len + 1];
+        let copied_len = unsafe { GetWindowTextW(hwnd, &mut buffer) } as usize;
+        if copied_len == len {
+            Ok(String::from_utf16_lossy(&buffer[..len]))
+        } else {
+            Err(windows::core::Error::from_win32())
+        }
+    }
+
+    pub fn close_window(&mut self, method: CloseMethod) -> Result<(), windows::core::Error> {
+        //! If `WM_CLOSE` can't be posted, e.g., because the window's message queue is full, the process is terminated instead. A window that doesn't exist anymore counts as closed. With `CloseMethod::CtrlC`, the event is sent on a worker thread, which falls back to `WM_CLOSE` itself.
+
+        let hwnd = if let Some(hwnd) = self.hwnd {
+            hwnd
+        } else {
+            return Ok(());
+        };
+
+        let mut process_id = 0;
+        unsafe { GetWindowThreadProcessId(hwnd, Some(&mut process_id)) };
+
+        // (The other methods fall back to `WM_CLOSE`.)
+        let is_closed = match method {
+            CloseMethod::WmClose => false,
+            CloseMethod::CtrlC => {
+                // (Sending the event involves waiting for its delivery.)
+                self.ctrl_c_thread = Some(thread::spawn(move || {
+                    if Self::send_ctrl_c(process_id).is_err() {
+                        let _ = Self::post_close(hwnd, process_id);
+                    }
+                }));
+
+                true
+            }
+            CloseMethod::Terminate => Self::terminate_process(process_id).is_ok(),
+        };
+
+        if is_closed {
+            return Ok(());
+        }
+
+        Self::post_close(hwnd, process_id)
+    }
+
+    fn post_close(hwnd: HWND, process_id: u32) -> Result<(), windows::core::Error> {
+        match unsafe { PostMessageW(hwnd, WM_CLOSE, WPARAM(0), LPARAM(0)) } {
+            Ok(()) => Ok(()),
+            Err(error) if error.code() == ERROR_INVALID_WINDOW_HANDLE.to_hresult() => Ok(()),
+            Err(_) => Self::terminate_process(process_id),
+        }
+    }
+
+    fn terminate_process(process_id: u32) -> Result<(), windows::core::Error> {
+        let h_process = unsafe { OpenProcess(PROCESS_TERMINATE, false, process_id)? };
+        let result = unsafe { TerminateProcess(h_process, 1) };
+        let _ = unsafe { CloseHandle(h_process) };
+
+        result
+    }
+
+    fn send_ctrl_c(process_id: u32) -> Result<(), windows::core::Error> {
+        let _console_switch = CONSOLE_SWITCH
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        // A process can only be attached to one console. (Detaching fails harmlessly, if there's no console, like in release builds.)
+        let _ = unsafe { FreeConsole() };
+
+        // Process group 0 means all processes attached to the console, which would include this process without the handler being disabled.
+        let result = unsafe {
+            AttachConsole(process_id)
+                .and_then(|()| SetConsoleCtrlHandler(None, true))
+                .and_then(|()| GenerateConsoleCtrlEvent(CTRL_C_EVENT, 0))
+        };
+
+        // (The event is delivered asynchronously, so the handler may only be reenabled after waiting for that. Otherwise, this process would terminate along with the foreign one.)
+        if result.is_ok() {
+            thread::sleep(Duration::from_millis(CTRL_C_DELIVERY_MILLIS));
+        }
+        let _ = unsafe { SetConsoleCtrlHandler(None, false) };
+
+        let _ = unsafe { FreeConsole() };
+        if CONSOLE_INHERITED.get().copied().unwrap_or(false) {
+            let _ = unsafe { AttachConsole(ATTACH_PARENT_PROCESS) };
+        }
+
+        result
+    }
+}
+
+/// How `ForeignProcessTree::new()` starts the process tree.
+#[derive(Default)]
+pub struct SpawnOptions {
+    /// The process doesn't inherit the console and gets its own process group.
+    pub spawn_detached: bool,
+    /// The process is created suspended and only resumed once the hooks for discovering its window are installed, so that a window it shows right away isn't missed. If it can't be created suspended, it's started regularly. (`CreateProcessW` isn't called directly, so that `std::process::Command` keeps handling argument quoting, the environment and output redirection; the flag is passed via `creation_flags()` instead.)
+    pub spawn_suspended: bool,
+    /// Keeps the spawned process's handle, so that `wait_for_root_process_exit()` can report its exit code.
+    pub keep_root_process: bool,
+    /// The priority class the process is created with. Processes it starts only inherit `ProcessPriority::Idle` and `ProcessPriority::BelowNormal`; otherwise, they run at normal priority by default.
+    pub priority: Option<ProcessPriority>,
+    /// The process doesn't inherit this process's environment variables.
+    pub clear_env: bool,
+    /// Environment variables set for the process, also with `clear_env`.
+    pub env_vars: Vec<(String, String)>,
+    /// The file the process's stdout and stderr are redirected to. Failure to open it counts as failure to start the process tree.
+    pub output_path: Option<PathBuf>,
+    /// Whether `output_path` is appended to instead of truncated.
+    pub append_output: bool,
+    /// The process tree is started by a timer, so that the message loop isn't blocked, and failure to start it is reported via the error window message.
+    pub spawn_delay_millis: Option<u32>,
+}
+
+/// How `ForeignProcessTree` finds the window in the process tree.
+#[derive(Default)]
+pub struct SearchOptions {
+    /// The window classes to look for. If empty, any window that looks like a main window matches.
+    pub window_classes: Vec<String>,
+    /// Whether `window_classes` are compared case-insensitively.
+    pub window_class_ignores_case: bool,
+    /// Only processes whose image name matches this are searched for the window.
+    pub process_name_filter: Option<String>,
+    /// How deep below the spawned process windows are searched for.
+    pub max_child_depth: Option<u32>,
+    /// Processes with this image name anywhere in the system that were started after the spawned process are tracked like it once it exited, until the timeout, for wrappers like `cmd /c start app.exe` whose child loses its parent. Keeps the spawned process's handle.
+    pub exe_match: Option<String>,
+    /// A matching window of any process is adopted if the spawned process exits quickly, like single-instance apps do when already running. Keeps the spawned process's handle.
+    pub adopt_existing_window: bool,
+    /// The window is only searched for once this file exists, for apps that signal the end of a long initialization with it.
+    pub ready_file: Option<PathBuf>,
+    /// How long `ready_file` may take to appear after spawning, before the error window message is posted.
+    pub ready_file_timeout_millis: u32,
+}
+
+/// How `ForeignProcessTree` watches and adjusts the found window.
+#[derive(Default)]
+pub struct TrackingOptions {
+    /// A timer periodically checks whether the found window still exists and belongs to the hooked thread, in case hooks stop delivering events. A vanished window leads to `ForeignWindowEvent::Destroyed`.
+    pub watchdog_millis: Option<u32>,
+    /// The window is resized into these when found and when shown.
+    pub size_limits: Option<WindowSizeLimits>,
+}
+
+/// Sizes in pixels that the foreign window is kept within.
+#[derive(Clone, Copy)]
+pub struct WindowSizeLimits {
+    /// Width and height.
+    pub min: Option<(i32, i32)>,
+    /// Width and height.
+    pub max: Option<(i32, i32)>,
+    /// Whether the limits are also applied whenever the window is resized.
+    pub reapply_on_resize: bool,
+}
+
+/// Disables the system-wide minimize and restore animations while alive. (The change isn't persisted, so the setting is reset on next login in any case.)
+struct MinimizeAnimationSuppression {
+    original_min_animate: i32,
+}
+
+impl MinimizeAnimationSuppression {
+    fn new() -> Option<Self> {
+        //! `None`, if the animations are already disabled or the setting couldn't be changed.
+
+        let mut animation_info = ANIMATIONINFO {
+            cbSize: size_of::<ANIMATIONINFO>() as _,
+            iMinAnimate: 0,
+        };
+
+        unsafe {
+            SystemParametersInfoW(
+                SPI_GETANIMATION,
+                animation_info.cbSize,
+                Some(ptr::addr_of_mut!(animation_info).cast()),
+                SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS(0),
+            )
+        }
+        .ok()?;
+
+        if animation_info.iMinAnimate == 0 {
+            return None;
+        }
+
+        let instance = Self {
+            original_min_animate: animation_info.iMinAnimate,
+        };
+        animation_info.iMinAnimate = 0;
+        Self::set_animation_info(&mut animation_info).then_some(instance)
+    }
+
+    fn set_animation_info(animation_info: &mut ANIMATIONINFO) -> bool {
+        unsafe {
+            SystemParametersInfoW(
+                SPI_SETANIMATION,
+                animation_info.cbSize,
+                Some(ptr::addr_of_mut!(*animation_info).cast()),
+                SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS(0),
+            )
+        }
+        .is_ok()
+    }
+}
+
+impl Drop for MinimizeAnimationSuppression {
+    fn drop(&mut self) {
+        let mut animation_info = ANIMATIONINFO {
+            cbSize: size_of::<ANIMATIONINFO>() as _,
+            iMinAnimate: self.original_min_animate,
+        };
+        Self::set_animation_info(&mut animation_info);
+    }
+}
+
+fn file_time_to_u64(file_time: FILETIME) -> u64 {
+    (file_time.dwHighDateTime as u64) << 32 | file_time.dwLowDateTime as u64
+}
+
+fn resume_process(process_id: u32) -> Result<(), windows::core::Error> {
+    //! Resumes the primary thread of a process created with `CREATE_SUSPENDED`, which is its earliest created thread. (`Child` doesn't expose the thread handle, so the thread is looked up. Threads that another process may have injected meanwhile are left alone.)
+
+    resume_thread(ProcessThreadSet::main_thread_of(process_id)?)
+}
+
+fn resume_thread(thread_id: u32) -> Result<(), windows::core::Error> {
+    let h_thread = unsafe { OpenThread(THREAD_SUSPEND_RESUME, false, thread_id)? };
+
+    let result = if unsafe { ResumeThread(h_thread) } == u32::MAX {
+        Err(windows::core::Error::from_win32())
+    } else {
+        Ok(())
+    };
+
+    let _ = unsafe { CloseHandle(h_thread) };
+
+    result
+}
+
+impl Drop for ForeignProcessTree {
+    fn drop(&mut self) {
+        self.release();
+
+        // (Otherwise, exiting could prevent the event from being sent or leave this process attached to the foreign console.)
+        if let Some(ctrl_c_thread) = self.ctrl_c_thread.take() {
+            let _ = ctrl_c_thread.join();
+        }
+    }
+}
+
+fn console_inherited() -> bool {
+    //! Whether another process, i.e., the parent process, is attached to this process's console.
+
+    let mut process_ids = [0; 2];
+    let process_count = unsafe { GetConsoleProcessList(&mut process_ids) };
+
+    process_count > 1
+}
+
+pub enum ForeignWindowEvent {
+    Found,
+    Minimized,
+    Restored,
+    TitleChanged,
+    /// The window was moved or resized, and its location then didn't change for a moment. Also after showing, hiding, minimizing and restoring. Only with `set_moves_reported()` or `WindowSizeLimits::reapply_on_resize`, which needs move tracking itself.
+    Moved,
+    /// The user started dragging the window or one of its borders.
+    MoveSizeStarted,
+    /// The user stopped dragging the window or one of its borders.
+    MoveSizeEnded,
+    /// Any window in the system became the foreground window. `is_ours` tells whether it's the window or one owned by it, like its dialogs. Only with `set_foreground_tracked()`.
+    ForegroundChanged {
+        is_ours: bool,
+    },
+    Destroyed,
+    Internal,
+}
+
+fn rect_drifted(old_rect: RECT, new_rect: RECT, tolerance: u32) -> bool {
+    [
+        (old_rect.left, new_rect.left),
+        (old_rect.top, new_rect.top),
+        (old_rect.right, new_rect.right),
+        (old_rect.bottom, new_rect.bottom),
+    ]
+    .into_iter()
+    .any(|(old, new)| old.abs_diff(new) > tolerance)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, pin::Pin};
+    use windows::{
+        core::h,
+        Win32::{
+            Foundation::{HANDLE, HWND, LPARAM, LRESULT, RECT, WPARAM},
+            System::Threading::GetCurrentProcessId,
+            UI::WindowsAndMessaging::{
+                GetClassNameW, IsWindowVisible, SetPropW, SetWindowTextW, ShowWindow, SW_SHOW,
+            },
+        },
+    };
+
+    use super::{rect_drifted, ForeignProcessTree, CLAIM_PROP_NAME};
+    use crate::win32::base_window::{BaseWindow, OnWindowMsg};
+
+    struct DummyWindow;
+
+    impl OnWindowMsg for DummyWindow {
+        fn on_window_msg(
+            _this: Pin<&mut Self>,
+            _hwnd: HWND,
+            _msg_id: u32,
+            _wparam: WPARAM,
+            _lparam: LPARAM,
+        ) -> Option<LRESULT> {
+            None
+        }
+    }
+
+    fn create_dummy_window() -> (Pin<Box<BaseWindow<'static, DummyWindow>>>, String) {
+        let window = BaseWindow::new().expect("dummy window should be created");
+
+        let mut buffer = vec![0; 257];
+        let len = unsafe { GetClassNameW(window.hwnd(), &mut buffer) } as usize;
+        let class_name = String::from_utf16_lossy(&buffer[..len]);
+
+        (window, class_name)
+    }
+
+    #[test]
+    fn finds_visible_window_with_class() {
+        let (window, class_name) = create_dummy_window();
+        unsafe { ShowWindow(window.hwnd(), SW_SHOW) };
+
+        let process_id = unsafe { GetCurrentProcessId() };
+        assert_eq!(
+            ForeignProcessTree::find_window(
+                process_id,
+                &[class_name.clone()],
+                false,
+                false,
+                HWND(0)
+            ),
+            Some(window.hwnd())
+        );
+    }
+
+    #[test]
+    fn ignores_hidden_window() {
+        let (window, class_name) = create_dummy_window();
+
+        let process_id = unsafe { GetCurrentProcessId() };
+        assert!(!ForeignProcessTree::is_matching_window(
+            window.hwnd(),
+            process_id,
+            &[class_name.clone()],
+            false,
+            false
+        ));
+        assert_eq!(
+            ForeignProcessTree::find_window(
+                process_id,
+                &[class_name.clone()],
+                false,
+                false,
+                HWND(0)
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn finds_hidden_main_window_if_included() {
+        let (window, class_name) = create_dummy_window();
+
+        let process_id = unsafe { GetCurrentProcessId() };
+        assert_eq!(
+            ForeignProcessTree::find_window(
+                process_id,
+                &[class_name.clone()],
+                false,
+                true,
+                HWND(0)
+            ),
+            None
+        );
+
+        unsafe { SetWindowTextW(window.hwnd(), h!("Dummy")) }.expect("title should be set");
+        assert_eq!(
+            ForeignProcessTree::find_window(
+                process_id,
+                &[class_name.clone()],
+                false,
+                true,
+                HWND(0)
+            ),
+            Some(window.hwnd())
+        );
+        assert_eq!(
+            ForeignProcessTree::find_window(
+                process_id,
+                &[class_name.clone()],
+                false,
+                false,
+                HWND(0)
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn ignores_window_claimed_by_other() {
+        let (window, class_name) = create_dummy_window();
+        let (claiming_window, _) = create_dummy_window();
+        unsafe { ShowWindow(window.hwnd(), SW_SHOW) };
+        unsafe {
+            SetPropW(
+                window.hwnd(),
+                CLAIM_PROP_NAME,
+                HANDLE(claiming_window.hwnd().0),
+            )
+        }
+        .expect("property should be set");
+
+        let process_id = unsafe { GetCurrentProcessId() };
+        assert_eq!(
+            ForeignProcessTree::find_window(
+                process_id,
+                &[class_name.clone()],
+                false,
+                false,
+                HWND(0)
+            ),
+            None
+        );
+        assert_eq!(
+            ForeignProcessTree::find_window(
+                process_id,
+                &[class_name.clone()],
+                false,
+                false,
+                claiming_window.hwnd()
+            ),
+            Some(window.hwnd())
+        );
+
+        drop(claiming_window);
+        assert_eq!(
+            ForeignProcessTree::find_window(
+                process_id,
+                &[class_name.clone()],
+                false,
+                false,
+                HWND(0)
+            ),
+            Some(window.hwnd())
+        );
+    }
+
+    #[test]
+    fn ignores_other_class_and_process() {
+        let (window, class_name) = create_dummy_window();
+        unsafe { ShowWindow(window.hwnd(), SW_SHOW) };
+
+        let process_id = unsafe { GetCurrentProcessId() };
+        assert!(ForeignProcessTree::window_class_matches(
+            window.hwnd(),
+            &[class_name.clone()],
+            false
+        ));
+        assert!(ForeignProcessTree::window_class_matches(
+            window.hwnd(),
+            &["Other".to_string(), class_name.clone()],
+            false
+        ));
+        assert!(!ForeignProcessTree::window_class_matches(
+            window.hwnd(),
+            &[class_name[..class_name.len() - 1].to_string()],
+            false
+        ));
+        assert!(!ForeignProcessTree::window_class_matches(
+            window.hwnd(),
+            &[class_name.to_uppercase()],
+            false
+        ));
+        assert!(ForeignProcessTree::window_class_matches(
+            window.hwnd(),
+            &[class_name.to_uppercase()],
+            true
+        ));
+        assert!(!ForeignProcessTree::is_matching_window(
+            window.hwnd(),
+            process_id.wrapping_add(4),
+            &[class_name.clone()],
+            false,
+            false
+        ));
+    }
+
+    #[test]
+    fn matches_titled_window_without_class() {
+        let (window, _) = create_dummy_window();
+        unsafe { ShowWindow(window.hwnd(), SW_SHOW) };
+
+        let process_id = unsafe { GetCurrentProcessId() };
+        assert!(!ForeignProcessTree::is_matching_window(
+            window.hwnd(),
+            process_id,
+            &[],
+            false,
+            false
+        ));
+
+        unsafe { SetWindowTextW(window.hwnd(), h!("Dummy")) }.expect("title should be set");
+        assert!(ForeignProcessTree::is_matching_window(
+            window.hwnd(),
+            process_id,
+            &[],
+            false,
+            false
+        ));
+    }
+
+    #[test]
+    fn queries_exe_path_of_window() {
+        let (window, _) = create_dummy_window();
+
+        assert_eq!(
+            ForeignProcessTree::query_exe_path(window.hwnd()).ok(),
+            env::current_exe().ok()
+        );
+    }
+
+    #[test]
+    fn toggles_visibility() {
+        let (window, _) = create_dummy_window();
+        let hwnd = window.hwnd();
+
+        ForeignProcessTree::set_hwnd_visible(hwnd, true);
+        assert!(unsafe { IsWindowVisible(hwnd).as_bool() });
+
+        ForeignProcessTree::set_hwnd_visible(hwnd, false);
+        assert!(!unsafe { IsWindowVisible(hwnd).as_bool() });
+    }
+
+    #[test]
+    fn detects_drift_beyond_tolerance() {
+        let rect = RECT {
+            left: 100,
+            top: 100,
+            right: 900,
+            bottom: 700,
+        };
+
+        assert!(!rect_drifted(rect, rect, 0));
+        assert!(!rect_drifted(
+            rect,
+            RECT {
+                left: 108,
+                right: 908,
+                ..rect
+            },
+            8
+        ));
+        assert!(rect_drifted(
+            rect,
+            RECT {
+                top: 91,
+                bottom: 691,
+                ..rect
+            },
+            8
+        ));
+        assert!(rect_drifted(rect, RECT { right: 920, ..rect }, 8));
+    }
 }
@@ -1,25 +1,59 @@
 // No console window in release build. (An alternative would be to call `FreeConsole()` in release builds, in which case a console window is briefly shown, however.)
 #![cfg_attr(all(not(debug_assertions), not(test)), windows_subsystem = "windows")]
 
+mod autostart;
 mod background_window;
 mod cli;
+mod config;
+mod control_server;
+mod error;
+mod event_command;
+mod event_output;
 mod foreign_process_tree;
+mod pick;
+mod state;
 mod win32;
 
 use anyhow::anyhow;
 use clap::Parser;
 use cli::Cli;
 use std::process;
+#[cfg(debug_assertions)]
+use std::sync::atomic::{AtomicU32, Ordering};
 use windows::core::HSTRING;
+#[cfg(debug_assertions)]
+use windows::Win32::{
+    Foundation::{BOOL, LPARAM, WPARAM},
+    System::{
+        Console::{SetConsoleCtrlHandler, CTRL_BREAK_EVENT, CTRL_C_EVENT},
+        Threading::GetCurrentThreadId,
+    },
+    UI::WindowsAndMessaging::{PostThreadMessageW, WM_QUIT},
+};
 
 use background_window::BackgroundWindow;
-use win32::msg_loop::Win32MsgLoop;
+use win32::{
+    icon::{assert_all_hicons_destroyed, clear_icon_cache},
+    msg_loop::Win32MsgLoop,
+};
 
 static APP_NAME: &str = "Tray Valet";
 
+/// How often the message loop is rerun after an error before the app exits.
+const MSG_LOOP_MAX_RETRIES: u32 = 3;
+
+/// The thread running the message loop, for the console control handler.
+#[cfg(debug_assertions)]
+static MSG_LOOP_THREAD_ID: AtomicU32 = AtomicU32::new(0);
+
 fn main() {
+    let quiet_errors = Cli::quiet_errors_in_args();
+
+    #[cfg(debug_assertions)]
+    install_console_ctrl_handler();
+
     let exit_result = 'block: {
-        let cli = {
+        let app_clis = {
             let parse_result = Cli::try_parse()
                 .map_err(|error| {
                     let has_info_error = matches!(
@@ -31,38 +65,106 @@ fn main() {
                     (anyhow!(error), has_info_error)
                 })
                 .and_then(|cli| {
-                    if cli.foreign_process_tree_args.len() < 1 {
-                        Err((
-                            anyhow!(
-                                "Missing command or command arguments after separating ` -- `."
-                            ),
-                            false,
-                        ))
-                    } else {
-                        Ok(cli)
+                    // One-shot management commands.
+                    if cli.install_autostart || cli.uninstall_autostart {
+                        let (result, success_msg) = if cli.install_autostart {
+                            // (An entry that would fail at every login isn't installed.)
+                            let result = cli
+                                .clone()
+                                .into_app_clis()
+                                .and_then(|_| autostart::install());
+                            (result, "Autostart entry was installed.")
+                        } else {
+                            (autostart::uninstall(), "Autostart entry was removed.")
+                        };
+
+                        // (Success is reported like an info error to exit with a message box.)
+                        return Err(match result {
+                            Ok(()) => (anyhow!(success_msg), true),
+                            Err(error) => (error, false),
+                        });
+                    }
+
+                    if cli.reset {
+                        // (The report is shown like an info error to exit with a message box.)
+                        return Err(match reset(cli) {
+                            Ok(report) => (anyhow!(report), true),
+                            Err(error) => (error, false),
+                        });
+                    }
+
+                    if cli.pick {
+                        // (The result is reported like an info error to exit with a message box.)
+                        return Err(match pick::run() {
+                            Ok(Some(description)) => (anyhow!(description), true),
+                            Ok(None) => (anyhow!("Picking a window was canceled."), true),
+                            Err(error) => (error, false),
+                        });
                     }
+
+                    cli.into_app_clis().map_err(|error| (error, false))
                 });
 
             match parse_result {
-                Ok(cli) => cli,
+                Ok(app_clis) => app_clis,
                 Err(data) => break 'block Err(data),
             }
         };
 
-        let _background_window = match BackgroundWindow::new(cli) {
-            Ok(window) => window,
-            Err(error) => break 'block Err((error, false)),
+        let mut background_windows = match app_clis
+            .into_iter()
+            .enumerate()
+            .map(|(app_index, cli)| BackgroundWindow::new(cli, app_index))
+            .collect::<Result<Vec<_>, _>>()
+        {
+            Ok(windows) => windows,
+            Err(error) => break 'block Err((error.into(), false)),
+        };
+
+        // (Returns only on `WM_QUIT`, i.e., after all background windows were destroyed.)
+        let exit_code = match Win32MsgLoop::run_resilient(MSG_LOOP_MAX_RETRIES) {
+            Ok(exit_code) => exit_code,
+            // (Breaking drops `background_windows`, which restores the foreign windows before the error is shown.)
+            Err(error) => break 'block Err((anyhow!(error), false)),
         };
 
-        Win32MsgLoop::run().map_err(|error| (anyhow!(error), false))
+        // `--wait`. (The first nonzero exit code of the started processes, unless this app had an error.)
+        let foreign_exit_code = background_windows
+            .iter_mut()
+            .filter_map(|background_window| background_window.wait_for_foreign_exit())
+            .fold(
+                0,
+                |code, foreign_code| if code == 0 { foreign_code } else { code },
+            );
+
+        Ok(if exit_code == 0 {
+            foreign_exit_code as _
+        } else {
+            exit_code
+        })
+
+        // (`background_windows` is dropped here, restoring the foreign windows, since `process::exit()` doesn't run destructors.)
     };
 
+    clear_icon_cache();
+    assert_all_hicons_destroyed();
+
     process::exit(match exit_result {
         // May still be an error.
         Ok(exit_code) => exit_code as _,
+        Err((error, has_info_error)) if quiet_errors => {
+            if has_info_error {
+                println!("{error:#}");
+                0
+            } else {
+                eprintln!("{APP_NAME}: {error:#}");
+                1
+            }
+        }
         Err((error, has_info_error)) => {
             win_msgbox::MessageBox::<win_msgbox::Okay>::new(
-                HSTRING::from(error.to_string()).as_ptr(),
+                // (Alternate formatting includes the causes.)
+                HSTRING::from(format!("{error:#}")).as_ptr(),
             )
             .icon(if has_info_error {
                 win_msgbox::Icon::Information
@@ -81,3 +183,51 @@ fn main() {
         }
     });
 }
+
+fn reset(cli: Cli) -> anyhow::Result<String> {
+    //! `--reset`. Returns a report of what was removed.
+
+    let mut removed = Vec::new();
+    for app_cli in cli.into_app_clis()? {
+        if state::remove(&app_cli.state_key())? {
+            removed.push(format!(
+                "the remembered state of `{}`",
+                app_cli.foreign_process_tree_args.join(" ")
+            ));
+        }
+    }
+
+    if autostart::uninstall_if_installed()? {
+        removed.push("the autostart entry".to_string());
+    }
+
+    Ok(if removed.is_empty() {
+        "There was nothing to remove.".to_string()
+    } else {
+        format!("Removed {}.", removed.join(", "))
+    })
+}
+
+#[cfg(debug_assertions)]
+fn install_console_ctrl_handler() {
+    //! Makes Ctrl+C in the console end the message loop, so that the regular teardown shows and releases the foreign windows, instead of the process being killed with the windows staying hidden.
+
+    MSG_LOOP_THREAD_ID.store(unsafe { GetCurrentThreadId() }, Ordering::Relaxed);
+    let _ = unsafe { SetConsoleCtrlHandler(Some(console_ctrl_handler), true) };
+}
+
+#[cfg(debug_assertions)]
+unsafe extern "system" fn console_ctrl_handler(ctrl_type: u32) -> BOOL {
+    //! Called on a separate thread.
+
+    match ctrl_type {
+        CTRL_C_EVENT | CTRL_BREAK_EVENT => {
+            let thread_id = MSG_LOOP_THREAD_ID.load(Ordering::Relaxed);
+            unsafe { PostThreadMessageW(thread_id, WM_QUIT, WPARAM(0), LPARAM(0)) }
+                .is_ok()
+                .into()
+        }
+        // (Closing the console terminates the process after the handler returns anyway.)
+        _ => false.into(),
+    }
+}
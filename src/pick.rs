@@ -0,0 +1,56 @@
+use anyhow::Result;
+use std::env;
+use windows::Win32::{
+    Foundation::HWND,
+    UI::WindowsAndMessaging::{GetClassNameW, GetWindowTextLengthW, GetWindowTextW},
+};
+
+use crate::{
+    autostart::quote_arg, foreign_process_tree::ForeignProcessTree,
+    win32::window_picker::pick_window,
+};
+
+pub fn run() -> Result<Option<String>> {
+    //! Lets the user click a window and describes it with a ready-to-use command line for this app. `None`, if canceled.
+
+    let hwnd = if let Some(hwnd) = pick_window()? {
+        hwnd
+    } else {
+        return Ok(None);
+    };
+
+    let class_name = window_class_name(hwnd);
+    let title = window_title(hwnd);
+
+    // (The command can't be known, only the executable file.)
+    let command = ForeignProcessTree::query_exe_path(hwnd).map_or_else(
+        |_| "COMMAND".to_string(),
+        |path| quote_arg(&path.to_string_lossy()),
+    );
+    let app_file_name = env::current_exe()
+        .ok()
+        .and_then(|path| Some(path.file_name()?.to_string_lossy().into_owned()))
+        .unwrap_or_else(|| "tray-valet.exe".to_string());
+
+    Ok(Some(format!(
+        "Class: {class_name}\nTitle: {title}\n\nCommand line:\n{} --win-class {} -- {command}",
+        quote_arg(&app_file_name),
+        quote_arg(&class_name),
+    )))
+}
+
+fn window_class_name(hwnd: HWND) -> String {
+    // Class names are limited to 256 characters.
+    let mut buffer = vec![0; 257];
+    let len = unsafe { GetClassNameW(hwnd, &mut buffer) } as usize;
+
+    String::from_utf16_lossy(&buffer[..len])
+}
+
+fn window_title(hwnd: HWND) -> String {
+    let len = unsafe { GetWindowTextLengthW(hwnd) } as usize;
+    let mut buffer = vec![0; len + 1];
+    let copied_len = unsafe { GetWindowTextW(hwnd, &mut buffer) } as usize;
+
+    String::from_utf16_lossy(&buffer[..copied_len])
+}
@@ -0,0 +1,183 @@
+use anyhow::{anyhow, Context, Result};
+use serde_json::{json, Map, Value};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+use windows::Win32::Foundation::RECT;
+
+use crate::autostart::quote_arg;
+
+/// What's remembered about an app between runs with `--remember-state`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AppState {
+    pub visible: bool,
+    /// The foreign window's rectangle in virtual-screen coordinates. `None`, if the window was minimized or maximized at the time.
+    pub rect: Option<RECT>,
+}
+
+pub fn state_key(window_class: Option<&str>, foreign_process_tree_args: &[String]) -> String {
+    //! Identifies an app by its window class and command, which are what the command line or `[[app]]` table is built around.
+
+    let mut key = window_class.map_or_else(String::new, quote_arg);
+    key.push_str(" --");
+    for arg in foreign_process_tree_args {
+        key.push(' ');
+        key.push_str(&quote_arg(arg));
+    }
+
+    key
+}
+
+pub fn load(key: &str) -> Option<AppState> {
+    //! `None`, if there's no state for the app, or if the state file is missing or corrupt.
+
+    parse_app_state(read_state_file()?.get(key)?)
+}
+
+pub fn save(key: &str, app_state: &AppState) -> Result<()> {
+    //! Keeps the states of other apps. A corrupt state file is replaced.
+
+    let path = state_file_path().ok_or_else(|| anyhow!("`%APPDATA%` isn't set."))?;
+    let mut apps = read_state_file().unwrap_or_default();
+    apps.insert(key.to_string(), app_state_value(app_state));
+
+    if let Some(dir_path) = path.parent() {
+        fs::create_dir_all(dir_path)
+            .with_context(|| format!("Couldn't create folder \"{}\".", dir_path.display()))?;
+    }
+    write_state_file(&path, apps)
+}
+
+pub fn remove(key: &str) -> Result<bool> {
+    //! Returns whether there was a state for the app. Keeps the states of other apps, and deletes the state file, if there are none.
+
+    let (path, mut apps) = match (state_file_path(), read_state_file()) {
+        (Some(path), Some(apps)) => (path, apps),
+        _ => return Ok(false),
+    };
+
+    if apps.remove(key).is_none() {
+        return Ok(false);
+    }
+
+    if apps.is_empty() {
+        fs::remove_file(&path)
+            .with_context(|| format!("Couldn't delete state file \"{}\".", path.display()))?;
+    } else {
+        write_state_file(&path, apps)?;
+    }
+
+    Ok(true)
+}
+
+fn state_file_path() -> Option<PathBuf> {
+    Some(
+        PathBuf::from(env::var_os("APPDATA")?)
+            .join(env!("CARGO_PKG_NAME"))
+            .join("state.json"),
+    )
+}
+
+fn write_state_file(path: &Path, apps: Map<String, Value>) -> Result<()> {
+    fs::write(path, serde_json::to_string_pretty(&Value::Object(apps))?)
+        .with_context(|| format!("Couldn't write state file \"{}\".", path.display()))
+}
+
+fn read_state_file() -> Option<Map<String, Value>> {
+    match serde_json::from_str(&fs::read_to_string(state_file_path()?).ok()?).ok()? {
+        Value::Object(apps) => Some(apps),
+        _ => None,
+    }
+}
+
+fn parse_app_state(value: &Value) -> Option<AppState> {
+    let visible = value.get("visible")?.as_bool()?;
+
+    // (A malformed rectangle is ignored on its own.)
+    let rect = value.get("rect").and_then(|rect| {
+        let coords = rect
+            .as_array()?
+            .iter()
+            .map(|coord| i32::try_from(coord.as_i64()?).ok())
+            .collect::<Option<Vec<_>>>()?;
+
+        match coords[..] {
+            [left, top, right, bottom] if left < right && top < bottom => Some(RECT {
+                left,
+                top,
+                right,
+                bottom,
+            }),
+            _ => None,
+        }
+    });
+
+    Some(AppState { visible, rect })
+}
+
+fn app_state_value(app_state: &AppState) -> Value {
+    json!({
+        "visible": app_state.visible,
+        "rect": app_state
+            .rect
+            .map(|rect| [rect.left, rect.top, rect.right, rect.bottom]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use windows::Win32::Foundation::RECT;
+
+    use super::{app_state_value, parse_app_state, state_key, AppState};
+
+    #[test]
+    fn round_trips_app_state() {
+        for app_state in [
+            AppState {
+                visible: false,
+                rect: Some(RECT {
+                    left: -1200,
+                    top: 40,
+                    right: -200,
+                    bottom: 740,
+                }),
+            },
+            AppState {
+                visible: true,
+                rect: None,
+            },
+        ] {
+            assert_eq!(
+                parse_app_state(&app_state_value(&app_state)),
+                Some(app_state)
+            );
+        }
+    }
+
+    #[test]
+    fn ignores_corrupt_app_state() {
+        assert_eq!(parse_app_state(&json!("visible")), None);
+        assert_eq!(parse_app_state(&json!({ "rect": [0, 0, 10, 10] })), None);
+        assert_eq!(
+            parse_app_state(&json!({ "visible": true, "rect": [0, 0, 10] })),
+            Some(AppState {
+                visible: true,
+                rect: None,
+            })
+        );
+    }
+
+    #[test]
+    fn builds_distinct_keys() {
+        assert_eq!(
+            state_key(Some("ConsoleWindowClass"), &["cmd".into(), "/k".into()]),
+            "ConsoleWindowClass -- cmd /k"
+        );
+        assert_ne!(
+            state_key(None, &["app.exe".into(), "a b".into()]),
+            state_key(None, &["app.exe".into(), "a".into(), "b".into()])
+        );
+    }
+}
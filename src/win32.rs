@@ -1,6 +1,10 @@
 pub mod base_window;
 pub mod context_menu;
+pub mod environment;
+pub mod hotkey;
 pub mod icon;
 pub mod msg_loop;
+pub mod timer;
 pub mod tray_icon;
 pub mod win_event_hook;
+pub mod window_picker;
@@ -2,19 +2,23 @@
 
 use std::marker::PhantomPinned;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use windows::{
     core::{HSTRING, PCWSTR},
     Win32::{
-        Foundation::{HWND, LPARAM, LRESULT, WPARAM},
+        Foundation::{ERROR_CLASS_ALREADY_EXISTS, HWND, LPARAM, LRESULT, WPARAM},
         System::{LibraryLoader::GetModuleHandleW, Performance::QueryPerformanceCounter},
         UI::WindowsAndMessaging::{
             CreateWindowExW, DefWindowProcW, DestroyWindow, GetWindowLongPtrW, RegisterClassW,
-            SetWindowLongPtrW, UnregisterClassW, CREATESTRUCTW, GWLP_USERDATA, HMENU,
-            WINDOW_EX_STYLE, WINDOW_STYLE, WM_NCCREATE, WNDCLASSW,
+            SetWindowLongPtrW, UnregisterClassW, CREATESTRUCTW, GWLP_USERDATA, HMENU, SC_CLOSE,
+            SC_MAXIMIZE, SC_MINIMIZE, SC_RESTORE, WINDOW_EX_STYLE, WINDOW_STYLE, WM_NCCREATE,
+            WNDCLASSW,
         },
     },
 };
 
+static CLASS_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
 /// Structs using this type may never implement `Unpin`!
 pub struct BaseWindow<'a, T>
 where
@@ -37,21 +41,38 @@ where
     pub fn new() -> Result<Pin<Box<Self>>, windows::core::Error> {
         let hmodule = unsafe { GetModuleHandleW(PCWSTR::null())? };
 
-        let mut precise_time_value = 0;
-        let _ = unsafe { QueryPerformanceCounter(&mut precise_time_value) };
-
-        let class_atom = unsafe {
-            RegisterClassW(&WNDCLASSW {
-                lpfnWndProc: Some(Self::window_procedure),
-                hInstance: hmodule.into(),
-                lpszClassName: PCWSTR(
-                    HSTRING::from(format!("Win32WindowByRust_{precise_time_value:x}")).as_ptr(),
-                ),
-                ..Default::default()
-            })
-        };
+        // The counter makes the class name unique within the process, which is the scope of application-local classes. If the name is taken nonetheless, the registration is retried with the next counter value.
+        let mut class_atom = 0;
+        for _ in 0..8 {
+            let mut precise_time_value = 0;
+            let _ = unsafe { QueryPerformanceCounter(&mut precise_time_value) };
+            let counter = CLASS_COUNTER.fetch_add(1, Ordering::Relaxed);
+
+            class_atom = unsafe {
+                RegisterClassW(&WNDCLASSW {
+                    lpfnWndProc: Some(Self::window_procedure),
+                    hInstance: hmodule.into(),
+                    lpszClassName: PCWSTR(
+                        HSTRING::from(format!(
+                            "Win32WindowByRust_{precise_time_value:x}_{counter}"
+                        ))
+                        .as_ptr(),
+                    ),
+                    ..Default::default()
+                })
+            };
+
+            if class_atom != 0 {
+                break;
+            }
+
+            let error = windows::core::Error::from_win32();
+            if error.code() != ERROR_CLASS_ALREADY_EXISTS.to_hresult() {
+                return Err(error);
+            }
+        }
         if class_atom == 0 {
-            return Err(windows::core::Error::from_win32());
+            return Err(ERROR_CLASS_ALREADY_EXISTS.into());
         }
 
         let instance = Self {
@@ -174,6 +195,8 @@ pub trait OnWindowMsg {
     ) -> Option<LRESULT>;
 }
 
+// (Unused since the context menu returns the selected item directly, but kept for windows that need `WM_COMMAND`.)
+#[allow(dead_code)]
 pub fn translate_command_msg(wparam: WPARAM, lparam: LPARAM) -> CommandMsg {
     let wparam_hiword = (wparam.0 >> 16 & 0xffff) as u16;
     let wparam_loword = (wparam.0 & 0xffff) as u16;
@@ -189,6 +212,7 @@ pub fn translate_command_msg(wparam: WPARAM, lparam: LPARAM) -> CommandMsg {
     }
 }
 
+#[allow(dead_code)]
 pub enum CommandMsg {
     MenuItem {
         id: u16,
@@ -202,3 +226,26 @@ pub enum CommandMsg {
         control_hwnd: HWND,
     },
 }
+
+pub fn translate_sys_command_msg(wparam: WPARAM, _lparam: LPARAM) -> SystemCommand {
+    //! For `WM_SYSCOMMAND`. To keep the default behavior, e.g., for `SystemCommand::Other`, the message must be passed on to `DefWindowProcW()`.
+
+    // The four low-order bits are used internally by the system.
+    match (wparam.0 & 0xfff0) as u32 {
+        SC_MINIMIZE => SystemCommand::Minimize,
+        SC_MAXIMIZE => SystemCommand::Maximize,
+        SC_RESTORE => SystemCommand::Restore,
+        SC_CLOSE => SystemCommand::Close,
+        _ => SystemCommand::Other,
+    }
+}
+
+/// A command from the window menu, the title bar buttons or the corresponding shortcuts, as sent via `WM_SYSCOMMAND`.
+pub enum SystemCommand {
+    Minimize,
+    Maximize,
+    Restore,
+    Close,
+    /// Any other `SC_*` value, which remains in `WPARAM`.
+    Other,
+}
@@ -1,25 +1,34 @@
-use std::{borrow::Cow, marker::PhantomData};
+use std::{borrow::Cow, marker::PhantomData, mem::size_of};
 
 use anyhow::Result;
 use num_traits::{FromPrimitive, ToPrimitive};
 use windows::{
-    core::{HSTRING, PCWSTR},
+    core::{HSTRING, PWSTR},
     Win32::{
         Foundation::{E_FAIL, HWND, LPARAM, WPARAM},
+        Graphics::Gdi::{DeleteObject, HBITMAP},
         UI::WindowsAndMessaging::{
-            CreatePopupMenu, DestroyMenu, GetSystemMetrics, InsertMenuW, PostMessageW,
-            SetForegroundWindow, SetMenuDefaultItem, TrackPopupMenuEx, HMENU, MF_BYPOSITION,
-            MF_STRING, SM_MENUDROPALIGNMENT, TPM_BOTTOMALIGN, TPM_LEFTALIGN, TPM_RIGHTALIGN,
-            TPM_RIGHTBUTTON, WM_NULL,
+            CreatePopupMenu, DestroyMenu, GetSystemMetrics, InsertMenuItemW, PostMessageW,
+            SetForegroundWindow, SetMenuDefaultItem, TrackPopupMenuEx, HICON, HMENU, MENUITEMINFOW,
+            MFS_ENABLED, MFS_GRAYED, MFT_SEPARATOR, MIIM_BITMAP, MIIM_FTYPE, MIIM_ID, MIIM_STATE,
+            MIIM_STRING, SM_MENUDROPALIGNMENT, TPM_BOTTOMALIGN, TPM_LEFTALIGN, TPM_NONOTIFY,
+            TPM_RETURNCMD, TPM_RIGHTALIGN, TPM_RIGHTBUTTON, WM_NULL,
         },
     },
 };
 
+use crate::win32::icon::create_menu_bitmap;
+
+/// Added to the enum value to get the menu item ID, since `TrackPopupMenuEx()` returns 0 when the menu is canceled.
+const ITEM_ID_OFFSET: u32 = 1;
+
 pub struct ContextMenu<T>
 where
     T: FromPrimitive + ToPrimitive,
 {
     hmenu: HMENU,
+    /// The item icons, which the menu doesn't own.
+    hbitmaps: Vec<HBITMAP>,
     event_hwnd: HWND,
     _phantom_data: PhantomData<T>,
 }
@@ -29,88 +38,296 @@ where
     T: FromPrimitive + ToPrimitive,
 {
     pub fn new(
-        items: Vec<(T, Cow<str>)>,
+        entries: Vec<MenuEntry<T>>,
         default_item: T,
         event_hwnd: HWND,
     ) -> Result<Self, windows::core::Error> {
+        let (hmenu, hbitmaps) = Self::create_hmenu(entries, default_item)?;
+
+        Ok(Self {
+            hmenu,
+            hbitmaps,
+            event_hwnd,
+            _phantom_data: PhantomData,
+        })
+    }
+
+    pub fn rebuild(
+        &mut self,
+        entries: Vec<MenuEntry<T>>,
+        default_item: T,
+    ) -> Result<(), windows::core::Error> {
+        //! Replaces all entries. The IDs stay stable, since they're derived from the enum variants. On error, the previous menu is kept.
+
+        let (hmenu, hbitmaps) = Self::create_hmenu(entries, default_item)?;
+        self.destroy_hmenu();
+        self.hmenu = hmenu;
+        self.hbitmaps = hbitmaps;
+
+        Ok(())
+    }
+
+    fn create_hmenu(
+        mut entries: Vec<MenuEntry<T>>,
+        default_item: T,
+    ) -> Result<(HMENU, Vec<HBITMAP>), windows::core::Error> {
+        assign_mnemonics(&mut entries);
+
         let hmenu = unsafe { CreatePopupMenu()? };
+        let mut hbitmaps = Vec::new();
 
         let mut result = Ok(());
-        for (enum_variant, text) in items {
-            let id = match enum_variant.to_u32() {
-                Some(id) => id,
-                None => {
-                    result = Err(E_FAIL.into());
-                    break;
+        for entry in entries {
+            let insert_result = match entry {
+                MenuEntry::Item {
+                    id,
+                    text,
+                    enabled,
+                    icon,
+                } => {
+                    let id = match item_id(&id) {
+                        Some(id) => id,
+                        None => {
+                            result = Err(E_FAIL.into());
+                            break;
+                        }
+                    };
+
+                    let text = HSTRING::from(&*text);
+                    let mut item_info = MENUITEMINFOW {
+                        cbSize: size_of::<MENUITEMINFOW>() as _,
+                        fMask: MIIM_ID | MIIM_STRING | MIIM_STATE,
+                        wID: id,
+                        // (A grayed item can't be selected.)
+                        fState: if enabled { MFS_ENABLED } else { MFS_GRAYED },
+                        // (Only read.)
+                        dwTypeData: PWSTR(text.as_ptr() as _),
+                        ..Default::default()
+                    };
+
+                    // (An icon that can't be converted is left out.)
+                    if let Some(hbitmap) = icon.and_then(|hicon| create_menu_bitmap(hicon).ok()) {
+                        item_info.fMask |= MIIM_BITMAP;
+                        item_info.hbmpItem = hbitmap;
+                        hbitmaps.push(hbitmap);
+                    }
+
+                    unsafe { InsertMenuItemW(hmenu, u32::MAX, true, &item_info) }
                 }
+                MenuEntry::Separator => unsafe {
+                    InsertMenuItemW(
+                        hmenu,
+                        u32::MAX,
+                        true,
+                        &MENUITEMINFOW {
+                            cbSize: size_of::<MENUITEMINFOW>() as _,
+                            fMask: MIIM_FTYPE,
+                            fType: MFT_SEPARATOR,
+                            ..Default::default()
+                        },
+                    )
+                },
             };
 
-            if let Err(error) = unsafe {
-                InsertMenuW(
-                    hmenu,
-                    u32::MAX,
-                    MF_BYPOSITION | MF_STRING,
-                    id as _,
-                    PCWSTR(HSTRING::from(&*text).as_ptr()),
-                )
-            } {
+            if let Err(error) = insert_result {
                 result = Err(error);
                 break;
             }
         }
 
         if let Ok(()) = result {
-            if let Some(id) = default_item.to_u32() {
+            if let Some(id) = item_id(&default_item) {
                 result = unsafe { SetMenuDefaultItem(hmenu, id, false.into()) };
             }
         }
 
         if let Err(error) = result {
             let _ = unsafe { DestroyMenu(hmenu) };
+            for hbitmap in hbitmaps {
+                let _ = unsafe { DeleteObject(hbitmap) };
+            }
+
             return Err(error);
         }
 
-        Ok(Self {
-            hmenu,
-            event_hwnd,
-            _phantom_data: PhantomData,
-        })
+        Ok((hmenu, hbitmaps))
     }
 
-    pub fn show(&mut self, x: i32, y: i32) {
-        //! Shows the context menu at the specified virtual-screen coordinates and blocks the call site until the menu is hidden. The event window will receive a `WM_COMMAND` message with the result.
+    fn destroy_hmenu(&mut self) {
+        let _ = unsafe { DestroyMenu(self.hmenu) };
+        for hbitmap in self.hbitmaps.drain(..) {
+            let _ = unsafe { DeleteObject(hbitmap) };
+        }
+    }
+
+    pub fn show(&mut self, x: i32, y: i32) -> Option<T> {
+        //! Shows the context menu at the specified virtual-screen coordinates and blocks the call site until the menu is hidden. Returns the selected item, or `None`, if the menu was canceled.
 
         unsafe {
             SetForegroundWindow(self.event_hwnd); // Doesn't seem to matter whether it's invisible.
 
+            // (With `TPM_RETURNCMD`, the return value is the selected item's ID instead of a success flag, and no `WM_COMMAND` is posted.)
             //TODO: See <https://github.com/microsoft/win32metadata/issues/1783>.
-            let _ = TrackPopupMenuEx(
+            let id = TrackPopupMenuEx(
                 self.hmenu,
                 (if GetSystemMetrics(SM_MENUDROPALIGNMENT) != 0 {
                     TPM_RIGHTALIGN
                 } else {
                     TPM_LEFTALIGN
                 } | TPM_BOTTOMALIGN
-                    | TPM_RIGHTBUTTON)
+                    | TPM_RIGHTBUTTON
+                    | TPM_NONOTIFY
+                    | TPM_RETURNCMD)
                     .0,
                 x,
                 y,
                 self.event_hwnd,
                 None,
-            );
+            )
+            .0 as u32;
 
             let _ = PostMessageW(self.event_hwnd, WM_NULL, WPARAM(0), LPARAM(0));
 
             // (For reasons for `SetForegroundWindow()` and `PostMessageW()`, see: https://learn.microsoft.com/en-us/windows/win32/api/winuser/nf-winuser-trackpopupmenu#remarks.)
+
+            id.checked_sub(ITEM_ID_OFFSET).and_then(T::from_u32)
         }
     }
 }
 
+fn item_id<T: ToPrimitive>(item: &T) -> Option<u32> {
+    item.to_u32()?.checked_add(ITEM_ID_OFFSET)
+}
+
+pub enum MenuEntry<'a, T> {
+    /// An item identified by the enum variant. Its text can contain an `&` before the mnemonic character; a literal `&` must be doubled. Enabled items without a mnemonic, or with one already used by a previous item, get the first available letter or digit of their text. The icon is copied, so it stays owned by the caller.
+    Item {
+        id: T,
+        text: Cow<'a, str>,
+        enabled: bool,
+        icon: Option<HICON>,
+    },
+    Separator,
+}
+
+impl<'a, T> MenuEntry<'a, T> {
+    pub fn item(id: T, text: impl Into<Cow<'a, str>>) -> Self {
+        //! An enabled item.
+
+        Self::Item {
+            id,
+            text: text.into(),
+            enabled: true,
+            icon: None,
+        }
+    }
+}
+
+fn assign_mnemonics<T>(entries: &mut [MenuEntry<T>]) {
+    let mut used_mnemonics = Vec::new();
+    let mut items_without_mnemonic = Vec::new();
+
+    for (index, entry) in entries.iter_mut().enumerate() {
+        if let MenuEntry::Item {
+            text,
+            enabled: true,
+            ..
+        } = entry
+        {
+            match mnemonic_position(text) {
+                Some(position) => {
+                    let mnemonic = text[position + 1..].chars().next().map(mnemonic_key);
+                    if used_mnemonics.contains(&mnemonic) {
+                        // (Duplicate mnemonics would require pressing the key repeatedly.)
+                        text.to_mut().remove(position);
+                        items_without_mnemonic.push(index);
+                    } else {
+                        used_mnemonics.push(mnemonic);
+                    }
+                }
+                None => items_without_mnemonic.push(index),
+            }
+        }
+    }
+
+    for index in items_without_mnemonic {
+        if let MenuEntry::Item { text, .. } = &mut entries[index] {
+            let available = text.char_indices().find(|&(_, char)| {
+                char.is_alphanumeric() && !used_mnemonics.contains(&Some(mnemonic_key(char)))
+            });
+
+            if let Some((position, char)) = available {
+                used_mnemonics.push(Some(mnemonic_key(char)));
+                text.to_mut().insert(position, '&');
+            }
+        }
+    }
+}
+
+fn mnemonic_position(text: &str) -> Option<usize> {
+    //! The byte position of the `&` that marks the mnemonic, skipping `&&`.
+
+    let mut char_indices = text.char_indices();
+    while let Some((position, char)) = char_indices.next() {
+        if char == '&' {
+            match char_indices.next() {
+                Some((_, '&')) => {}
+                Some(_) => return Some(position),
+                None => break,
+            }
+        }
+    }
+
+    None
+}
+
+fn mnemonic_key(char: char) -> char {
+    char.to_lowercase().next().unwrap_or(char)
+}
+
 impl<T> Drop for ContextMenu<T>
 where
     T: FromPrimitive + ToPrimitive,
 {
     fn drop(&mut self) {
-        let _ = unsafe { DestroyMenu(self.hmenu) };
+        self.destroy_hmenu();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assign_mnemonics, MenuEntry};
+
+    fn texts(entries: &[MenuEntry<u32>]) -> Vec<&str> {
+        entries
+            .iter()
+            .filter_map(|entry| match entry {
+                MenuEntry::Item { text, .. } => Some(&**text),
+                MenuEntry::Separator => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn assigns_unique_mnemonics() {
+        let mut entries = vec![
+            MenuEntry::item(0, "&Show/Hide"),
+            MenuEntry::Separator,
+            MenuEntry::item(1, "Settings"),
+            MenuEntry::item(2, "&Save && Exit"),
+            MenuEntry::item(3, "&&"),
+            MenuEntry::Item {
+                id: 4,
+                text: "Status".into(),
+                enabled: false,
+                icon: None,
+            },
+        ];
+        assign_mnemonics(&mut entries);
+
+        assert_eq!(
+            texts(&entries),
+            ["&Show/Hide", "S&ettings", "S&ave && Exit", "&&", "Status"]
+        );
     }
 }
@@ -0,0 +1,47 @@
+use std::{
+    ffi::{OsStr, OsString},
+    os::windows::prelude::OsStringExt,
+};
+use windows::{core::HSTRING, Win32::System::Environment::ExpandEnvironmentStringsW};
+
+pub fn expand_env_vars(string: &OsStr) -> OsString {
+    //! Replaces `%NAME%` references with the values of the environment variables, like `cmd` does. References to undefined variables are kept as they are. If the expansion fails, the string is returned unchanged.
+
+    let source = HSTRING::from(string);
+    let mut buffer = vec![0; source.len() + 1];
+
+    // (The variables could change between calls.)
+    for _ in 0..4 {
+        // Includes the terminating null character.
+        let len = unsafe { ExpandEnvironmentStringsW(&source, Some(&mut buffer)) } as usize;
+        if len == 0 {
+            break;
+        } else if len <= buffer.len() {
+            return OsString::from_wide(&buffer[..len - 1]);
+        }
+
+        buffer.resize(len, 0);
+    }
+
+    string.to_os_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, ffi::OsStr};
+
+    use super::expand_env_vars;
+
+    #[test]
+    fn expands_defined_and_keeps_undefined_variables() {
+        env::set_var("TRAY_VALET_TEST_VAR", "C:\\Some Folder");
+        env::remove_var("TRAY_VALET_TEST_UNDEFINED");
+
+        assert_eq!(
+            expand_env_vars(OsStr::new(
+                "%TRAY_VALET_TEST_VAR%\\app.exe %TRAY_VALET_TEST_UNDEFINED% 100%"
+            )),
+            "C:\\Some Folder\\app.exe %TRAY_VALET_TEST_UNDEFINED% 100%"
+        );
+    }
+}
@@ -0,0 +1,161 @@
+use windows::Win32::{
+    Foundation::HWND,
+    UI::Input::KeyboardAndMouse::{
+        RegisterHotKey, UnregisterHotKey, HOT_KEY_MODIFIERS, MOD_ALT, MOD_CONTROL, MOD_NOREPEAT,
+        MOD_SHIFT, MOD_WIN, VIRTUAL_KEY, VK_BACK, VK_DELETE, VK_DOWN, VK_END, VK_ESCAPE, VK_F1,
+        VK_HOME, VK_INSERT, VK_LEFT, VK_NEXT, VK_PAUSE, VK_PRIOR, VK_RETURN, VK_RIGHT, VK_SPACE,
+        VK_TAB, VK_UP,
+    },
+};
+
+/// A key combination like `Ctrl+Alt+R` that can be registered system-wide.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Hotkey {
+    pub modifiers: HOT_KEY_MODIFIERS,
+    pub key: VIRTUAL_KEY,
+}
+
+impl Hotkey {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        //! Parses modifiers (`Ctrl`, `Alt`, `Shift`, `Win`) and a key, separated by `+`. The key is a letter, a digit, `F1` to `F24` or a name like `Space`, `Enter` or `PageUp`. Case-insensitive.
+
+        let error = || format!("\"{value}\" isn't a hotkey like Ctrl+Alt+R.");
+
+        let mut parts = value.split('+').map(str::trim).collect::<Vec<_>>();
+        let key = parts.pop().and_then(virtual_key).ok_or_else(error)?;
+
+        let mut modifiers = HOT_KEY_MODIFIERS(0);
+        for part in parts {
+            modifiers |= match part.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => MOD_CONTROL,
+                "alt" => MOD_ALT,
+                "shift" => MOD_SHIFT,
+                "win" => MOD_WIN,
+                _ => return Err(error()),
+            };
+        }
+
+        Ok(Self { modifiers, key })
+    }
+}
+
+fn virtual_key(name: &str) -> Option<VIRTUAL_KEY> {
+    let name = name.to_ascii_uppercase();
+
+    // (The virtual-key codes of letters and digits are their uppercase ASCII codes.)
+    if let [char] = name.as_bytes() {
+        return char
+            .is_ascii_alphanumeric()
+            .then(|| VIRTUAL_KEY(*char as _));
+    }
+
+    if let Some(number) = name
+        .strip_prefix('F')
+        .and_then(|number| number.parse::<u16>().ok())
+    {
+        return (1..=24)
+            .contains(&number)
+            .then(|| VIRTUAL_KEY(VK_F1.0 + number - 1));
+    }
+
+    Some(match name.as_str() {
+        "SPACE" => VK_SPACE,
+        "ENTER" => VK_RETURN,
+        "TAB" => VK_TAB,
+        "ESC" | "ESCAPE" => VK_ESCAPE,
+        "BACKSPACE" => VK_BACK,
+        "INS" | "INSERT" => VK_INSERT,
+        "DEL" | "DELETE" => VK_DELETE,
+        "HOME" => VK_HOME,
+        "END" => VK_END,
+        "PAGEUP" => VK_PRIOR,
+        "PAGEDOWN" => VK_NEXT,
+        "UP" => VK_UP,
+        "DOWN" => VK_DOWN,
+        "LEFT" => VK_LEFT,
+        "RIGHT" => VK_RIGHT,
+        "PAUSE" => VK_PAUSE,
+        _ => return None,
+    })
+}
+
+/// A hotkey that makes the window receive `WM_HOTKEY` with the ID as `WPARAM`. Unregistered when dropped.
+pub struct HotkeyRegistration {
+    hwnd: HWND,
+    id: i32,
+}
+
+impl HotkeyRegistration {
+    pub fn new(hwnd: HWND, id: i32, hotkey: Hotkey) -> Result<Self, windows::core::Error> {
+        //! Fails, if another app already registered the key combination. Holding the keys down doesn't repeat the message.
+
+        unsafe {
+            RegisterHotKey(
+                hwnd,
+                id,
+                hotkey.modifiers | MOD_NOREPEAT,
+                hotkey.key.0.into(),
+            )?
+        };
+
+        Ok(Self { hwnd, id })
+    }
+}
+
+impl Drop for HotkeyRegistration {
+    fn drop(&mut self) {
+        let _ = unsafe { UnregisterHotKey(self.hwnd, self.id) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Win32::UI::Input::KeyboardAndMouse::{
+        HOT_KEY_MODIFIERS, MOD_ALT, MOD_CONTROL, MOD_SHIFT, MOD_WIN, VIRTUAL_KEY, VK_F12, VK_NEXT,
+        VK_SPACE,
+    };
+
+    use super::Hotkey;
+
+    #[test]
+    fn parses_hotkeys() {
+        assert_eq!(
+            Hotkey::parse("Ctrl+Alt+R"),
+            Ok(Hotkey {
+                modifiers: MOD_CONTROL | MOD_ALT,
+                key: VIRTUAL_KEY('R' as _),
+            })
+        );
+        assert_eq!(
+            Hotkey::parse("win + shift + 7"),
+            Ok(Hotkey {
+                modifiers: MOD_WIN | MOD_SHIFT,
+                key: VIRTUAL_KEY('7' as _),
+            })
+        );
+        assert_eq!(
+            Hotkey::parse("F12"),
+            Ok(Hotkey {
+                modifiers: HOT_KEY_MODIFIERS(0),
+                key: VK_F12,
+            })
+        );
+        assert_eq!(
+            Hotkey::parse("CONTROL+PageDown").map(|hotkey| hotkey.key),
+            Ok(VK_NEXT)
+        );
+        assert_eq!(
+            Hotkey::parse("Alt+Space").map(|hotkey| hotkey.key),
+            Ok(VK_SPACE)
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_hotkeys() {
+        for value in [
+            "", "Ctrl+", "Ctrl+Alt", "Hyper+R", "Ctrl+F25", "Ctrl+RR", "Ctrl+ä",
+        ] {
+            assert!(Hotkey::parse(value).is_err(), "{value}");
+        }
+    }
+}
@@ -1,35 +1,114 @@
-use std::{io, mem::size_of, path::Path};
+#[cfg(debug_assertions)]
+use std::sync::atomic::{AtomicIsize, Ordering};
+use std::{
+    io,
+    mem::size_of,
+    path::{Path, PathBuf},
+    ptr, slice,
+    sync::Mutex,
+};
 use windows::{
     core::{h, HSTRING, PCWSTR},
     Win32::{
-        Foundation::{ERROR_FILE_NOT_FOUND, E_FAIL, HANDLE},
-        Graphics::Gdi::{MonitorFromWindow, MONITOR_DEFAULTTOPRIMARY},
+        Foundation::{COLORREF, ERROR_FILE_NOT_FOUND, E_FAIL, HANDLE, RECT, SIZE},
+        Graphics::Gdi::{
+            CreateBitmap, CreateCompatibleDC, CreateDIBSection, CreateFontIndirectW, DeleteDC,
+            DeleteObject, DrawTextW, GdiFlush, GetTextExtentPoint32W, MonitorFromWindow,
+            SelectObject, SetBkMode, SetTextColor, ANTIALIASED_QUALITY, BITMAPINFO,
+            BITMAPINFOHEADER, BI_RGB, DIB_RGB_COLORS, DT_CENTER, DT_NOPREFIX, DT_SINGLELINE,
+            DT_VCENTER, FW_BOLD, HBITMAP, HBRUSH, HDC, HGDIOBJ, HMONITOR, LOGFONTW,
+            MONITOR_DEFAULTTOPRIMARY, TRANSPARENT,
+        },
         Storage::FileSystem::FILE_FLAGS_AND_ATTRIBUTES,
+        System::{
+            LibraryLoader::GetModuleHandleW,
+            Registry::{RegGetValueW, HKEY_CURRENT_USER, RRF_RT_REG_DWORD},
+        },
         UI::{
             HiDpi::{GetDpiForMonitor, GetSystemMetricsForDpi, MDT_EFFECTIVE_DPI},
             Shell::{
                 SHDefExtractIconW, SHGetFileInfoW, SHGetStockIconInfo, SHFILEINFOW, SHGFI_ICON,
                 SHGFI_LARGEICON, SHGFI_SMALLICON, SHGSI_ICON, SHGSI_LARGEICON, SHGSI_SMALLICON,
-                SHSTOCKICONINFO, SIID_DOCNOASSOC,
+                SHSTOCKICONID, SHSTOCKICONINFO, SIID_APPLICATION, SIID_DOCNOASSOC, SIID_FOLDER,
             },
             WindowsAndMessaging::{
-                CopyImage, FindWindowW, HICON, IMAGE_FLAGS, IMAGE_ICON, SM_CXICON, SM_CXSMICON,
-                SM_CYICON, SM_CYSMICON,
+                CopyImage, CreateIconIndirect, DestroyIcon, DrawIconEx, FindWindowW, LoadImageW,
+                DI_NORMAL, HICON, ICONINFO, IMAGE_FLAGS, IMAGE_ICON, LR_DEFAULTCOLOR, SM_CXICON,
+                SM_CXSMICON, SM_CYICON, SM_CYSMICON,
             },
         },
     },
 };
 
-pub fn load_tray_monitor_icon<T>(file_path: T, large: bool) -> Result<HICON, windows::core::Error>
+use crate::win32::environment::expand_env_vars;
+
+/// Marks a path as referring to an icon resource of this executable file.
+const RESOURCE_PATH_PREFIX: &str = "res://";
+
+/// The canonical file path, whether the icon is large and the size in pixels.
+type IconCacheKey = (PathBuf, bool, i32);
+
+/// Icons loaded from files, since multiple apps from `--config` often share executable files. Shared with the icon loading threads.
+static ICON_CACHE: Mutex<Vec<(IconCacheKey, HICON)>> = Mutex::new(Vec::new());
+
+/// The badge color of `create_badged_icon()` as RGB.
+const BADGE_COLOR: [u8; 3] = [0xd1, 0x34, 0x38];
+/// The colors of `create_monochrome_icon()` as RGB, like the system's tray icons on a dark and a light taskbar.
+const DARK_THEME_GLYPH_COLOR: [u8; 3] = [0xff, 0xff, 0xff];
+const LIGHT_THEME_GLYPH_COLOR: [u8; 3] = [0x00, 0x00, 0x00];
+
+/// The number of icons from this module's functions that weren't destroyed with `destroy_hicon()` yet, to catch leaks and double frees during development.
+#[cfg(debug_assertions)]
+static LIVE_HICON_COUNT: AtomicIsize = AtomicIsize::new(0);
+
+pub fn load_tray_monitor_icon<T>(
+    file_path: T,
+    large: bool,
+    size: Option<u32>,
+) -> Result<HICON, windows::core::Error>
 where
     T: AsRef<Path>,
 {
-    //! Returned `HICON` must be destroyed with `DestroyIcon()`.
+    //! Returned `HICON` must be destroyed with `destroy_hicon()`. Icons from files are cached, so that loading the same one again only copies it.
+    //!
+    //! With `size`, the icon is extracted with this width and height in physical pixels instead of the size from the system metrics, for shells with non-standard tray icon sizes. The fallback icons are unaffected.
+    //!
+    //! Environment variables like `%USERPROFILE%` in the path are expanded. A path like `res://1` or `res://NAME` refers to an icon resource of this executable file by ID or name, without fallback icons.
     //!
     //! Paths longer than `MAX_PATH` don't work. More on the problem: https://www.zabkat.com/blog/max-path-programmers-cookbook.htm.
 
-    let file_path = match dunce::canonicalize(file_path) {
-        Ok(path) => HSTRING::from(&*path),
+    let small_icon_size = match size {
+        Some(size) => size as i32,
+        None => {
+            // Get icon size - specifically for monitor with main taskbar that displays the tray.
+            let dpi = get_tray_monitor_dpi();
+
+            let small_icon_width =
+                unsafe { GetSystemMetricsForDpi(if large { SM_CXICON } else { SM_CXSMICON }, dpi) };
+            if small_icon_width == 0 {
+                return Err(windows::core::Error::from_win32());
+            }
+
+            let small_icon_height =
+                unsafe { GetSystemMetricsForDpi(if large { SM_CYICON } else { SM_CYSMICON }, dpi) };
+            if small_icon_height == 0 {
+                return Err(windows::core::Error::from_win32());
+            }
+
+            (small_icon_width + small_icon_height) / 2
+        }
+    };
+
+    if let Some(resource_name) = file_path
+        .as_ref()
+        .to_str()
+        .and_then(|path| path.strip_prefix(RESOURCE_PATH_PREFIX))
+    {
+        return load_resource_icon(resource_name, small_icon_size);
+    }
+
+    let file_path = match dunce::canonicalize(expand_env_vars(file_path.as_ref().as_os_str())) {
+        Ok(path) => path,
         Err(io_error) => {
             return Err(match io_error.kind() {
                 io::ErrorKind::NotFound => ERROR_FILE_NOT_FOUND.to_hresult(),
@@ -39,23 +118,101 @@ where
         }
     };
 
-    // Get icon size - specifically for monitor with main taskbar that displays the tray.
-    let dpi = get_tray_monitor_dpi();
+    // Hand out a copy of a cached icon...
+    let cache_key = (file_path, large, small_icon_size);
+    if let Some(hicon) = cached_icon(&cache_key) {
+        return duplicate_hicon(hicon);
+    }
+
+    // ...or extract and cache it.
+    let hicon = extract_file_icon(&HSTRING::from(&*cache_key.0), large, small_icon_size)?;
+    Ok(cache_icon(cache_key, hicon))
+}
+
+fn cached_icon(key: &IconCacheKey) -> Option<HICON> {
+    ICON_CACHE
+        .lock()
+        .ok()?
+        .iter()
+        .find_map(|(cached_key, hicon)| (cached_key == key).then_some(*hicon))
+}
+
+fn cache_icon(key: IconCacheKey, hicon: HICON) -> HICON {
+    //! Returns a copy of the icon, while the cache keeps the original. If it can't be copied, the original is returned uncached.
+
+    let copy = match duplicate_hicon(hicon) {
+        Ok(copy) => copy,
+        Err(_) => return hicon,
+    };
+
+    match ICON_CACHE.lock() {
+        Ok(mut cache) => {
+            // (Another thread may have cached the same icon meanwhile.)
+            if cache.iter().any(|(cached_key, _)| *cached_key == key) {
+                destroy_hicon(hicon);
+            } else {
+                cache.push((key, hicon));
+            }
 
-    let small_icon_width =
-        unsafe { GetSystemMetricsForDpi(if large { SM_CXICON } else { SM_CXSMICON }, dpi) };
-    if small_icon_width == 0 {
-        return Err(windows::core::Error::from_win32());
+            copy
+        }
+        Err(_) => {
+            destroy_hicon(copy);
+            hicon
+        }
     }
+}
 
-    let small_icon_height =
-        unsafe { GetSystemMetricsForDpi(if large { SM_CYICON } else { SM_CYSMICON }, dpi) };
-    if small_icon_height == 0 {
-        return Err(windows::core::Error::from_win32());
+pub fn clear_icon_cache() {
+    //! Destroys the cached icons. Should be called before the process exits.
+
+    if let Ok(mut cache) = ICON_CACHE.lock() {
+        for (_, hicon) in cache.drain(..) {
+            destroy_hicon(hicon);
+        }
     }
+}
 
-    let small_icon_size = (small_icon_width + small_icon_height) / 2;
+pub fn destroy_hicon(hicon: HICON) {
+    //! Destroys an icon from this module's functions, so that it's accounted for in debug builds. Zero handles are ignored.
 
+    if hicon.is_invalid() {
+        return;
+    }
+
+    #[cfg(debug_assertions)]
+    LIVE_HICON_COUNT.fetch_sub(1, Ordering::Relaxed);
+
+    let _ = unsafe { DestroyIcon(hicon) };
+}
+
+pub fn assert_all_hicons_destroyed() {
+    //! In debug builds, panics if icons from this module weren't destroyed or were destroyed twice. To be called at process teardown, after `clear_icon_cache()`.
+
+    #[cfg(debug_assertions)]
+    debug_assert_eq!(
+        LIVE_HICON_COUNT.load(Ordering::Relaxed),
+        0,
+        "`HICON`s were leaked or destroyed twice"
+    );
+}
+
+fn count_hicon(hicon: HICON) -> HICON {
+    //! To be applied to every icon this module creates.
+
+    #[cfg(debug_assertions)]
+    if !hicon.is_invalid() {
+        LIVE_HICON_COUNT.fetch_add(1, Ordering::Relaxed);
+    }
+
+    hicon
+}
+
+fn extract_file_icon(
+    file_path: &HSTRING,
+    large: bool,
+    small_icon_size: i32,
+) -> Result<HICON, windows::core::Error> {
     // Obtain icon from file, with best size for monitor.
     let mut hicon = HICON(0);
 
@@ -75,7 +232,7 @@ where
     let def_extract_icon_error = if hicon.is_invalid() {
         windows::core::Error::from_win32()
     } else {
-        return Ok(hicon);
+        return Ok(count_hicon(hicon));
     };
 
     // ...or from a function that returns a file-type-based fallback icon when there are no icons in the file.
@@ -96,16 +253,389 @@ where
         )
     } != 0
     {
-        return Ok(file_info.hIcon);
+        return Ok(count_hicon(file_info.hIcon));
     }
 
     // ...or a fallback stock icon.
+    load_stock_icon(SIID_DOCNOASSOC, large).map_err(|_| def_extract_icon_error)
+}
+
+fn load_resource_icon(name: &str, size: i32) -> Result<HICON, windows::core::Error> {
+    //! A numeric name is used as ID. The icon isn't shared, so it can be destroyed like the others.
+
+    let hmodule = unsafe { GetModuleHandleW(PCWSTR::null())? };
+
+    let name_hstring;
+    let name = match name.parse::<u16>() {
+        // (Like `MAKEINTRESOURCEW()`.)
+        Ok(id) => PCWSTR(id as usize as _),
+        Err(_) => {
+            name_hstring = HSTRING::from(name);
+            PCWSTR(name_hstring.as_ptr())
+        }
+    };
+
+    unsafe { LoadImageW(hmodule, name, IMAGE_ICON, size, size, LR_DEFAULTCOLOR) }
+        .map(|handle| count_hicon(HICON(handle.0)))
+}
+
+pub fn load_placeholder_icon() -> Result<HICON, windows::core::Error> {
+    //! The small generic application icon, to be shown while the actual icon is loading. Must be destroyed with `destroy_hicon()`.
+
+    load_stock_icon(SIID_APPLICATION, false)
+}
+
+pub fn load_folder_icon() -> Result<HICON, windows::core::Error> {
+    //! The small folder icon. Must be destroyed with `destroy_hicon()`.
+
+    load_stock_icon(SIID_FOLDER, false)
+}
+
+pub fn create_menu_bitmap(hicon: HICON) -> Result<HBITMAP, windows::core::Error> {
+    //! Draws the icon onto a bitmap with alpha channel in the small icon size of the monitor with the taskbar, where the tray icon's context menu appears. Icons without alpha channel end up transparent. Returned `HBITMAP` must be deleted with `DeleteObject()`.
+
+    let size = tray_icon_size(None)?;
+    let hdc = unsafe { CreateCompatibleDC(HDC(0)) };
+    if hdc.is_invalid() {
+        return Err(E_FAIL.into());
+    }
+
+    let hbitmap = match create_dib_section(hdc, size) {
+        Ok((hbitmap, _)) => hbitmap,
+        Err(error) => {
+            let _ = unsafe { DeleteDC(hdc) };
+            return Err(error);
+        }
+    };
+
+    let result = unsafe {
+        let old_hgdiobj = SelectObject(hdc, hbitmap);
+        let result = DrawIconEx(hdc, 0, 0, hicon, size, size, 0, HBRUSH(0), DI_NORMAL);
+        SelectObject(hdc, old_hgdiobj);
+        let _ = DeleteDC(hdc);
+
+        result
+    };
+
+    if let Err(error) = result {
+        let _ = unsafe { DeleteObject(hbitmap) };
+        return Err(error);
+    }
+
+    Ok(hbitmap)
+}
+
+pub fn create_badged_icon(
+    hicon: HICON,
+    text: &str,
+    size: Option<u32>,
+) -> Result<HICON, windows::core::Error> {
+    //! Draws the icon in the small icon size of the monitor with the taskbar, or in `size`, with the text in white on a red badge in the bottom-right corner. The badge grows to the left with the text, up to the icon's width. Icons without alpha channel end up transparent, like with `create_menu_bitmap()`. Returned `HICON` must be destroyed with `destroy_hicon()`.
+
+    let size = tray_icon_size(size)?;
+    let hdc = unsafe { CreateCompatibleDC(HDC(0)) };
+    if hdc.is_invalid() {
+        return Err(E_FAIL.into());
+    }
+
+    let result = draw_badged_icon(hdc, hicon, text, size);
+    let _ = unsafe { DeleteDC(hdc) };
+
+    result.map(count_hicon)
+}
+
+fn draw_badged_icon(
+    hdc: HDC,
+    hicon: HICON,
+    text: &str,
+    size: i32,
+) -> Result<HICON, windows::core::Error> {
+    // (All GDI objects are deleted when going out of scope.)
+    let (icon_hbitmap, icon_bits) = create_dib_section(hdc, size)?;
+    let _icon_bitmap = OwnedGdiObject(HGDIOBJ(icon_hbitmap.0));
+    // (Text is drawn separately, since GDI text rendering doesn't preserve the alpha channel. Only its coverage is used.)
+    let (text_hbitmap, text_bits) = create_dib_section(hdc, size)?;
+    let _text_bitmap = OwnedGdiObject(HGDIOBJ(text_hbitmap.0));
+
+    let badge_height = (size * 3 / 5).max(1);
+    let mut log_font = LOGFONTW {
+        lfHeight: -(badge_height * 4 / 5).max(1),
+        lfWeight: FW_BOLD.0 as _,
+        lfQuality: ANTIALIASED_QUALITY,
+        ..Default::default()
+    };
+    for (dest, src) in log_font
+        .lfFaceName
+        .iter_mut()
+        .zip("Segoe UI".encode_utf16())
+    {
+        *dest = src;
+    }
+    let hfont = unsafe { CreateFontIndirectW(&log_font) };
+    if hfont.is_invalid() {
+        return Err(E_FAIL.into());
+    }
+    let _font = OwnedGdiObject(HGDIOBJ(hfont.0));
+
+    let mut wide_text = text.encode_utf16().collect::<Vec<_>>();
+    let badge_rect = unsafe {
+        let old_hbitmap = SelectObject(hdc, text_hbitmap);
+        let old_hfont = SelectObject(hdc, hfont);
+
+        let mut text_size = SIZE::default();
+        let _ = GetTextExtentPoint32W(hdc, &wide_text, &mut text_size);
+        let padding = badge_height / 4;
+        let badge_width = (text_size.cx + 2 * padding).clamp(badge_height, size);
+        let mut badge_rect = RECT {
+            left: size - badge_width,
+            top: size - badge_height,
+            right: size,
+            bottom: size,
+        };
+
+        SetTextColor(hdc, COLORREF(0xff_ff_ff));
+        SetBkMode(hdc, TRANSPARENT);
+        DrawTextW(
+            hdc,
+            &mut wide_text,
+            &mut badge_rect,
+            DT_CENTER | DT_VCENTER | DT_SINGLELINE | DT_NOPREFIX,
+        );
+
+        SelectObject(hdc, icon_hbitmap);
+        let result = DrawIconEx(hdc, 0, 0, hicon, size, size, 0, HBRUSH(0), DI_NORMAL);
+
+        SelectObject(hdc, old_hfont);
+        SelectObject(hdc, old_hbitmap);
+        let _ = GdiFlush();
+        result?;
+
+        badge_rect
+    };
+
+    let pixel_count = (size * size) as usize;
+    let icon_pixels = unsafe { slice::from_raw_parts_mut(icon_bits, pixel_count) };
+    let text_pixels = unsafe { slice::from_raw_parts(text_bits, pixel_count) };
+    composite_badge(icon_pixels, text_pixels, size, badge_rect);
+
+    create_icon_from_bitmap(icon_hbitmap, size)
+}
+
+fn composite_badge(icon_pixels: &mut [u32], text_pixels: &[u32], size: i32, badge_rect: RECT) {
+    //! Draws the pill-shaped badge with anti-aliased edges over the premultiplied BGRA pixels, with the text coverage from the green channel of `text_pixels`, then converts all pixels to straight alpha, as icons require.
+
+    let radius = (badge_rect.bottom - badge_rect.top) as f32 / 2.0;
+    let center_y = badge_rect.top as f32 + radius;
+    let (min_center_x, max_center_x) = (
+        badge_rect.left as f32 + radius,
+        (badge_rect.right as f32 - radius).max(badge_rect.left as f32 + radius),
+    );
+
+    for y in badge_rect.top.max(0)..badge_rect.bottom.min(size) {
+        for x in badge_rect.left.max(0)..badge_rect.right.min(size) {
+            let index = (y * size + x) as usize;
+
+            let (pixel_x, pixel_y) = (x as f32 + 0.5, y as f32 + 0.5);
+            let nearest_center_x = pixel_x.clamp(min_center_x, max_center_x);
+            let distance =
+                ((pixel_x - nearest_center_x).powi(2) + (pixel_y - center_y).powi(2)).sqrt();
+            let coverage = (radius - distance + 0.5).clamp(0.0, 1.0);
+            if coverage == 0.0 {
+                continue;
+            }
+
+            let text_coverage = (text_pixels[index] >> 8 & 0xff) as f32 / 255.0;
+            let badge_color = BADGE_COLOR
+                .map(|channel| channel as f32 + (255.0 - channel as f32) * text_coverage);
+
+            // Blend premultiplied: source plus background weighted by the remaining transparency.
+            let background = icon_pixels[index].to_le_bytes();
+            let source = [badge_color[2], badge_color[1], badge_color[0], 255.0];
+            let [blue, green, red, alpha] = [0, 1, 2, 3].map(|channel_index| {
+                (source[channel_index] * coverage
+                    + background[channel_index] as f32 * (1.0 - coverage))
+                    .round() as u8
+            });
+
+            icon_pixels[index] = u32::from_le_bytes([blue, green, red, alpha]);
+        }
+    }
+
+    for pixel in icon_pixels.iter_mut() {
+        let [blue, green, red, alpha] = pixel.to_le_bytes();
+        if alpha != 0 && alpha != 255 {
+            let [blue, green, red] = [blue, green, red]
+                .map(|channel| (channel as u32 * 255 / alpha as u32).min(255) as u8);
+            *pixel = u32::from_le_bytes([blue, green, red, alpha]);
+        }
+    }
+}
+
+pub fn create_monochrome_icon(
+    hicon: HICON,
+    size: Option<u32>,
+) -> Result<HICON, windows::core::Error> {
+    //! Draws the icon's silhouette in white or black, depending on the taskbar's theme, like the system's tray icons, in the small icon size of the monitor with the taskbar, or in `size`. Pixels that are at least half opaque make up the silhouette. Fails for icons without alpha channel, which have no silhouette. Returned `HICON` must be destroyed with `destroy_hicon()`.
+
+    let size = tray_icon_size(size)?;
+    let color = if taskbar_uses_light_theme() {
+        LIGHT_THEME_GLYPH_COLOR
+    } else {
+        DARK_THEME_GLYPH_COLOR
+    };
+
+    let hdc = unsafe { CreateCompatibleDC(HDC(0)) };
+    if hdc.is_invalid() {
+        return Err(E_FAIL.into());
+    }
+
+    let result = draw_monochrome_icon(hdc, hicon, size, color);
+    let _ = unsafe { DeleteDC(hdc) };
+
+    result.map(count_hicon)
+}
+
+fn draw_monochrome_icon(
+    hdc: HDC,
+    hicon: HICON,
+    size: i32,
+    color: [u8; 3],
+) -> Result<HICON, windows::core::Error> {
+    let (icon_hbitmap, icon_bits) = create_dib_section(hdc, size)?;
+    let _icon_bitmap = OwnedGdiObject(HGDIOBJ(icon_hbitmap.0));
+
+    unsafe {
+        let old_hbitmap = SelectObject(hdc, icon_hbitmap);
+        let result = DrawIconEx(hdc, 0, 0, hicon, size, size, 0, HBRUSH(0), DI_NORMAL);
+        SelectObject(hdc, old_hbitmap);
+        let _ = GdiFlush();
+        result?;
+    }
+
+    let icon_pixels = unsafe { slice::from_raw_parts_mut(icon_bits, (size * size) as usize) };
+    if !fill_silhouette(icon_pixels, color) {
+        return Err(E_FAIL.into());
+    }
+
+    create_icon_from_bitmap(icon_hbitmap, size)
+}
+
+fn fill_silhouette(pixels: &mut [u32], color: [u8; 3]) -> bool {
+    //! Makes the BGRA pixels that are at least half opaque fully opaque in the color, and all others fully transparent. Returns whether any pixel is opaque.
+
+    let opaque_pixel = u32::from_le_bytes([color[2], color[1], color[0], 255]);
+    let mut has_opaque_pixel = false;
+
+    for pixel in pixels.iter_mut() {
+        *pixel = if pixel.to_le_bytes()[3] >= 128 {
+            has_opaque_pixel = true;
+            opaque_pixel
+        } else {
+            0
+        };
+    }
+
+    has_opaque_pixel
+}
+
+fn taskbar_uses_light_theme() -> bool {
+    //! Whether the taskbar has the light theme. `false` on Windows versions before it was introduced, where the taskbar is always dark.
+
+    let mut value = 0_u32;
+    let mut value_size = size_of::<u32>() as u32;
+    let result = unsafe {
+        RegGetValueW(
+            HKEY_CURRENT_USER,
+            h!(r"Software\Microsoft\Windows\CurrentVersion\Themes\Personalize"),
+            h!("SystemUsesLightTheme"),
+            RRF_RT_REG_DWORD,
+            None,
+            Some(&mut value as *mut _ as _),
+            Some(&mut value_size),
+        )
+    };
+
+    result.is_ok() && value != 0
+}
+
+fn tray_icon_size(size: Option<u32>) -> Result<i32, windows::core::Error> {
+    //! `size`, or else the small icon size of the monitor with the taskbar.
+
+    match size {
+        Some(size) => Ok(size as i32),
+        None => {
+            let size = unsafe { GetSystemMetricsForDpi(SM_CXSMICON, get_tray_monitor_dpi()) };
+            if size == 0 {
+                return Err(windows::core::Error::from_win32());
+            }
+
+            Ok(size)
+        }
+    }
+}
+
+fn create_icon_from_bitmap(hbitmap: HBITMAP, size: i32) -> Result<HICON, windows::core::Error> {
+    //! Creates an icon from a square bitmap with straight alpha, which is copied.
+
+    // (Ignored for icons with alpha channel, but required.)
+    let mask_bits = vec![0_u8; ((size + 15) / 16 * 2 * size) as usize];
+    let mask_hbitmap = unsafe { CreateBitmap(size, size, 1, 1, Some(mask_bits.as_ptr() as _)) };
+    if mask_hbitmap.is_invalid() {
+        return Err(E_FAIL.into());
+    }
+    let _mask_bitmap = OwnedGdiObject(HGDIOBJ(mask_hbitmap.0));
+
+    // (Copies the bitmaps.)
+    unsafe {
+        CreateIconIndirect(&ICONINFO {
+            fIcon: true.into(),
+            hbmMask: mask_hbitmap,
+            hbmColor: hbitmap,
+            ..Default::default()
+        })
+    }
+}
+
+fn create_dib_section(hdc: HDC, size: i32) -> Result<(HBITMAP, *mut u32), windows::core::Error> {
+    //! A square, top-down bitmap with 32-bit BGRA pixels that are initially zero, i.e., transparent.
+
+    let bitmap_info = BITMAPINFO {
+        bmiHeader: BITMAPINFOHEADER {
+            biSize: size_of::<BITMAPINFOHEADER>() as _,
+            biWidth: size,
+            // (Negative for top-down.)
+            biHeight: -size,
+            biPlanes: 1,
+            biBitCount: 32,
+            biCompression: BI_RGB.0,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let mut bits = ptr::null_mut();
+    let hbitmap =
+        unsafe { CreateDIBSection(hdc, &bitmap_info, DIB_RGB_COLORS, &mut bits, HANDLE(0), 0)? };
+
+    Ok((hbitmap, bits as *mut u32))
+}
+
+/// Deletes the GDI object when dropped. It must not be selected into a device context anymore by then.
+struct OwnedGdiObject(HGDIOBJ);
+
+impl Drop for OwnedGdiObject {
+    fn drop(&mut self) {
+        let _ = unsafe { DeleteObject(self.0) };
+    }
+}
+
+fn load_stock_icon(id: SHSTOCKICONID, large: bool) -> Result<HICON, windows::core::Error> {
     let mut stock_icon_info = SHSTOCKICONINFO::default();
     stock_icon_info.cbSize = size_of::<SHSTOCKICONINFO>() as _;
 
-    match unsafe {
+    unsafe {
         SHGetStockIconInfo(
-            SIID_DOCNOASSOC,
+            id,
             SHGSI_ICON
                 | if large {
                     SHGSI_LARGEICON
@@ -113,14 +643,34 @@ where
                     SHGSI_SMALLICON
                 },
             &mut stock_icon_info,
-        )
-    } {
-        Ok(()) => Ok(stock_icon_info.hIcon),
-        Err(_) => Err(def_extract_icon_error),
+        )?
+    };
+
+    Ok(count_hicon(stock_icon_info.hIcon))
+}
+
+pub fn load_tray_monitor_icons<T>(
+    file_path: T,
+    small_size: Option<u32>,
+) -> Result<(HICON, HICON), windows::core::Error>
+where
+    T: AsRef<Path>,
+{
+    //! Loads the small and the large icon like `load_tray_monitor_icon()`. Fails if either fails. `small_size` only applies to the small icon.
+
+    let small_hicon = load_tray_monitor_icon(&file_path, false, small_size)?;
+    match load_tray_monitor_icon(&file_path, true, None) {
+        Ok(large_hicon) => Ok((small_hicon, large_hicon)),
+        Err(error) => {
+            destroy_hicon(small_hicon);
+            Err(error)
+        }
     }
 }
 
-fn get_tray_monitor_dpi() -> u32 {
+pub fn get_tray_monitor() -> HMONITOR {
+    //! The monitor with the main taskbar, which displays the tray.
+
     let hwnd = unsafe {
         FindWindowW(
             // Other taskbars have class `Shell_SecondaryTrayWnd`.
@@ -128,7 +678,12 @@ fn get_tray_monitor_dpi() -> u32 {
             PCWSTR(0 as _),
         )
     };
-    let hmonitor = unsafe { MonitorFromWindow(hwnd, MONITOR_DEFAULTTOPRIMARY) }; // `HWND(0)` should yield primary.
+
+    unsafe { MonitorFromWindow(hwnd, MONITOR_DEFAULTTOPRIMARY) } // `HWND(0)` should yield primary.
+}
+
+fn get_tray_monitor_dpi() -> u32 {
+    let hmonitor = get_tray_monitor();
 
     let mut dpi_x = 0;
     let mut dpi_y = 0;
@@ -140,5 +695,71 @@ fn get_tray_monitor_dpi() -> u32 {
 
 pub fn duplicate_hicon(hicon: HICON) -> Result<HICON, windows::core::Error> {
     unsafe { CopyImage(HANDLE(hicon.0), IMAGE_ICON, 0, 0, IMAGE_FLAGS(0)) }
-        .map(|handle| HICON(handle.0))
+        .map(|handle| count_hicon(HICON(handle.0)))
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Win32::Foundation::RECT;
+
+    use super::{composite_badge, fill_silhouette, BADGE_COLOR};
+
+    #[test]
+    fn composites_badge_with_straight_alpha() {
+        let size = 16;
+        let mut icon_pixels = vec![0; (size * size) as usize];
+        // (Half-transparent gray outside of the badge, premultiplied.)
+        icon_pixels[0] = u32::from_le_bytes([64, 64, 64, 128]);
+        let mut text_pixels = vec![0; (size * size) as usize];
+        text_pixels[12 * 16 + 12] = 0xff_ff_ff;
+
+        composite_badge(
+            &mut icon_pixels,
+            &text_pixels,
+            size,
+            RECT {
+                left: 6,
+                top: 8,
+                right: 16,
+                bottom: 16,
+            },
+        );
+
+        assert_eq!(icon_pixels[0].to_le_bytes(), [127, 127, 127, 128]);
+        assert_eq!(
+            icon_pixels[12 * 16 + 9].to_le_bytes(),
+            [BADGE_COLOR[2], BADGE_COLOR[1], BADGE_COLOR[0], 255]
+        );
+        assert_eq!(
+            icon_pixels[12 * 16 + 12].to_le_bytes(),
+            [255, 255, 255, 255]
+        );
+        // (Outside of the rounded corner.)
+        assert_eq!(icon_pixels[8 * 16 + 6], 0);
+    }
+
+    #[test]
+    fn fills_silhouette_by_alpha_threshold() {
+        let mut pixels = [
+            u32::from_le_bytes([10, 20, 30, 255]),
+            u32::from_le_bytes([64, 64, 64, 128]),
+            u32::from_le_bytes([63, 63, 63, 127]),
+            0,
+        ];
+
+        assert!(fill_silhouette(&mut pixels, [0x12, 0x34, 0x56]));
+        assert_eq!(
+            pixels.map(u32::to_le_bytes),
+            [
+                [0x56, 0x34, 0x12, 255],
+                [0x56, 0x34, 0x12, 255],
+                [0, 0, 0, 0],
+                [0, 0, 0, 0],
+            ]
+        );
+
+        // (Icons without alpha channel are drawn fully transparent.)
+        let mut pixels = [u32::from_le_bytes([255, 255, 255, 0]); 4];
+        assert!(!fill_silhouette(&mut pixels, [0, 0, 0]));
+    }
 }
@@ -1,8 +1,12 @@
 // Note: This module was transferred to the `windows-helpers` crate and improved there. When refactoring, that crate should be used.
 
 use windows::Win32::{
-    Foundation::HWND,
-    UI::WindowsAndMessaging::{DispatchMessageW, GetMessageW, TranslateMessage, MSG, WM_QUIT},
+    Foundation::{HWND, WAIT_FAILED},
+    System::Threading::INFINITE,
+    UI::WindowsAndMessaging::{
+        DispatchMessageW, GetMessageW, MsgWaitForMultipleObjects, PeekMessageW, TranslateMessage,
+        MSG, PM_REMOVE, QS_ALLINPUT, WM_QUIT,
+    },
 };
 
 /// A Win32 message loop runner.
@@ -20,6 +24,54 @@ impl Win32MsgLoop {
         }
     }
 
+    pub fn run_resilient(max_retries: u32) -> Result<usize, windows::core::Error> {
+        //! Runs the message loop like `run()`, but reruns it after an error, since `GetMessageW()` may fail only transiently. Returns the last error after `max_retries` reruns.
+
+        let mut retries = 0;
+        loop {
+            match Self::run() {
+                Err(_) if retries < max_retries => retries += 1,
+                result => break result,
+            }
+        }
+    }
+
+    pub fn run_until<F>(mut should_quit: F) -> Result<Option<usize>, windows::core::Error>
+    where
+        F: FnMut() -> bool,
+    {
+        //! Runs the message loop like `run()`, but checks the predicate before waiting and after every dispatched message. Returns `None` as soon as the predicate returns `true`. If `WM_QUIT` arrives first, returns its exit code like `run()`. Thread messages other than `WM_QUIT` are ignored.
+
+        let mut msg = MSG::default();
+        loop {
+            if should_quit() {
+                break Ok(None);
+            }
+
+            while unsafe { PeekMessageW(&mut msg, HWND(0), 0, 0, PM_REMOVE).as_bool() } {
+                if msg.message == WM_QUIT {
+                    return Ok(Some(msg.wParam.0));
+                }
+
+                unsafe {
+                    TranslateMessage(&msg);
+                    DispatchMessageW(&msg);
+                }
+
+                if should_quit() {
+                    return Ok(None);
+                }
+            }
+
+            // Sleep until new input arrives in the queue.
+            if unsafe { MsgWaitForMultipleObjects(None, false, INFINITE, QS_ALLINPUT) }
+                == WAIT_FAILED
+            {
+                break Err(windows::core::Error::from_win32());
+            }
+        }
+    }
+
     pub fn run_till_thread_msg() -> Result<MSG, windows::core::Error> {
         //! Runs the message loop until a thread message is received, sending window messages to the corresponding window procedures in between. In most programs, the only thread message will be `WM_QUIT` (sent via `PostQuitMessage()`); but others are possible via `PostThreadMessageW()` and `PostMessageW()`.
 
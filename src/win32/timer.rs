@@ -0,0 +1,37 @@
+use windows::Win32::{
+    Foundation::HWND,
+    UI::WindowsAndMessaging::{KillTimer, SetTimer},
+};
+
+/// A window timer that's killed when dropped. The window procedure receives `WM_TIMER` with the ID as `WPARAM`.
+///
+/// Since timers are identified by window and ID, replacing a `Timer` with one of the same ID requires dropping the old one first. Otherwise, the drop kills the new timer. `replace()` takes care of that.
+pub struct Timer {
+    hwnd: HWND,
+    id: usize,
+}
+
+impl Timer {
+    pub fn new(hwnd: HWND, id: usize, millis: u32) -> Result<Self, windows::core::Error> {
+        //! IDs 0 and 1 shouldn't be used, since messages with them are also received without having set a timer.
+
+        if unsafe { SetTimer(hwnd, id, millis, None) } == 0 {
+            return Err(windows::core::Error::from_win32());
+        }
+
+        Ok(Self { hwnd, id })
+    }
+
+    pub fn replace(timer: &mut Option<Self>, hwnd: HWND, id: usize, millis: u32) {
+        //! Kills the timer, if any, and sets a new one in its place, which restarts the countdown. If setting the timer fails, there's none afterwards.
+
+        *timer = None;
+        *timer = Self::new(hwnd, id, millis).ok();
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        let _ = unsafe { KillTimer(self.hwnd, self.id) };
+    }
+}
@@ -1,6 +1,8 @@
 // Note: This module was transferred to the `windows-helpers` crate and improved there. When refactoring, that crate should be used.
 
-use std::{mem::size_of, time::Instant};
+#![allow(dead_code)]
+
+use std::{mem::size_of, path::Path, time::Instant};
 use windows::{
     core::HSTRING,
     Win32::{
@@ -8,21 +10,27 @@ use windows::{
         UI::{
             Input::KeyboardAndMouse::GetDoubleClickTime,
             Shell::{
-                Shell_NotifyIconW, NIF_ICON, NIF_MESSAGE, NIF_SHOWTIP, NIF_TIP, NIM_ADD,
-                NIM_DELETE, NIM_MODIFY, NIM_SETVERSION, NINF_KEY, NIN_SELECT, NOTIFYICONDATAW,
-                NOTIFYICON_VERSION_4, NOTIFY_ICON_DATA_FLAGS,
+                Shell_NotifyIconW, NIF_ICON, NIF_INFO, NIF_MESSAGE, NIF_SHOWTIP, NIF_STATE,
+                NIF_TIP, NIIF_INFO, NIIF_RESPECT_QUIET_TIME, NIM_ADD, NIM_DELETE, NIM_MODIFY,
+                NIM_SETVERSION, NINF_KEY, NIN_SELECT, NIS_HIDDEN, NOTIFYICONDATAW,
+                NOTIFYICON_VERSION_4, NOTIFY_ICON_DATA_FLAGS, NOTIFY_ICON_STATE,
             },
-            WindowsAndMessaging::{DestroyIcon, HICON, WM_CONTEXTMENU},
+            WindowsAndMessaging::{HICON, WM_CONTEXTMENU, WM_LBUTTONDBLCLK},
         },
     },
 };
 
+use crate::win32::icon::{destroy_hicon, load_tray_monitor_icon};
+
 const NIN_KEYSELECT: u32 = NIN_SELECT | NINF_KEY;
+const ELLIPSIS: u16 = '\u{2026}' as _;
 
 /// A tray icon to be used with a window. To prevent a low-quality icon, The app needs to be declared in its manifest as DPI-aware in the same way that the operating system is.
 pub struct TrayIcon {
     notify_icon_data: NOTIFYICONDATAW,
     last_activation_time: Instant,
+    double_click_millis: Option<u32>,
+    tooltip_truncation: Truncation,
 }
 
 impl TrayIcon {
@@ -71,6 +79,8 @@ impl TrayIcon {
         Ok(Self {
             notify_icon_data,
             last_activation_time: Instant::now(),
+            double_click_millis: None,
+            tooltip_truncation: Truncation::End,
         })
     }
 
@@ -78,11 +88,14 @@ impl TrayIcon {
     where
         T: Into<HSTRING>,
     {
-        let tooltip: HSTRING = tooltip.into();
-        let len = tooltip.len().min(self.notify_icon_data.szTip.len() - 1);
+        //! A tooltip that's too long is truncated as specified via `set_tooltip_truncation()`.
 
-        self.notify_icon_data.szTip[..len].copy_from_slice(&tooltip.as_wide()[..len]);
-        self.notify_icon_data.szTip[len] = 0;
+        let tooltip: HSTRING = tooltip.into();
+        copy_to_wide_buffer(
+            &mut self.notify_icon_data.szTip,
+            tooltip.as_wide(),
+            self.tooltip_truncation,
+        );
 
         if unsafe { Shell_NotifyIconW(NIM_MODIFY, &self.notify_icon_data).as_bool() } {
             Ok(())
@@ -91,8 +104,48 @@ impl TrayIcon {
         }
     }
 
+    pub fn show_balloon<T, U>(&mut self, title: T, text: U) -> Result<(), windows::core::Error>
+    where
+        T: Into<HSTRING>,
+        U: Into<HSTRING>,
+    {
+        //! Shows a notification at the tray icon, which newer Windows versions show as a toast. It's removed when the tray icon is deleted. Too long texts are cut off.
+
+        let (title, text): (HSTRING, HSTRING) = (title.into(), text.into());
+        copy_to_wide_buffer(
+            &mut self.notify_icon_data.szInfoTitle,
+            title.as_wide(),
+            Truncation::End,
+        );
+        copy_to_wide_buffer(
+            &mut self.notify_icon_data.szInfo,
+            text.as_wide(),
+            Truncation::End,
+        );
+        self.notify_icon_data.dwInfoFlags = NIIF_INFO | NIIF_RESPECT_QUIET_TIME;
+
+        self.notify_icon_data.uFlags |= NIF_INFO;
+        let succeeded = unsafe { Shell_NotifyIconW(NIM_MODIFY, &self.notify_icon_data).as_bool() };
+        // (Later modifications mustn't show the notification again.)
+        self.notify_icon_data.uFlags &= !NIF_INFO;
+
+        if succeeded {
+            Ok(())
+        } else {
+            Err(E_FAIL.into())
+        }
+    }
+
+    pub fn set_tooltip_truncation(&mut self, truncation: Truncation) {
+        //! Applies to subsequent calls to `set_tooltip()`. Defaults to `Truncation::End`.
+
+        self.tooltip_truncation = truncation;
+    }
+
     pub fn set_icon(&mut self, hicon: HICON) -> Result<(), windows::core::Error> {
-        let _ = unsafe { DestroyIcon(self.notify_icon_data.hIcon) };
+        //! Takes ownership of the icon. The previous icon is destroyed.
+
+        destroy_hicon(self.notify_icon_data.hIcon);
         self.notify_icon_data.hIcon = hicon;
 
         if unsafe { Shell_NotifyIconW(NIM_MODIFY, &self.notify_icon_data).as_bool() } {
@@ -102,12 +155,56 @@ impl TrayIcon {
         }
     }
 
+    pub fn set_icon_from_path<T>(
+        &mut self,
+        file_path: T,
+        large: bool,
+        size: Option<u32>,
+    ) -> Result<(), windows::core::Error>
+    where
+        T: AsRef<Path>,
+    {
+        //! Loads the icon like `load_tray_monitor_icon()` and sets it, keeping ownership internal. On a loading error, the previous icon is kept.
+
+        self.set_icon(load_tray_monitor_icon(file_path, large, size)?)
+    }
+
+    pub fn set_hidden(&mut self, hidden: bool) -> Result<(), windows::core::Error> {
+        //! Hides or shows the tray icon without deleting it, so that icon and tooltip are kept and showing it again is instant.
+
+        // (The flag stays set, so that later modifications reapply the state.)
+        self.notify_icon_data.uFlags |= NIF_STATE;
+        self.notify_icon_data.dwStateMask = NIS_HIDDEN;
+        self.notify_icon_data.dwState = if hidden {
+            NIS_HIDDEN
+        } else {
+            NOTIFY_ICON_STATE(0)
+        };
+
+        if unsafe { Shell_NotifyIconW(NIM_MODIFY, &self.notify_icon_data).as_bool() } {
+            Ok(())
+        } else {
+            Err(E_FAIL.into())
+        }
+    }
+
+    pub fn set_double_click_millis(&mut self, millis: Option<u32>) {
+        //! Overrides the system's double-click time used to merge activations. `None` restores querying `GetDoubleClickTime()`.
+
+        self.double_click_millis = millis;
+    }
+
     pub fn translate_window_msg(
         &mut self,
         wparam: WPARAM,
         lparam: LPARAM,
     ) -> Option<TrayIconEvent> {
+        //! With `NOTIFYICON_VERSION_4`, every event carries the anchor coordinates of the icon in `wparam`, also for keyboard events, which is more reliable than the cursor position.
+
         let msg_id = lparam.0 & 0xffff;
+        let wparam_loword = (wparam.0 & 0xffff) as i16;
+        let wparam_hiword = (wparam.0 >> 16 & 0xffff) as i16;
+
         match msg_id as _ {
             NIN_SELECT | NIN_KEYSELECT => {
                 // NIN_SELECT - After every up-event of the primary mouse button.
@@ -115,24 +212,27 @@ impl TrayIcon {
                 //
                 // Since Space and Enter key presses can't be distinguished, and an Enter key press sends two undistinguishable events, the logic of reacting only once on double-click is also applied to the keyboard events.
 
-                if self.last_activation_time.elapsed().as_millis()
-                    > unsafe { GetDoubleClickTime() } as _
-                {
+                let double_click_millis = self
+                    .double_click_millis
+                    .unwrap_or_else(|| unsafe { GetDoubleClickTime() });
+
+                if self.last_activation_time.elapsed().as_millis() > double_click_millis as _ {
                     self.last_activation_time = Instant::now();
-                    Some(TrayIconEvent::Activated)
+                    Some(TrayIconEvent::Activated {
+                        x: wparam_loword,
+                        y: wparam_hiword,
+                    })
                 } else {
                     None
                 }
             }
+            // (Follows the `NIN_SELECT` of the first click.)
+            WM_LBUTTONDBLCLK => Some(TrayIconEvent::DoubleClicked),
             // Context menu request via mouse or keyboard.
-            WM_CONTEXTMENU => {
-                let wparam_loword = (wparam.0 & 0xffff) as i16;
-                let wparam_hiword = (wparam.0 >> 16 & 0xffff) as i16;
-                Some(TrayIconEvent::ContextMenuRequested {
-                    x: wparam_loword,
-                    y: wparam_hiword,
-                })
-            }
+            WM_CONTEXTMENU => Some(TrayIconEvent::ContextMenuRequested {
+                x: wparam_loword,
+                y: wparam_hiword,
+            }),
             _ => None,
         }
     }
@@ -140,16 +240,103 @@ impl TrayIcon {
 
 impl Drop for TrayIcon {
     fn drop(&mut self) {
-        unsafe {
-            Shell_NotifyIconW(NIM_DELETE, &self.notify_icon_data);
-            let _ = DestroyIcon(self.notify_icon_data.hIcon);
-        }
+        // (Also deletes a hidden icon.)
+        unsafe { Shell_NotifyIconW(NIM_DELETE, &self.notify_icon_data) };
+        destroy_hicon(self.notify_icon_data.hIcon);
     }
 }
 
 pub enum TrayIconEvent {
-    /// Tray icon was clicked or double-clicked, or Space or Enter was pressed on a keyboard-focused icon.
-    Activated,
+    /// Tray icon was clicked or double-clicked, or Space or Enter was pressed on a keyboard-focused icon. With x-and-y virtual-screen coordinates like `ContextMenuRequested`.
+    Activated { x: i16, y: i16 },
+    /// Tray icon was double-clicked with the primary mouse button, in addition to `Activated` for the first click.
+    DoubleClicked,
     /// With x-and-y virtual-screen coordinates.
     ContextMenuRequested { x: i16, y: i16 },
 }
+
+/// Which part of a too long text is cut off.
+#[derive(Clone, Copy)]
+pub enum Truncation {
+    /// Cuts off the end.
+    End,
+    /// Keeps the start and the end around an ellipsis.
+    Middle,
+    /// Replaces the start with an ellipsis.
+    Start,
+}
+
+fn copy_to_wide_buffer(buffer: &mut [u16], text: &[u16], truncation: Truncation) {
+    //! Zero-terminates the text in the buffer.
+
+    let text = truncate_wide(text, buffer.len() - 1, truncation);
+    let len = text.len();
+
+    buffer[..len].copy_from_slice(&text);
+    buffer[len] = 0;
+}
+
+fn truncate_wide(text: &[u16], max_len: usize, truncation: Truncation) -> Vec<u16> {
+    //! Never splits surrogate pairs, so the result may be shorter than `max_len`.
+
+    if text.len() <= max_len {
+        return text.to_vec();
+    }
+
+    let (head_len, ellipsis, tail_len) = match truncation {
+        Truncation::End => (max_len, None, 0),
+        Truncation::Middle => {
+            let available = max_len - 1;
+            (available - available / 2, Some(ELLIPSIS), available / 2)
+        }
+        Truncation::Start => (0, Some(ELLIPSIS), max_len - 1),
+    };
+
+    let mut head_end = head_len;
+    if head_end > 0 && is_low_surrogate(text[head_end]) {
+        head_end -= 1;
+    }
+
+    let mut tail_start = text.len() - tail_len;
+    if tail_start < text.len() && is_low_surrogate(text[tail_start]) {
+        tail_start += 1;
+    }
+
+    let mut result = text[..head_end].to_vec();
+    result.extend(ellipsis);
+    result.extend_from_slice(&text[tail_start..]);
+    result
+}
+
+fn is_low_surrogate(unit: u16) -> bool {
+    (0xDC00..=0xDFFF).contains(&unit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{truncate_wide, Truncation};
+
+    fn truncate(text: &str, max_len: usize, truncation: Truncation) -> String {
+        let wide: Vec<u16> = text.encode_utf16().collect();
+        String::from_utf16(&truncate_wide(&wide, max_len, truncation)).unwrap()
+    }
+
+    #[test]
+    fn truncates_without_splitting_surrogate_pairs() {
+        assert_eq!(truncate("Short", 10, Truncation::Middle), "Short");
+        assert_eq!(
+            truncate("Document - Editor", 8, Truncation::End),
+            "Document"
+        );
+        assert_eq!(
+            truncate("Document - Editor", 8, Truncation::Middle),
+            "Docu…tor"
+        );
+        assert_eq!(
+            truncate("Document - Editor", 8, Truncation::Start),
+            "… Editor"
+        );
+        assert_eq!(truncate("ab😀cd", 3, Truncation::End), "ab");
+        assert_eq!(truncate("ab😀cd", 4, Truncation::Start), "…cd");
+    }
+}
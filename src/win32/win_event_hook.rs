@@ -1,10 +1,18 @@
 #![allow(dead_code)]
 
 use nohash_hasher::IntMap;
-use std::{cell::RefCell, marker::PhantomData};
+use std::{cell::RefCell, marker::PhantomData, mem::size_of, process::Child};
 use windows::Win32::{
-    Foundation::{HWND, LPARAM, WPARAM},
-    System::Threading::GetCurrentProcessId,
+    Foundation::{CloseHandle, E_FAIL, FILETIME, HWND, LPARAM, WPARAM},
+    System::{
+        Diagnostics::ToolHelp::{
+            CreateToolhelp32Snapshot, Thread32First, Thread32Next, TH32CS_SNAPTHREAD, THREADENTRY32,
+        },
+        SystemInformation::GetTickCount,
+        Threading::{
+            GetCurrentProcessId, GetThreadTimes, OpenThread, THREAD_QUERY_LIMITED_INFORMATION,
+        },
+    },
     UI::{
         Accessibility::{SetWinEventHook, UnhookWinEvent, HWINEVENTHOOK},
         WindowsAndMessaging::{
@@ -140,8 +148,12 @@ impl WinEventHook {
         thread_id: u32,
         time_millis: u32,
     ) {
-        let hook_data =
-            HOOK_DATA.with_borrow_mut(|data| data.get(&h_win_event_hook.0).map(Clone::clone));
+        // The data is copied out, so that no borrow is held while the window procedure runs, which may drop or create hooks. (`try_borrow()` guards against events delivered while the map is being modified, which are ignored.)
+        let hook_data = HOOK_DATA.with(|data| {
+            data.try_borrow()
+                .ok()
+                .and_then(|data| data.get(&h_win_event_hook.0).copied())
+        });
         let (event_hwnd, window_msg_id) = if let Some(data) = hook_data {
             data
         } else {
@@ -157,7 +169,7 @@ impl WinEventHook {
             time_millis,
         }));
 
-        // Synchronously call window procedure.
+        // Synchronously call window procedure. (May reenter this function, e.g., when the window procedure waits for another thread.)
         unsafe {
             SendMessageW(
                 event_hwnd,
@@ -171,12 +183,16 @@ impl WinEventHook {
 
 impl Drop for WinEventHook {
     fn drop(&mut self) {
+        // Removing the data first makes pending events of these hooks be ignored. Unhooking happens without holding the borrow.
         HOOK_DATA.with_borrow_mut(|hook_data| {
             for h_win_event_hook in self.h_win_event_hooks.iter() {
-                unsafe { UnhookWinEvent(*h_win_event_hook) };
                 hook_data.remove(&h_win_event_hook.0);
             }
         });
+
+        for h_win_event_hook in self.h_win_event_hooks.drain(..) {
+            unsafe { UnhookWinEvent(h_win_event_hook) };
+        }
     }
 }
 
@@ -194,6 +210,79 @@ pub enum ProcessThreadSet {
     ProcessAndThread(u32, u32),
 }
 
+impl ProcessThreadSet {
+    pub fn from_pid(process_id: u32) -> Self {
+        Self::Process(process_id)
+    }
+
+    pub fn from_child(child: &Child) -> Self {
+        Self::Process(child.id())
+    }
+
+    pub fn main_thread_of(process_id: u32) -> Result<u32, windows::core::Error> {
+        //! The ID of the process's earliest created thread that still runs, which is usually the one with the main window, e.g., for `ProcessAndThread`. Fails if no thread's creation time can be queried.
+
+        let h_snapshot = unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPTHREAD, 0)? };
+
+        let mut thread_entry = THREADENTRY32 {
+            dwSize: size_of::<THREADENTRY32>() as _,
+            ..Default::default()
+        };
+        let mut main_thread = None;
+        let mut next_thread_result = unsafe { Thread32First(h_snapshot, &mut thread_entry) };
+        while next_thread_result.is_ok() {
+            if thread_entry.th32OwnerProcessID == process_id {
+                if let Some(creation_time) = thread_creation_time(thread_entry.th32ThreadID) {
+                    if main_thread.map_or(true, |(_, main_creation_time)| {
+                        creation_time < main_creation_time
+                    }) {
+                        main_thread = Some((thread_entry.th32ThreadID, creation_time));
+                    }
+                }
+            }
+
+            next_thread_result = unsafe { Thread32Next(h_snapshot, &mut thread_entry) };
+        }
+
+        let _ = unsafe { CloseHandle(h_snapshot) };
+
+        main_thread.map(|(thread_id, _)| thread_id).ok_or_else(|| {
+            windows::core::Error::new(E_FAIL, "No thread of the process could be queried".into())
+        })
+    }
+}
+
+impl From<&Child> for ProcessThreadSet {
+    fn from(child: &Child) -> Self {
+        Self::from_child(child)
+    }
+}
+
+fn thread_creation_time(thread_id: u32) -> Option<u64> {
+    let h_thread =
+        unsafe { OpenThread(THREAD_QUERY_LIMITED_INFORMATION, false, thread_id) }.ok()?;
+
+    let mut creation_time = FILETIME::default();
+    let mut exit_time = FILETIME::default();
+    let mut kernel_time = FILETIME::default();
+    let mut user_time = FILETIME::default();
+    let result = unsafe {
+        GetThreadTimes(
+            h_thread,
+            &mut creation_time,
+            &mut exit_time,
+            &mut kernel_time,
+            &mut user_time,
+        )
+    };
+
+    let _ = unsafe { CloseHandle(h_thread) };
+
+    result
+        .ok()
+        .map(|()| (creation_time.dwHighDateTime as u64) << 32 | creation_time.dwLowDateTime as u64)
+}
+
 pub struct WinEvent {
     pub event_id: u32,
     pub hwnd: HWND,
@@ -203,5 +292,154 @@ pub struct WinEvent {
     pub time_millis: u32,
 }
 
+impl WinEvent {
+    pub fn lag_millis(&self) -> u32 {
+        //! How long ago the event happened. Shows how far behind the out-of-context hook is running.
+
+        // (The tick count wraps around after 49.7 days, like `time_millis`.)
+        unsafe { GetTickCount() }.wrapping_sub(self.time_millis)
+    }
+}
+
 type PhantomUnsend = PhantomData<std::sync::MutexGuard<'static, ()>>;
 type PhantomUnsync = PhantomData<std::cell::Cell<()>>;
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, pin::Pin, ptr, time::Instant};
+    use windows::Win32::{
+        Foundation::{HWND, LPARAM, LRESULT, WPARAM},
+        System::Threading::GetCurrentProcessId,
+        UI::WindowsAndMessaging::{
+            IsWindowVisible, ShowWindow, EVENT_OBJECT_HIDE, EVENT_OBJECT_SHOW, SW_HIDE, SW_SHOWNA,
+            WM_APP,
+        },
+    };
+
+    use super::{ProcessThreadSet, WinEvent, WinEventHook};
+    use crate::win32::{
+        base_window::{BaseWindow, OnWindowMsg},
+        msg_loop::Win32MsgLoop,
+    };
+
+    const WIN_EVENT_MSG_ID: u32 = WM_APP;
+    const TOGGLE_COUNT: usize = 200;
+
+    thread_local! {
+        static HANDLED_EVENT_COUNT: Cell<usize> = const { Cell::new(0) };
+    }
+
+    struct DummyWindow;
+
+    impl OnWindowMsg for DummyWindow {
+        fn on_window_msg(
+            _this: Pin<&mut Self>,
+            _hwnd: HWND,
+            _msg_id: u32,
+            _wparam: WPARAM,
+            _lparam: LPARAM,
+        ) -> Option<LRESULT> {
+            None
+        }
+    }
+
+    /// Toggles the target window's visibility on every show/hide event of it, which causes the next event.
+    struct TogglingWindow {
+        base_window: Pin<Box<BaseWindow<'static, TogglingWindow>>>,
+        target_window: Pin<Box<BaseWindow<'static, DummyWindow>>>,
+        win_event_hook: Option<WinEventHook>,
+    }
+
+    impl TogglingWindow {
+        fn new() -> Pin<Box<Self>> {
+            let mut instance = Box::new(Self {
+                base_window: BaseWindow::new().expect("window should be created"),
+                target_window: BaseWindow::new().expect("target window should be created"),
+                win_event_hook: None,
+            });
+            instance.rehook();
+
+            BaseWindow::set_msg_callback_with_this_arg(
+                ptr::addr_of_mut!(instance.base_window),
+                Self::on_window_msg,
+                instance,
+            )
+        }
+
+        fn rehook(&mut self) {
+            // Drops the previous hook, possibly while one of its events is being handled.
+            self.win_event_hook = None;
+
+            let mut win_event_hook = unsafe {
+                WinEventHook::new(
+                    ProcessThreadSet::Process(GetCurrentProcessId()),
+                    self.base_window.hwnd(),
+                    WIN_EVENT_MSG_ID,
+                )
+            };
+            win_event_hook
+                .add_event_range(EVENT_OBJECT_SHOW, EVENT_OBJECT_HIDE)
+                .expect("hook should be set");
+
+            self.win_event_hook = Some(win_event_hook);
+        }
+    }
+
+    impl OnWindowMsg for TogglingWindow {
+        fn on_window_msg(
+            mut this: Pin<&mut Self>,
+            _hwnd: HWND,
+            msg_id: u32,
+            _wparam: WPARAM,
+            lparam: LPARAM,
+        ) -> Option<LRESULT> {
+            if msg_id != WIN_EVENT_MSG_ID {
+                return None;
+            }
+
+            let win_event = unsafe { *Box::from_raw(lparam.0 as *mut WinEvent) };
+            let target_hwnd = this.target_window.hwnd();
+            if win_event.hwnd == target_hwnd {
+                let count = HANDLED_EVENT_COUNT.with(|count| {
+                    count.set(count.get() + 1);
+                    count.get()
+                });
+
+                if count < TOGGLE_COUNT {
+                    if count % 10 == 0 {
+                        this.rehook();
+                    }
+
+                    let visible = unsafe { IsWindowVisible(target_hwnd).as_bool() };
+                    unsafe { ShowWindow(target_hwnd, if visible { SW_HIDE } else { SW_SHOWNA }) };
+                }
+            }
+
+            Some(LRESULT(0))
+        }
+    }
+
+    #[test]
+    fn survives_rapid_toggling_while_rehooking() {
+        let window = TogglingWindow::new();
+        unsafe { ShowWindow(window.target_window.hwnd(), SW_SHOWNA) };
+
+        let start = Instant::now();
+        let result = Win32MsgLoop::run_until(|| {
+            HANDLED_EVENT_COUNT.with(Cell::get) >= TOGGLE_COUNT || start.elapsed().as_secs() >= 10
+        });
+
+        assert!(matches!(result, Ok(None)));
+        assert!(HANDLED_EVENT_COUNT.with(Cell::get) >= TOGGLE_COUNT);
+    }
+
+    #[test]
+    fn finds_main_thread_of_current_process() {
+        let process_id = unsafe { GetCurrentProcessId() };
+
+        assert!(matches!(
+            ProcessThreadSet::main_thread_of(process_id),
+            Ok(thread_id) if thread_id != 0
+        ));
+    }
+}
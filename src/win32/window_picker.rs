@@ -0,0 +1,120 @@
+use std::cell::Cell;
+use windows::{
+    core::PCWSTR,
+    Win32::{
+        Foundation::{HINSTANCE, HWND, LPARAM, LRESULT, POINT, WPARAM},
+        System::LibraryLoader::GetModuleHandleW,
+        UI::WindowsAndMessaging::{
+            CallNextHookEx, CopyIcon, GetAncestor, LoadCursorW, SetSystemCursor, SetWindowsHookExW,
+            SystemParametersInfoW, UnhookWindowsHookEx, WindowFromPoint, GA_ROOT, HCURSOR,
+            HC_ACTION, HHOOK, HICON, IDC_CROSS, MSLLHOOKSTRUCT, OCR_HAND, OCR_IBEAM, OCR_NORMAL,
+            SPI_SETCURSORS, SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS, WH_MOUSE_LL, WM_LBUTTONDOWN,
+            WM_LBUTTONUP, WM_RBUTTONDOWN, WM_RBUTTONUP,
+        },
+    },
+};
+
+use crate::win32::msg_loop::Win32MsgLoop;
+
+thread_local! {
+    /// The progress of the pick on this thread, for the hook procedure.
+    static PICK_STATE: Cell<PickState> = const { Cell::new(PickState::Waiting) };
+}
+
+#[derive(Clone, Copy)]
+enum PickState {
+    Waiting,
+    /// The primary button is down; its up-event is still to be swallowed.
+    Pressing(POINT),
+    Picked(POINT),
+    /// The secondary button is down; its up-event is still to be swallowed.
+    Canceling,
+    Canceled,
+}
+
+pub fn pick_window() -> Result<Option<HWND>, windows::core::Error> {
+    //! Lets the user click any window under a crosshair cursor and returns the top-level window that was clicked. `None`, if the user right-clicked to cancel or `WM_QUIT` arrived. Both clicks are swallowed, so that they don't reach the window. Runs a message loop, which the low-level mouse hook needs, until then.
+
+    PICK_STATE.with(|state| state.set(PickState::Waiting));
+
+    let hmodule = unsafe { GetModuleHandleW(PCWSTR::null())? };
+    let hhook =
+        unsafe { SetWindowsHookExW(WH_MOUSE_LL, Some(mouse_hook_proc), HINSTANCE(hmodule.0), 0)? };
+    // (Restores the cursors when dropped, also on panic.)
+    let crosshair_cursor = CrosshairCursor::new();
+
+    let result = Win32MsgLoop::run_until(|| {
+        matches!(
+            PICK_STATE.with(Cell::get),
+            PickState::Picked(_) | PickState::Canceled
+        )
+    });
+
+    drop(crosshair_cursor);
+    let _ = unsafe { UnhookWindowsHookEx(hhook) };
+
+    match (result?, PICK_STATE.with(Cell::get)) {
+        (None, PickState::Picked(point)) => {
+            let hwnd = unsafe { GetAncestor(WindowFromPoint(point), GA_ROOT) };
+            Ok((hwnd.0 != 0).then_some(hwnd))
+        }
+        _ => Ok(None),
+    }
+}
+
+unsafe extern "system" fn mouse_hook_proc(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    if code == HC_ACTION as i32 {
+        let hook_struct = unsafe { &*(lparam.0 as *const MSLLHOOKSTRUCT) };
+
+        let new_state = match (wparam.0 as u32, PICK_STATE.with(Cell::get)) {
+            (WM_LBUTTONDOWN, PickState::Waiting) => Some(PickState::Pressing(hook_struct.pt)),
+            (WM_LBUTTONUP, PickState::Pressing(point)) => Some(PickState::Picked(point)),
+            (WM_RBUTTONDOWN, PickState::Waiting) => Some(PickState::Canceling),
+            (WM_RBUTTONUP, PickState::Canceling) => Some(PickState::Canceled),
+            _ => None,
+        };
+
+        if let Some(new_state) = new_state {
+            PICK_STATE.with(|state| state.set(new_state));
+
+            // Swallow the event.
+            return LRESULT(1);
+        }
+    }
+
+    unsafe { CallNextHookEx(HHOOK(0), code, wparam, lparam) }
+}
+
+/// Replaces the system's arrow, I-beam and hand cursors with the crosshair cursor. Restores all system cursors when dropped.
+struct CrosshairCursor;
+
+impl CrosshairCursor {
+    fn new() -> Self {
+        //! Best effort.
+
+        if let Ok(cross_hcursor) = unsafe { LoadCursorW(HINSTANCE(0), IDC_CROSS) } {
+            for cursor_id in [OCR_NORMAL, OCR_IBEAM, OCR_HAND] {
+                // (`SetSystemCursor()` takes ownership of a copy, since the shared cursor must not be destroyed.)
+                if let Ok(hicon) = unsafe { CopyIcon(HICON(cross_hcursor.0)) } {
+                    let _ = unsafe { SetSystemCursor(HCURSOR(hicon.0), cursor_id) };
+                }
+            }
+        }
+
+        Self
+    }
+}
+
+impl Drop for CrosshairCursor {
+    fn drop(&mut self) {
+        // Reload the system cursors.
+        let _ = unsafe {
+            SystemParametersInfoW(
+                SPI_SETCURSORS,
+                0,
+                None,
+                SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS(0),
+            )
+        };
+    }
+}